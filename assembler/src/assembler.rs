@@ -1,7 +1,12 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 
-// TODO: Add line and column number to error message
+use vm::Isa;
+
+use crate::encode::{self, Context, Operation};
+use crate::macros::{self, Line};
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum AssemblerError {
     InvalidBinary(String),
@@ -11,6 +16,22 @@ pub enum AssemblerError {
     UnknownPseudoOp(String),
     OrigUsage(String),
     EndUsage(String),
+    IsaUsage(String),
+    FillUsage(String),
+    BlkwUsage(String),
+    StringzUsage(String),
+    UnknownOpcode(String),
+    WrongIsa(String),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    InvalidRegister(String),
+    InvalidOperands(String),
+    OutOfRange(String),
+    MacroUsage(String),
+    MacroArguments(String),
+    MacroRecursion(String),
+    EndOfMemory(String),
 }
 
 impl fmt::Display for AssemblerError {
@@ -20,123 +41,1041 @@ impl fmt::Display for AssemblerError {
             AssemblerError::InvalidDecimal(s) => write!(f, "Invalid decimal number: {}", s),
             AssemblerError::InvalidHex(s) => write!(f, "Invalid hex number: {}", s),
             AssemblerError::InvalidNumber(s) => write!(f, "Invalid number: {}", s),
-            AssemblerError::UnknownPseudoOp(s) => write!(f, "Unkown Pseudo-op: {}", s),
+            AssemblerError::UnknownPseudoOp(s) => write!(f, "Unknown Pseudo-op: {}", s),
             AssemblerError::OrigUsage(s) => write!(f, "{}", s),
             AssemblerError::EndUsage(s) => write!(f, "{}", s),
+            AssemblerError::IsaUsage(s) => write!(f, "{}", s),
+            AssemblerError::FillUsage(s) => write!(f, "{}", s),
+            AssemblerError::BlkwUsage(s) => write!(f, "{}", s),
+            AssemblerError::StringzUsage(s) => write!(f, "{}", s),
+            AssemblerError::UnknownOpcode(s) => write!(f, "Unknown opcode: {}", s),
+            AssemblerError::WrongIsa(s) => {
+                write!(f, "{} is not an instruction of the selected .ISA", s)
+            }
+            AssemblerError::InvalidLabel(s) => write!(f, "Invalid label: {}", s),
+            AssemblerError::DuplicateLabel(s) => write!(f, "Label defined twice: {}", s),
+            AssemblerError::UndefinedLabel(s) => write!(f, "Undefined label: {}", s),
+            AssemblerError::InvalidRegister(s) => write!(f, "Invalid register: {}", s),
+            AssemblerError::InvalidOperands(s) => write!(f, "{}", s),
+            AssemblerError::OutOfRange(s) => write!(f, "Out of range: {}", s),
+            AssemblerError::MacroUsage(s) => write!(f, "{}", s),
+            AssemblerError::MacroArguments(s) => write!(f, "{}", s),
+            AssemblerError::MacroRecursion(s) => write!(f, "{}", s),
+            AssemblerError::EndOfMemory(s) => write!(f, "No room after xFFFF for {}", s),
         }
     }
 }
 
 impl Error for AssemblerError {}
 
-pub fn assemble(program: String) -> Result<Vec<u16>, AssemblerError> {
-    let output: Vec<u16> = Vec::new();
-    let mut first_line = true;
-    let mut orig_found = false;
-    let mut end_found = false;
-    let mut _memory_offset = 0;
+impl AssemblerError {
+    /// A code that names the kind of error in diagnostics. Codes are never
+    /// renumbered or reused, so they can be looked up in course material.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AssemblerError::InvalidBinary(_) => "E001",
+            AssemblerError::InvalidDecimal(_) => "E002",
+            AssemblerError::InvalidHex(_) => "E003",
+            AssemblerError::InvalidNumber(_) => "E004",
+            AssemblerError::UnknownPseudoOp(_) => "E005",
+            AssemblerError::OrigUsage(_) => "E006",
+            AssemblerError::EndUsage(_) => "E007",
+            AssemblerError::IsaUsage(_) => "E008",
+            AssemblerError::FillUsage(_) => "E009",
+            AssemblerError::BlkwUsage(_) => "E010",
+            AssemblerError::StringzUsage(_) => "E011",
+            AssemblerError::UnknownOpcode(_) => "E012",
+            AssemblerError::WrongIsa(_) => "E013",
+            AssemblerError::InvalidLabel(_) => "E014",
+            AssemblerError::DuplicateLabel(_) => "E015",
+            AssemblerError::UndefinedLabel(_) => "E016",
+            AssemblerError::InvalidRegister(_) => "E017",
+            AssemblerError::InvalidOperands(_) => "E018",
+            AssemblerError::OutOfRange(_) => "E019",
+            AssemblerError::MacroUsage(_) => "E020",
+            AssemblerError::MacroArguments(_) => "E021",
+            AssemblerError::MacroRecursion(_) => "E022",
+            AssemblerError::EndOfMemory(_) => "E023",
+        }
+    }
+}
+
+/// An error and the token of the source it is about, before it is turned
+/// into a `Diagnostic`
+pub(crate) type Located<'a> = (AssemblerError, &'a str);
 
-    for line in program.lines() {
-        // Remove whitespace
-        let line = line.trim();
+/// Attaches `token` to an error, for `map_err`
+pub(crate) fn at<'a>(token: &'a str) -> impl FnOnce(AssemblerError) -> Located<'a> {
+    move |error| (error, token)
+}
 
-        // Ignore empty lines
-        if line.is_empty() {
-            continue;
+/// A place in the source, by 1-based line and column, counting characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    /// Characters to underline, at least one is shown
+    pub length: usize,
+}
+
+impl Span {
+    /// The span of `token`, which must be a slice of `source`, with the text
+    /// of its line
+    fn of<'a>(source: &'a str, token: &str) -> (Self, &'a str) {
+        let offset = (token.as_ptr() as usize)
+            .checked_sub(source.as_ptr() as usize)
+            .filter(|&offset| offset <= source.len())
+            .expect("token is not part of the source");
+        let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |index| offset + index);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let span = Span {
+            line: source[..line_start].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            length: token.chars().count(),
+        };
+        (span, text)
+    }
+}
+
+/// More on where an error or warning comes from, shown below it: for one in
+/// the expansion of a macro, the definition and the invocations
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Note {
+    pub message: String,
+    pub span: Span,
+    /// The line the note is about
+    pub source_line: String,
+}
+
+/// The span of `token` with the text of its line, like `Span::of`, and notes.
+/// `token` can also be a slice of a line a macro expanded to, which is
+/// reported where it came from in the source.
+fn locate(source: &str, lines: &[Line], token: &str) -> (Span, String, Vec<Note>) {
+    let (token, notes) = macros::origin(lines, token);
+    let (span, text) = Span::of(source, token);
+    let notes = notes
+        .into_iter()
+        .map(|(message, token)| {
+            let (span, text) = Span::of(source, token);
+            Note {
+                message,
+                span,
+                source_line: text.to_string(),
+            }
+        })
+        .collect();
+    (span, text.to_string(), notes)
+}
+
+/// Something that assembles but is likely a mistake
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssemblerWarning {
+    /// A `.ORIG` address without `#`, `x` or `b` that reads differently as hex
+    ImplicitDecimal(String),
+    /// `.ORIG` below x3000, over the trap vector table and the operating
+    /// system
+    SystemSpace(String),
+}
+
+impl fmt::Display for AssemblerWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Only four digits can also be meant as a hex address
+            AssemblerWarning::ImplicitDecimal(s) if s.len() <= 4 => {
+                write!(f, "{s} is read as decimal, write #{s} or x{s} to be clear")
+            }
+            AssemblerWarning::ImplicitDecimal(s) => {
+                write!(f, "{s} is read as decimal, write #{s} to be clear")
+            }
+            AssemblerWarning::SystemSpace(s) => write!(
+                f,
+                ".ORIG {s} is below x3000, where the operating system and trap vectors are"
+            ),
         }
+    }
+}
 
-        // Ignore comments
-        if line.starts_with(";") {
-            continue;
+impl AssemblerWarning {
+    /// Like `AssemblerError::code`, with codes of its own
+    pub fn code(&self) -> &'static str {
+        match self {
+            AssemblerWarning::ImplicitDecimal(_) => "W001",
+            AssemblerWarning::SystemSpace(_) => "W002",
+        }
+    }
+}
+
+/// An error with where it is in the source
+///
+/// ```
+/// let errors = assembler::assemble_program(".ORIG x3000\nBR NOWHERE\n.END\n")
+///     .unwrap_err();
+/// assert_eq!(
+///     "error[E016]: Undefined label: NOWHERE
+///  --> loop.asm:2:4
+///   |
+/// 2 | BR NOWHERE
+///   |    ^^^^^^^
+/// ",
+///     errors[0].display("loop.asm").to_string()
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub error: AssemblerError,
+    pub span: Span,
+    /// The line the error is on
+    pub source_line: String,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    fn new(source: &str, lines: &[Line], (error, token): Located) -> Self {
+        let (span, source_line, notes) = locate(source, lines, token);
+        Self {
+            error,
+            span,
+            source_line,
+            notes,
+        }
+    }
+
+    /// Formats the diagnostic in the style of rustc, with the line and the
+    /// error underlined, for a source read from `file`
+    pub fn display<'a>(&'a self, file: &'a str) -> impl fmt::Display + 'a {
+        DisplaySnippet {
+            severity: "error",
+            code: Some(self.error.code()),
+            message: &self.error,
+            span: self.span,
+            source_line: &self.source_line,
+            notes: &self.notes,
+            file,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: error[{}]: {}",
+            self.span.line,
+            self.span.column,
+            self.error.code(),
+            self.error
+        )
+    }
+}
+
+impl Error for Diagnostic {}
+
+/// A warning with where it is in the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub warning: AssemblerWarning,
+    pub span: Span,
+    /// The line the warning is on
+    pub source_line: String,
+    pub notes: Vec<Note>,
+}
+
+impl Warning {
+    fn new(source: &str, lines: &[Line], (warning, token): (AssemblerWarning, &str)) -> Self {
+        let (span, source_line, notes) = locate(source, lines, token);
+        Self {
+            warning,
+            span,
+            source_line,
+            notes,
+        }
+    }
+
+    /// Formats the warning like `Diagnostic::display`
+    pub fn display<'a>(&'a self, file: &'a str) -> impl fmt::Display + 'a {
+        DisplaySnippet {
+            severity: "warning",
+            code: Some(self.warning.code()),
+            message: &self.warning,
+            span: self.span,
+            source_line: &self.source_line,
+            notes: &self.notes,
+            file,
         }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: warning[{}]: {}",
+            self.span.line,
+            self.span.column,
+            self.warning.code(),
+            self.warning
+        )
+    }
+}
 
-        if end_found {
-            return Err(AssemblerError::EndUsage(
-                "{line.to_string()} after .END".to_string(),
+struct DisplaySnippet<'a> {
+    severity: &'static str,
+    code: Option<&'static str>,
+    message: &'a dyn fmt::Display,
+    span: Span,
+    source_line: &'a str,
+    /// Shown after it, as snippets of their own
+    notes: &'a [Note],
+    file: &'a str,
+}
+
+impl fmt::Display for DisplaySnippet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let DisplaySnippet {
+            severity,
+            code,
+            message,
+            span,
+            source_line,
+            notes,
+            file,
+        } = self;
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        match code {
+            Some(code) => writeln!(f, "{severity}[{code}]: {message}")?,
+            None => writeln!(f, "{severity}: {message}")?,
+        }
+        writeln!(f, "{gutter}--> {file}:{}:{}", span.line, span.column)?;
+        writeln!(f, "{gutter} |")?;
+
+        // Tabs are shown as four spaces, so the underline has to count them
+        // that way too
+        let width = |text: &str| text.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
+        let before: String = source_line.chars().take(span.column - 1).collect();
+        let underlined: String = source_line
+            .chars()
+            .skip(span.column - 1)
+            .take(span.length)
+            .collect();
+        writeln!(f, "{number} | {}", source_line.replace('\t', "    "))?;
+        writeln!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(width(&before)),
+            "^".repeat(width(&underlined).max(1))
+        )?;
+
+        for note in notes.iter() {
+            let snippet = DisplaySnippet {
+                severity: "note",
+                code: None,
+                message: &note.message,
+                span: note.span,
+                source_line: &note.source_line,
+                notes: &[],
+                file,
+            };
+            write!(f, "{snippet}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything one run of the assembler found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// The program, if there were no errors
+    pub program: Option<Program>,
+    /// Errors in the order they are in the source
+    pub errors: Vec<Diagnostic>,
+    /// Warnings in the order they are in the source
+    pub warnings: Vec<Warning>,
+}
+
+impl Report {
+    /// A last line for the output, counting the errors and warnings
+    ///
+    /// ```
+    /// let report = assembler::assemble_report(".ORIG x2000\nADD R0, R0, 10\nBR X\n");
+    /// assert_eq!(
+    ///     "error: could not assemble add.asm due to 2 errors; 1 warning emitted",
+    ///     report.summary("add.asm").to_string()
+    /// );
+    /// ```
+    pub fn summary<'a>(&'a self, file: &'a str) -> impl fmt::Display + 'a {
+        DisplaySummary { report: self, file }
+    }
+}
+
+struct DisplaySummary<'a> {
+    report: &'a Report,
+    file: &'a str,
+}
+
+impl fmt::Display for DisplaySummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        let errors = self.report.errors.len();
+        let warnings = self.report.warnings.len();
+        let warnings = match warnings {
+            0 => String::new(),
+            n => format!("{n} warning{} emitted", plural(n)),
+        };
+        match errors {
+            0 if warnings.is_empty() => write!(f, "assembled {}", self.file),
+            0 => write!(f, "warning: {warnings}"),
+            n => {
+                write!(
+                    f,
+                    "error: could not assemble {} due to {n} error{}",
+                    self.file,
+                    plural(n)
+                )?;
+                if !warnings.is_empty() {
+                    write!(f, "; {warnings}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Labels and the addresses they stand for, in the order they were defined
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    labels: Vec<(String, u16)>,
+    addresses: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    /// Labels and their addresses, in the order they were defined
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.labels
+            .iter()
+            .map(|(label, address)| (label.as_str(), *address))
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Adds a label, unless it is already defined
+    fn insert(&mut self, label: &str, address: u16) -> Result<(), AssemblerError> {
+        if self.addresses.contains_key(label) {
+            return Err(AssemblerError::DuplicateLabel(label.to_string()));
+        }
+        self.addresses.insert(label.to_string(), address);
+        self.labels.push((label.to_string(), address));
+        Ok(())
+    }
+}
+
+/// An assembled program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    /// Address of the first word, from `.ORIG`
+    pub origin: u16,
+    pub isa: Isa,
+    /// The words to load at `origin`, one word apart
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+    /// The lines of source with code on them, in order
+    pub lines: Vec<SourceLine>,
+}
+
+/// Where the words of one line of source went. The words of each line follow
+/// those of the line before in `Program::words`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLine {
+    /// 1-based line number
+    pub number: usize,
+    /// Address of the line's first word, or of its label, if it has either
+    pub address: Option<u16>,
+    /// Number of words the line assembled to
+    pub words: usize,
+}
+
+/// One line of source, split into its parts
+struct Statement<'a> {
+    label: Option<&'a str>,
+    /// The instruction mnemonic or pseudo-op
+    operation: Option<&'a str>,
+    operands: Vec<&'a str>,
+}
+
+impl<'a> Statement<'a> {
+    /// Splits a line without its comment into an optional label, an
+    /// operation and operands separated by whitespace or commas
+    fn parse(code: &'a str) -> Self {
+        let mut tokens = tokens(code).into_iter();
+        let first = tokens.next();
+        let (label, operation) = match first {
+            Some(first) if first.starts_with('.') || Operation::parse(first).is_some() => {
+                (None, Some(first))
+            }
+            label => (label, tokens.next()),
+        };
+        Self {
+            label,
+            operation,
+            operands: tokens.collect(),
+        }
+    }
+}
+
+/// Splits code on whitespace and commas, keeping a quoted string together as
+/// one token with its quotes, even if it has spaces, commas or `\"` in it
+pub(crate) fn tokens(code: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in code.char_indices() {
+        if quoted {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {}
+            }
+        } else if c.is_whitespace() || c == ',' {
+            if let Some(start) = start.take() {
+                tokens.push(&code[start..index]);
+            }
+        } else {
+            start.get_or_insert(index);
+            quoted = c == '"';
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&code[start..]);
+    }
+    tokens
+}
+
+/// The line up to its comment. A `;` inside a quoted string doesn't start
+/// one.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// The characters of a `.STRINGZ` operand followed by the terminating zero,
+/// one word each
+fn stringz(operand: &str) -> Result<Vec<u16>, AssemblerError> {
+    let contents = operand
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|_| operand.len() >= 2)
+        .ok_or_else(|| AssemblerError::StringzUsage(format!("{operand} is not a quoted string")))?;
+
+    let mut words = Vec::with_capacity(contents.len() + 1);
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some(other) => {
+                    return Err(AssemblerError::StringzUsage(format!(
+                        "Unknown escape sequence \\{other} in {operand}"
+                    )))
+                }
+                None => {
+                    return Err(AssemblerError::StringzUsage(format!(
+                        "{operand} is not a quoted string"
+                    )))
+                }
+            },
+            '"' => {
+                return Err(AssemblerError::StringzUsage(format!(
+                    "Quote in {operand} must be escaped as \\\""
+                )))
+            }
+            c if c.is_ascii() => c,
+            c => {
+                return Err(AssemblerError::StringzUsage(format!(
+                    "{c} in {operand} is not an ASCII character"
+                )))
+            }
+        };
+        words.push(c as u16);
+    }
+    words.push(0);
+    Ok(words)
+}
+
+/// An instruction found by the first pass, to encode in the second
+struct Instruction<'a> {
+    address: u16,
+    operation: Operation,
+    mnemonic: &'a str,
+    operands: Vec<&'a str>,
+}
+
+/// What the first pass found at a location, in order
+enum Item<'a> {
+    Instruction(Instruction<'a>),
+    /// `.FILL` or `.BLKW` with a value: `count` copies of a number or of a
+    /// label's address, known in the second pass
+    Fill {
+        value: &'a str,
+        count: u16,
+    },
+    /// Words known from the first pass, from `.STRINGZ` or `.BLKW` without a
+    /// value
+    Words(Vec<u16>),
+}
+
+pub fn assemble(program: String) -> Result<Vec<u16>, Vec<Diagnostic>> {
+    assemble_program(&program).map(|program| program.words)
+}
+
+/// Assembles a program, or returns every error in it
+pub fn assemble_program(program: &str) -> Result<Program, Vec<Diagnostic>> {
+    let report = assemble_report(program);
+    report.program.ok_or(report.errors)
+}
+
+/// Assembles in two passes, after replacing every macro invocation by the
+/// lines of its `.MACRO` definition. The first pass finds the address of
+/// every line from `.ORIG` and builds the symbol table. The second encodes
+/// the instructions, now that every label has an address.
+///
+/// An error skips the rest of its line and assembling goes on with the next
+/// one, so that one run finds every error it can.
+pub fn assemble_report(program: &str) -> Report {
+    let expanded = macros::expand(program);
+    let mut pass = FirstPass::new(&program[..0]);
+    for line in &expanded {
+        let code = line.code.text();
+        pass.end_of_code = &line.source[line.source.len()..];
+
+        if pass.end_found {
+            // Everything after .END is ignored, so one error covers it all
+            let source = line.source;
+            pass.errors.push((
+                AssemblerError::EndUsage(format!("{source} after .END")),
+                source,
             ));
+            break;
+        }
+
+        if let Some((error, range)) = &line.error {
+            pass.errors.push((error.clone(), &code[range.clone()]));
+            continue;
+        }
+        // Definitions only take effect where their macros are invoked
+        if line.definition {
+            continue;
         }
 
-        // Get code before comment if there is a comment at the end of the line
-        let code = line.split(';').next().unwrap_or(line).trim();
+        let (location, word_count, labels) = (pass.location, pass.word_count, pass.symbols.len());
+        if let Err(error) = pass.line(code) {
+            pass.errors.push(error);
+        }
+        let words = pass.word_count - word_count;
+        let address = (words > 0 || pass.symbols.len() > labels).then_some(location);
+        match pass.lines.last_mut() {
+            // The lines a macro invocation expanded to
+            Some(last) if last.number == line.number => {
+                last.address = last.address.or(address);
+                last.words += words;
+            }
+            _ => pass.lines.push(SourceLine {
+                number: line.number,
+                address,
+                words,
+            }),
+        }
+    }
 
-        let parts: Vec<_> = code.split_whitespace().collect();
-        let line_parts: Vec<String> = parts.iter().map(|part| part.replace(",", "")).collect();
-        let first_part = line_parts.first().expect("Should be a symbol").as_str();
+    if !pass.end_found {
+        pass.errors.push((
+            AssemblerError::EndUsage("Missing .END".to_string()),
+            pass.end_of_code,
+        ));
+    }
+
+    let FirstPass {
+        origin,
+        isa,
+        symbols,
+        items,
+        lines,
+        mut errors,
+        warnings,
+        ..
+    } = pass;
 
-        if first_line {
-            if first_part == ".ORIG" {
-                first_line = false;
-            } else {
-                return Err(AssemblerError::OrigUsage(
-                    "The first line must be .ORIG".to_string(),
+    let mut words = Vec::with_capacity(items.len());
+    for item in &items {
+        let encoded = match item {
+            Item::Instruction(instruction) => {
+                let context = Context {
+                    address: instruction.address,
+                    isa,
+                    symbols: &symbols,
+                };
+                encode::encode(
+                    instruction.operation,
+                    instruction.mnemonic,
+                    &instruction.operands,
+                    &context,
+                )
+                .map(|word| words.push(word))
+            }
+            Item::Fill { value, count } => encode::word(value, &symbols)
+                .map(|word| words.extend(std::iter::repeat_n(word, *count as usize)))
+                .map_err(at(value)),
+            Item::Words(data) => {
+                words.extend(data);
+                Ok(())
+            }
+        };
+        if let Err(error) = encoded {
+            errors.push(error);
+        }
+    }
+
+    let mut errors: Vec<_> = errors
+        .into_iter()
+        .map(|error| Diagnostic::new(program, &expanded, error))
+        .collect();
+    errors.sort_by_key(|error| (error.span.line, error.span.column));
+    let warnings = warnings
+        .into_iter()
+        .map(|warning| Warning::new(program, &expanded, warning))
+        .collect();
+    let program = errors.is_empty().then_some(Program {
+        origin,
+        isa,
+        words,
+        symbols,
+        lines,
+    });
+    Report {
+        program,
+        errors,
+        warnings,
+    }
+}
+
+/// The state of the first pass as it goes through the lines
+struct FirstPass<'a> {
+    first_line: bool,
+    orig_found: bool,
+    end_found: bool,
+    origin: u16,
+    location: u16,
+    /// Set once the program reaches xFFFF, where the location wraps to x0000
+    full: bool,
+    isa_found: bool,
+    isa: Isa,
+    symbols: SymbolTable,
+    items: Vec<Item<'a>>,
+    /// Words the items will assemble to
+    word_count: usize,
+    lines: Vec<SourceLine>,
+    /// The end of the last line of code, where a missing .END is reported
+    end_of_code: &'a str,
+    errors: Vec<Located<'a>>,
+    warnings: Vec<(AssemblerWarning, &'a str)>,
+}
+
+impl<'a> FirstPass<'a> {
+    fn new(start: &'a str) -> Self {
+        Self {
+            first_line: true,
+            orig_found: false,
+            end_found: false,
+            origin: 0,
+            location: 0,
+            full: false,
+            isa_found: false,
+            isa: Isa::Lc3,
+            symbols: SymbolTable::new(),
+            items: Vec::new(),
+            word_count: 0,
+            lines: Vec::new(),
+            end_of_code: start,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, item: Item<'a>) {
+        self.word_count += match &item {
+            Item::Instruction(_) => 1,
+            Item::Fill { count, .. } => *count as usize,
+            Item::Words(words) => words.len(),
+        };
+        self.items.push(item);
+    }
+
+    /// Words that still fit between the location and xFFFF
+    fn available(&self) -> u32 {
+        if self.full {
+            return 0;
+        }
+        (0x10000 - self.location as u32) / self.isa.word_size() as u32
+    }
+
+    /// Fails for `token` if there is no room left for it before xFFFF
+    fn check_room(&self, token: &'a str) -> Result<(), Located<'a>> {
+        if self.full {
+            return Err((AssemblerError::EndOfMemory(token.to_string()), token));
+        }
+        Ok(())
+    }
+
+    /// Moves the location past `words` words, which have to fit before xFFFF
+    fn advance(&mut self, words: u32) {
+        let end = self.location as u32 + words * self.isa.word_size() as u32;
+        self.full = end == 0x10000;
+        self.location = end as u16;
+    }
+
+    fn line(&mut self, code: &'a str) -> Result<(), Located<'a>> {
+        let statement = Statement::parse(code);
+        let operation = statement.operation.unwrap_or(code);
+        let pseudo_op = operation.to_ascii_uppercase();
+
+        if self.first_line {
+            self.first_line = false;
+            if statement.label.is_some() || pseudo_op != ".ORIG" {
+                // Carry on as if it had come after .ORIG, to find more errors
+                self.errors.push((
+                    AssemblerError::OrigUsage("The first line must be .ORIG".to_string()),
+                    code,
                 ));
             }
         }
 
+        // A bad label doesn't stop the rest of the line from assembling
+        if let Some(label) = statement.label {
+            if !encode::is_label(label) {
+                self.errors
+                    .push((AssemblerError::InvalidLabel(label.to_string()), label));
+            } else if let Err(error) = self.symbols.insert(label, self.location) {
+                self.errors.push((error, label));
+            }
+        }
+
         // Pseudo-ops
-        if first_part.starts_with(".") {
-            match first_part {
-                ".ORIG" => {
-                    if orig_found {
-                        return Err(AssemblerError::OrigUsage(
-                            "Can only have one .ORIG".to_string(),
+        if operation.starts_with(".") {
+            return self.pseudo_op(&pseudo_op, operation, &statement.operands);
+        }
+
+        // Opcodes
+        if let Some(mnemonic) = statement.operation {
+            self.check_room(mnemonic)?;
+            let address = self.location;
+            // Leave room for the instruction even if it is wrong, so the
+            // addresses after it stay right
+            self.advance(1);
+            let operation = Operation::parse(mnemonic).ok_or_else(|| {
+                (
+                    AssemblerError::UnknownOpcode(mnemonic.to_string()),
+                    mnemonic,
+                )
+            })?;
+            self.push(Item::Instruction(Instruction {
+                address,
+                operation,
+                mnemonic,
+                operands: statement.operands,
+            }));
+        }
+        Ok(())
+    }
+
+    fn pseudo_op(
+        &mut self,
+        pseudo_op: &str,
+        operation: &'a str,
+        operands: &[&'a str],
+    ) -> Result<(), Located<'a>> {
+        let usage = |error: fn(String) -> AssemblerError, message: &str| {
+            Err((error(message.to_string()), operation))
+        };
+        match pseudo_op {
+            ".ORIG" => {
+                if self.orig_found {
+                    return usage(AssemblerError::OrigUsage, "Can only have one .ORIG");
+                } else {
+                    self.orig_found = true;
+                    if operands.len() != 1 {
+                        return usage(AssemblerError::OrigUsage, "Usage: .ORIG <numeric>");
+                    }
+                    let address = operands[0];
+                    // Addresses are nearly always hex, so a bare `.ORIG 3000`
+                    // was probably meant as x3000
+                    let bare_decimal = address.starts_with(|c: char| c.is_ascii_digit());
+                    if bare_decimal && matches!(encode::parse_number(address), Some(Ok(10..))) {
+                        self.warnings.push((
+                            AssemblerWarning::ImplicitDecimal(address.to_string()),
+                            address,
                         ));
-                    } else {
-                        orig_found = true;
-                        if line_parts.len() != 2 {
-                            return Err(AssemblerError::OrigUsage(
-                                "Usage: .ORIG <numeric> Given: {first_line}".to_string(),
-                            ));
-                        }
-                        //memory_offset =
-                        //    encode_numeric(line_parts.get(1).expect("Missing numeric"))?;
                     }
+                    self.origin = encode::number(address)
+                        .map_err(at(address))?
+                        .try_into()
+                        .map_err(|_| {
+                            (
+                                AssemblerError::OrigUsage(format!("{address} is not an address")),
+                                address,
+                            )
+                        })?;
+                    self.location = self.origin;
+                    if self.origin < 0x3000 {
+                        self.warnings
+                            .push((AssemblerWarning::SystemSpace(address.to_string()), address));
+                    }
+                }
+            }
+            ".ISA" => {
+                if self.isa_found {
+                    return usage(AssemblerError::IsaUsage, "Can only have one .ISA");
                 }
-                ".FILL" => {
-                    todo!()
+                self.isa_found = true;
+                if operands.len() != 1 {
+                    return usage(AssemblerError::IsaUsage, "Usage: .ISA <LC3|LC3B>");
                 }
-                ".BLKW" => {
-                    todo!()
+                if !self.items.is_empty() {
+                    return usage(
+                        AssemblerError::IsaUsage,
+                        ".ISA must come before the first instruction",
+                    );
+                }
+                let name = operands[0];
+                let unknown =
+                    |err: vm::UnknownIsa| (AssemblerError::IsaUsage(err.to_string()), name);
+                self.isa = name.parse().map_err(unknown)?;
+                // Only reachable when another crate turns on vm's LC-3b
+                if cfg!(not(feature = "lc3b")) && self.isa != Isa::Lc3 {
+                    return Err(unknown(vm::UnknownIsa(name.to_string())));
+                }
+                if !self.origin.is_multiple_of(self.isa.word_size()) {
+                    return Err((
+                        AssemblerError::IsaUsage(format!(
+                            ".ORIG x{:04X} is not word aligned",
+                            self.origin
+                        )),
+                        name,
+                    ));
+                }
+            }
+            ".FILL" => {
+                if operands.len() != 1 {
+                    return usage(AssemblerError::FillUsage, "Usage: .FILL <numeric|label>");
+                }
+                self.check_room(operation)?;
+                let value = operands[0];
+                encode::check_word(value).map_err(at(value))?;
+                self.push(Item::Fill { value, count: 1 });
+                self.advance(1);
+            }
+            ".BLKW" => {
+                if !(1..=2).contains(&operands.len()) {
+                    return usage(
+                        AssemblerError::BlkwUsage,
+                        "Usage: .BLKW <count> [numeric|label]",
+                    );
                 }
-                ".STRINGZ" => {
-                    todo!()
+                self.check_room(operation)?;
+                let count = operands[0];
+                let location = self.location;
+                let available = self.available();
+                let count = match encode::number(count).map_err(at(count))? {
+                    value if value < 1 => {
+                        return Err((
+                            AssemblerError::BlkwUsage(format!(
+                                "The count of .BLKW must be positive, found {count}"
+                            )),
+                            count,
+                        ))
+                    }
+                    value if value as u32 > available => {
+                        return Err((
+                            AssemblerError::BlkwUsage(format!(
+                                "{count} words don't fit between x{location:04X} and xFFFF"
+                            )),
+                            count,
+                        ))
+                    }
+                    value => value as u16,
+                };
+                match operands.get(1) {
+                    Some(&value) => {
+                        encode::check_word(value).map_err(at(value))?;
+                        self.push(Item::Fill { value, count });
+                    }
+                    None => self.push(Item::Words(vec![0; count as usize])),
                 }
-                ".END" => {
-                    end_found = true;
+                self.advance(count as u32);
+            }
+            ".STRINGZ" => {
+                if operands.len() != 1 {
+                    return usage(AssemblerError::StringzUsage, "Usage: .STRINGZ \"<string>\"");
                 }
-                _ => return Err(AssemblerError::UnknownPseudoOp(first_part.to_string())),
+                self.check_room(operation)?;
+                let string = operands[0];
+                let words = stringz(string).map_err(at(string))?;
+                let length = u32::try_from(words.len())
+                    .ok()
+                    .filter(|&length| length <= self.available())
+                    .ok_or_else(|| {
+                        (
+                            AssemblerError::StringzUsage(format!(
+                                "{} words don't fit between x{:04X} and xFFFF",
+                                words.len(),
+                                self.location
+                            )),
+                            string,
+                        )
+                    })?;
+                self.advance(length);
+                self.push(Item::Words(words));
+            }
+            ".END" => {
+                self.end_found = true;
+            }
+            _ => {
+                return Err((
+                    AssemblerError::UnknownPseudoOp(operation.to_string()),
+                    operation,
+                ))
             }
         }
-
-        // Opcodes
-
-        // Labels
-    }
-
-    if !end_found {
-        return Err(AssemblerError::EndUsage("Missing .END".to_string()));
+        Ok(())
     }
-
-    Ok(output)
 }
-
-// fn encode_numeric(s: &String) -> Result<u16, AssemblerError> {
-//     let symbol = s.chars().next().unwrap();
-//
-//     // TODO: figure out better way to get the rest of the string after the first char
-//     let mut chars = s.chars();
-//     chars.next().unwrap();
-//     let number = chars.as_str();
-//
-//     println!("Encoding: {} {}", symbol, number);
-//
-//     match symbol {
-//         'b' | 'B' => u16::from_str_radix(number, 2)
-//             .map_err(|_| AssemblerError::InvalidBinary(number.to_string())),
-//         '#' => u16::from_str_radix(number, 10)
-//             .map_err(|_| AssemblerError::InvalidDecimal(number.to_string())),
-//         'x' | 'X' => u16::from_str_radix(number, 16)
-//             .map_err(|_| AssemblerError::InvalidHex(number.to_string())),
-//         _ => Err(AssemblerError::InvalidNumber(number.to_string())),
-//     }
-// }
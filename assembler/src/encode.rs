@@ -0,0 +1,407 @@
+use std::str::FromStr;
+
+use vm::{Isa, Opcode, Register};
+
+use crate::assembler::{at, AssemblerError, Located, SymbolTable};
+
+/// An instruction mnemonic, with whatever it implies beyond its opcode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Add,
+    And,
+    Not,
+    /// BR with its n, z and p flags
+    Br(u16),
+    Jmp,
+    Ret,
+    Jsr,
+    Jsrr,
+    Ld,
+    Ldi,
+    Ldr,
+    Lea,
+    St,
+    Sti,
+    Str,
+    Rti,
+    Trap,
+    /// GETC, OUT, PUTS, IN, PUTSP and HALT, with their trap vector
+    TrapAlias(u8),
+    #[cfg(feature = "lc3b")]
+    Ldb,
+    #[cfg(feature = "lc3b")]
+    Stb,
+    #[cfg(feature = "lc3b")]
+    Ldw,
+    #[cfg(feature = "lc3b")]
+    Stw,
+    #[cfg(feature = "lc3b")]
+    Xor,
+    /// LSHF, RSHFL and RSHFA, with bits \[5:4\] of the encoding
+    #[cfg(feature = "lc3b")]
+    Shift(u16),
+}
+
+impl Operation {
+    /// The operation for `mnemonic`, in any case
+    pub(crate) fn parse(mnemonic: &str) -> Option<Self> {
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let operation = match mnemonic.as_str() {
+            "ADD" => Operation::Add,
+            "AND" => Operation::And,
+            "NOT" => Operation::Not,
+            "JMP" => Operation::Jmp,
+            "RET" => Operation::Ret,
+            "JSR" => Operation::Jsr,
+            "JSRR" => Operation::Jsrr,
+            "LD" => Operation::Ld,
+            "LDI" => Operation::Ldi,
+            "LDR" => Operation::Ldr,
+            "LEA" => Operation::Lea,
+            "ST" => Operation::St,
+            "STI" => Operation::Sti,
+            "STR" => Operation::Str,
+            "RTI" => Operation::Rti,
+            "TRAP" => Operation::Trap,
+            "GETC" => Operation::TrapAlias(0x20),
+            "OUT" => Operation::TrapAlias(0x21),
+            "PUTS" => Operation::TrapAlias(0x22),
+            "IN" => Operation::TrapAlias(0x23),
+            "PUTSP" => Operation::TrapAlias(0x24),
+            "HALT" => Operation::TrapAlias(0x25),
+            #[cfg(feature = "lc3b")]
+            "LDB" => Operation::Ldb,
+            #[cfg(feature = "lc3b")]
+            "STB" => Operation::Stb,
+            #[cfg(feature = "lc3b")]
+            "LDW" => Operation::Ldw,
+            #[cfg(feature = "lc3b")]
+            "STW" => Operation::Stw,
+            #[cfg(feature = "lc3b")]
+            "XOR" => Operation::Xor,
+            #[cfg(feature = "lc3b")]
+            "LSHF" => Operation::Shift(0b00),
+            #[cfg(feature = "lc3b")]
+            "RSHFL" => Operation::Shift(0b01),
+            #[cfg(feature = "lc3b")]
+            "RSHFA" => Operation::Shift(0b11),
+            _ => return Self::parse_br(&mnemonic),
+        };
+        Some(operation)
+    }
+
+    /// `BR` followed by the flags in `nzp` order. Plain `BR` branches always.
+    fn parse_br(mnemonic: &str) -> Option<Self> {
+        let flags = mnemonic.strip_prefix("BR")?;
+        if flags.is_empty() {
+            return Some(Operation::Br(0b111));
+        }
+        let mut remaining = flags;
+        let mut bits = 0;
+        for (flag, bit) in [('N', 0b100), ('Z', 0b010), ('P', 0b001)] {
+            if let Some(rest) = remaining.strip_prefix(flag) {
+                remaining = rest;
+                bits |= bit;
+            }
+        }
+        remaining.is_empty().then_some(Operation::Br(bits))
+    }
+
+    /// The LC-3b reuses the opcodes of the instructions it drops
+    fn opcode(self) -> Opcode {
+        match self {
+            Operation::Add => Opcode::ADD,
+            Operation::And => Opcode::AND,
+            Operation::Not => Opcode::NOT,
+            Operation::Br(_) => Opcode::BR,
+            Operation::Jmp | Operation::Ret => Opcode::JMP,
+            Operation::Jsr | Operation::Jsrr => Opcode::JSR,
+            Operation::Ld => Opcode::LD,
+            Operation::Ldi => Opcode::LDI,
+            Operation::Ldr => Opcode::LDR,
+            Operation::Lea => Opcode::LEA,
+            Operation::St => Opcode::ST,
+            Operation::Sti => Opcode::STI,
+            Operation::Str => Opcode::STR,
+            Operation::Rti => Opcode::RTI,
+            Operation::Trap | Operation::TrapAlias(_) => Opcode::TRAP,
+            #[cfg(feature = "lc3b")]
+            Operation::Ldb => Opcode::LD,
+            #[cfg(feature = "lc3b")]
+            Operation::Stb => Opcode::ST,
+            #[cfg(feature = "lc3b")]
+            Operation::Ldw => Opcode::LDR,
+            #[cfg(feature = "lc3b")]
+            Operation::Stw => Opcode::STR,
+            #[cfg(feature = "lc3b")]
+            Operation::Xor => Opcode::NOT,
+            #[cfg(feature = "lc3b")]
+            Operation::Shift(_) => Opcode::RES,
+        }
+    }
+
+    /// False for the LC-3 loads and stores the LC-3b replaces and for the
+    /// LC-3b instructions in an LC-3 program. `vm` can have the LC-3b when
+    /// this crate doesn't, so `isa` is compared rather than matched.
+    fn is_available(self, isa: Isa) -> bool {
+        if isa == Isa::Lc3 {
+            match self {
+                #[cfg(feature = "lc3b")]
+                Operation::Ldb
+                | Operation::Stb
+                | Operation::Ldw
+                | Operation::Stw
+                | Operation::Xor
+                | Operation::Shift(_) => false,
+                _ => true,
+            }
+        } else {
+            !matches!(
+                self,
+                Operation::Ld
+                    | Operation::Ldi
+                    | Operation::Ldr
+                    | Operation::St
+                    | Operation::Sti
+                    | Operation::Str
+            )
+        }
+    }
+}
+
+/// Where an instruction is assembled
+pub(crate) struct Context<'a> {
+    pub(crate) address: u16,
+    pub(crate) isa: Isa,
+    pub(crate) symbols: &'a SymbolTable,
+}
+
+/// Encodes one instruction. `mnemonic` is the operation as written, for
+/// error messages.
+///
+///  ADD  DR, SR1, SR2|imm5      NOT  DR, SR         BRnzp label
+///  JMP  BaseR                  RET                 JSR   label
+///  JSRR BaseR                  LD   DR, label      LDR   DR, BaseR, offset6
+///  LEA  DR, label              RTI                 TRAP  trapvect8
+pub(crate) fn encode<'a>(
+    operation: Operation,
+    mnemonic: &'a str,
+    operands: &[&'a str],
+    context: &Context,
+) -> Result<u16, Located<'a>> {
+    if !operation.is_available(context.isa) {
+        return Err((
+            AssemblerError::WrongIsa(mnemonic.to_ascii_uppercase()),
+            mnemonic,
+        ));
+    }
+
+    let expected = match operation {
+        Operation::Ret | Operation::Rti | Operation::TrapAlias(_) => 0,
+        Operation::Br(_) | Operation::Jmp | Operation::Jsr | Operation::Jsrr | Operation::Trap => 1,
+        Operation::Not
+        | Operation::Ld
+        | Operation::Ldi
+        | Operation::Lea
+        | Operation::St
+        | Operation::Sti => 2,
+        _ => 3,
+    };
+    if operands.len() != expected {
+        return Err((
+            AssemblerError::InvalidOperands(format!(
+                "{} takes {expected} operands, found {}",
+                mnemonic.to_ascii_uppercase(),
+                operands.len()
+            )),
+            mnemonic,
+        ));
+    }
+
+    // Each operand by its position, with errors pointing at it
+    let register = |index: usize| register(operands[index]).map_err(at(operands[index]));
+    let operand = |index: usize| operand(operands[index]).map_err(at(operands[index]));
+    let signed = |index: usize, bits| signed(operands[index], bits).map_err(at(operands[index]));
+    let unsigned =
+        |index: usize, bits| unsigned(operands[index], bits).map_err(at(operands[index]));
+    let pc_offset =
+        |index: usize, bits| pc_offset(operands[index], bits, context).map_err(at(operands[index]));
+
+    let opcode = (u8::from(operation.opcode()) as u16) << 12;
+    let instruction = match operation {
+        Operation::Add | Operation::And => {
+            opcode | register(0)? << 9 | register(1)? << 6 | operand(2)?
+        }
+        #[cfg(feature = "lc3b")]
+        Operation::Xor => opcode | register(0)? << 9 | register(1)? << 6 | operand(2)?,
+        Operation::Not => opcode | register(0)? << 9 | register(1)? << 6 | 0x3F,
+        Operation::Br(flags) => opcode | flags << 9 | pc_offset(0, 9)?,
+        Operation::Jmp | Operation::Jsrr => opcode | register(0)? << 6,
+        Operation::Ret => opcode | u16::from(Register::R7) << 6,
+        Operation::Jsr => opcode | 1 << 11 | pc_offset(0, 11)?,
+        Operation::Ld | Operation::Ldi | Operation::Lea | Operation::St | Operation::Sti => {
+            opcode | register(0)? << 9 | pc_offset(1, 9)?
+        }
+        Operation::Ldr | Operation::Str => {
+            opcode | register(0)? << 9 | register(1)? << 6 | signed(2, 6)?
+        }
+        #[cfg(feature = "lc3b")]
+        Operation::Ldb | Operation::Stb | Operation::Ldw | Operation::Stw => {
+            opcode | register(0)? << 9 | register(1)? << 6 | signed(2, 6)?
+        }
+        #[cfg(feature = "lc3b")]
+        Operation::Shift(kind) => {
+            opcode | register(0)? << 9 | register(1)? << 6 | kind << 4 | unsigned(2, 4)?
+        }
+        Operation::Rti => opcode,
+        Operation::Trap => opcode | unsigned(0, 8)?,
+        Operation::TrapAlias(vector) => opcode | vector as u16,
+    };
+    Ok(instruction)
+}
+
+/// A general purpose register, R0 to R7 in any case
+fn register(operand: &str) -> Result<u16, AssemblerError> {
+    match Register::from_str(&operand.to_ascii_uppercase()) {
+        Ok(register) if Register::GPRS.contains(&register) => Ok(u16::from(register)),
+        _ => Err(AssemblerError::InvalidRegister(operand.to_string())),
+    }
+}
+
+/// The second source operand of ADD, AND and XOR: a register, or an imm5
+/// with bit \[5\] set
+fn operand(operand: &str) -> Result<u16, AssemblerError> {
+    match register(operand) {
+        Ok(register) => Ok(register),
+        Err(_) if parse_number(operand).is_some() => Ok(1 << 5 | signed(operand, 5)?),
+        Err(error) => Err(error),
+    }
+}
+
+/// A number that fits `bits` bits as a two's complement integer
+fn signed(operand: &str, bits: u32) -> Result<u16, AssemblerError> {
+    let value = number(operand)?;
+    fit_signed(value, bits).ok_or_else(|| out_of_range(operand, bits))
+}
+
+/// A number that fits `bits` bits unsigned
+fn unsigned(operand: &str, bits: u32) -> Result<u16, AssemblerError> {
+    let value = number(operand)?;
+    if (0..1 << bits).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(out_of_range(operand, bits))
+    }
+}
+
+fn fit_signed(value: i32, bits: u32) -> Option<u16> {
+    let limit = 1 << (bits - 1);
+    (-limit..limit)
+        .contains(&value)
+        .then_some(value as u16 & ((1 << bits) - 1))
+}
+
+fn out_of_range(operand: &str, bits: u32) -> AssemblerError {
+    AssemblerError::OutOfRange(format!("{operand} does not fit in {bits} bits"))
+}
+
+/// The offset from the incremented PC to a label, in words, or a number used
+/// as the offset itself
+fn pc_offset(operand: &str, bits: u32, context: &Context) -> Result<u16, AssemblerError> {
+    if parse_number(operand).is_some() {
+        return signed(operand, bits);
+    }
+
+    let target = label(operand, context.symbols)?;
+    let word_size = context.isa.word_size() as i32;
+    let pc = context.address as i32 + word_size;
+    let offset = (target as i32 - pc) / word_size;
+    fit_signed(offset, bits).ok_or_else(|| {
+        AssemblerError::OutOfRange(format!(
+            "{operand} is {offset} words away, too far for a {bits}-bit offset"
+        ))
+    })
+}
+
+/// The operand of `.FILL`: a number that fits 16 bits signed or unsigned, or
+/// the address of a label
+pub(crate) fn word(operand: &str, symbols: &SymbolTable) -> Result<u16, AssemblerError> {
+    match parse_number(operand) {
+        Some(value) => {
+            let value = value?;
+            if (-0x8000..=0xFFFF).contains(&value) {
+                Ok(value as u16)
+            } else {
+                Err(out_of_range(operand, 16))
+            }
+        }
+        None => label(operand, symbols),
+    }
+}
+
+/// Checks what the first pass can about a `.FILL` operand, before labels
+/// have addresses
+pub(crate) fn check_word(operand: &str) -> Result<(), AssemblerError> {
+    match parse_number(operand) {
+        Some(_) => word(operand, &SymbolTable::new()).map(|_| ()),
+        None if is_label(operand) => Ok(()),
+        None => Err(AssemblerError::InvalidLabel(operand.to_string())),
+    }
+}
+
+/// The address of a defined label
+pub(crate) fn label(operand: &str, symbols: &SymbolTable) -> Result<u16, AssemblerError> {
+    match symbols.get(operand) {
+        Some(address) => Ok(address),
+        None if is_label(operand) => Err(AssemblerError::UndefinedLabel(operand.to_string())),
+        None => Err(AssemblerError::InvalidLabel(operand.to_string())),
+    }
+}
+
+/// Labels start with a letter or underscore, go on with letters, digits and
+/// underscores, and can't be read as a number, a register or an instruction
+pub(crate) fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_well = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_');
+    starts_well
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_number(name).is_none()
+        && register(name).is_err()
+        && Operation::parse(name).is_none()
+}
+
+pub(crate) fn number(operand: &str) -> Result<i32, AssemblerError> {
+    parse_number(operand).unwrap_or_else(|| Err(AssemblerError::InvalidNumber(operand.to_string())))
+}
+
+/// Reads `#` decimal, `x` hex and `b` binary numbers, and plain decimal.
+/// Returns `None` for a token that isn't written as a number, such as a
+/// label that happens to start with `x` or `b`.
+pub(crate) fn parse_number(operand: &str) -> Option<Result<i32, AssemblerError>> {
+    let mut chars = operand.chars();
+    let (radix, digits, error): (u32, &str, fn(String) -> AssemblerError) = match chars.next()? {
+        '#' => (10, chars.as_str(), AssemblerError::InvalidDecimal),
+        'x' | 'X' => (16, chars.as_str(), AssemblerError::InvalidHex),
+        'b' | 'B' => (2, chars.as_str(), AssemblerError::InvalidBinary),
+        '-' | '0'..='9' => (10, operand, AssemblerError::InvalidDecimal),
+        _ => return None,
+    };
+
+    let (negative, magnitude) = match digits.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, digits),
+    };
+    let is_digits = !magnitude.is_empty() && magnitude.chars().all(|c| c.is_digit(radix));
+    if !is_digits {
+        // x and b only make a number when digits follow
+        return (radix == 10).then(|| Err(error(operand.to_string())));
+    }
+
+    let value = match i32::from_str_radix(magnitude, radix) {
+        Ok(value) if value <= u16::MAX as i32 => value,
+        _ => return Some(Err(error(operand.to_string()))),
+    };
+    Some(Ok(if negative { -value } else { value }))
+}
@@ -1,3 +1,8 @@
 pub mod assembler;
+mod encode;
+mod listing;
+mod macros;
+mod symbol_file;
 
 pub use crate::assembler::*;
+pub use crate::macros::MACRO_DEPTH_LIMIT;
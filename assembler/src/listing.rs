@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::assembler::Program;
+
+impl Program {
+    /// Formats a listing of the program: every line of `source` with the
+    /// address and the words it assembled to, in hex and binary, then the
+    /// symbol table. `source` must be the text the program was assembled
+    /// from.
+    ///
+    /// ```
+    /// let source = ".ORIG x3000\nLOOP BR LOOP\n.END\n";
+    /// let program = assembler::assemble_program(source).unwrap();
+    /// assert_eq!(
+    ///     "\
+    /// Address  Hex    Binary                Line  Source
+    ///                                          1  .ORIG x3000
+    /// x3000    x0FFF  0000 1111 1111 1111      2  LOOP BR LOOP
+    ///                                          3  .END
+    ///
+    /// Symbol  Address
+    /// LOOP    x3000
+    /// ",
+    ///     program.listing(source).to_string()
+    /// );
+    /// ```
+    pub fn listing<'a>(&'a self, source: &'a str) -> impl fmt::Display + 'a {
+        DisplayListing {
+            program: self,
+            source,
+        }
+    }
+}
+
+struct DisplayListing<'a> {
+    program: &'a Program,
+    source: &'a str,
+}
+
+/// The address and a word in hex and binary, the first columns of a row
+fn columns(address: u16, word: u16) -> String {
+    let binary = format!("{word:016b}");
+    let nibbles: Vec<_> = binary
+        .as_bytes()
+        .chunks(4)
+        .map(|nibble| std::str::from_utf8(nibble).unwrap())
+        .collect();
+    format!("x{address:04X}    x{word:04X}  {}", nibbles.join(" "))
+}
+
+impl fmt::Display for DisplayListing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let word_size = self.program.isa.word_size();
+        let mut lines = self.program.lines.iter().peekable();
+        let mut words = self.program.words.iter().copied();
+
+        writeln!(f, "Address  Hex    Binary                Line  Source")?;
+        for (index, text) in self.source.lines().enumerate() {
+            let number = index + 1;
+            let (address, count) = match lines.next_if(|line| line.number == number) {
+                Some(line) => (line.address, line.words),
+                None => (None, 0),
+            };
+
+            let first = match (address, count) {
+                (Some(address), 0) => format!("x{address:04X}"),
+                (Some(address), _) => columns(address, words.next().unwrap()),
+                (None, _) => String::new(),
+            };
+            let row = format!("{first:<35}{number:>7}  {text}");
+            writeln!(f, "{}", row.trim_end())?;
+
+            // Lines with more than one word, such as .BLKW and .STRINGZ,
+            // go on below their source line
+            let address = address.unwrap_or_default();
+            for offset in 1..count {
+                let address = address.wrapping_add(offset as u16 * word_size);
+                writeln!(f, "{}", columns(address, words.next().unwrap()))?;
+            }
+        }
+
+        let width = self
+            .program
+            .symbols
+            .iter()
+            .map(|(label, _)| label.len())
+            .max()
+            .unwrap_or(0)
+            .max("Symbol".len());
+        writeln!(f)?;
+        writeln!(f, "{:<width$}  Address", "Symbol")?;
+        for (label, address) in self.program.symbols.iter() {
+            writeln!(f, "{label:<width$}  x{address:04X}")?;
+        }
+        Ok(())
+    }
+}
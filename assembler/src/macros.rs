@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::assembler::{strip_comment, tokens, AssemblerError};
+use crate::encode::{self, Operation};
+
+/// How deep macros may invoke other macros, counting the invocation in the
+/// source as the first level. There is no conditional assembly, so a macro
+/// that invokes itself never stops; this turns that into an error.
+pub const MACRO_DEPTH_LIMIT: usize = 16;
+
+/// A macro from `.MACRO` to `.ENDM`
+struct Macro<'a> {
+    /// The name as it was defined
+    name: &'a str,
+    parameters: Vec<&'a str>,
+    /// The code of the lines between `.MACRO` and `.ENDM`, without comments
+    body: Vec<&'a str>,
+}
+
+/// A macro that is still being defined
+struct Definition<'a> {
+    name: &'a str,
+    parameters: Vec<&'a str>,
+    body: Vec<&'a str>,
+    /// Index of the `.MACRO` line in the lines, for a missing `.ENDM`
+    header: usize,
+    /// Whether the `.MACRO` line was right, so the macro can be used
+    valid: bool,
+}
+
+/// One invocation of a macro
+pub(crate) struct Call<'a> {
+    name: &'a str,
+    /// The name of the macro where it was invoked, in the source or in the
+    /// definition of another macro
+    invocation: &'a str,
+    /// The invocation of the macro that this one was invoked from
+    parent: Option<Rc<Call<'a>>>,
+}
+
+/// A line a macro expanded to
+pub(crate) struct Expansion<'a> {
+    text: String,
+    /// The tokens of `text`, each with where it came from in the source and
+    /// the token of the definition it was expanded from. They are the same
+    /// but for a parameter, which came from the argument it was replaced by.
+    pieces: Vec<(Range<usize>, &'a str, &'a str)>,
+    /// The line of the definition the text was expanded from
+    definition: &'a str,
+    call: Rc<Call<'a>>,
+}
+
+impl<'a> Expansion<'a> {
+    /// The part of the source `token`, a slice of the text, came from, and
+    /// the part of the definition
+    fn pieces(&self, token: &str) -> (&'a str, &'a str) {
+        let start = offset(&self.text, token);
+        let end = start + token.len();
+        self.pieces
+            .iter()
+            .find(|(range, ..)| range.start <= start && end <= range.end)
+            .map_or((self.definition, self.definition), |&(_, origin, body)| {
+                (origin, body)
+            })
+    }
+
+    fn origin(&self, token: &str) -> &'a str {
+        self.pieces(token).0
+    }
+
+    /// Where to report something about `token` in the source, and notes on
+    /// how it got there: the line of the definition, unless the token is
+    /// already in it, and every invocation down from the source
+    fn trace(&self, token: &str) -> (&'a str, Vec<(String, &'a str)>) {
+        let (origin, body) = self.pieces(token);
+        let mut notes = Vec::new();
+        if !contains(self.definition, origin) {
+            let message = format!("in the definition of {}", self.call.name);
+            notes.push((message, body));
+        }
+        let mut call = Some(&self.call);
+        while let Some(current) = call {
+            let message = format!("in this expansion of {}", current.name);
+            notes.push((message, current.invocation));
+            call = current.parent.as_ref();
+        }
+
+        // A macro that invokes itself is invoked from the same place at
+        // every level but the first
+        let same = |a: &str, b: &str| a.as_ptr() == b.as_ptr() && a.len() == b.len();
+        let mut unique: Vec<(String, &'a str)> = Vec::new();
+        for (message, token) in notes {
+            let seen = unique
+                .iter()
+                .any(|(other, piece)| *other == message && same(piece, token));
+            if !seen && !same(origin, token) {
+                unique.push((message, token));
+            }
+        }
+        (origin, unique)
+    }
+}
+
+/// The code of a line for the first pass
+pub(crate) enum Code<'a> {
+    Source(&'a str),
+    Expanded(Expansion<'a>),
+}
+
+impl<'a> Code<'a> {
+    pub(crate) fn text(&self) -> &str {
+        match self {
+            Code::Source(code) => code,
+            Code::Expanded(expansion) => &expansion.text,
+        }
+    }
+
+    /// The part of the source `token`, a slice of the text, came from
+    fn origin(&self, token: &str) -> &'a str {
+        match self {
+            Code::Source(code) => {
+                let start = offset(code, token);
+                &code[start..start + token.len()]
+            }
+            Code::Expanded(expansion) => expansion.origin(token),
+        }
+    }
+
+    /// The code of just `token`, a slice of the text
+    fn only(&self, token: &str) -> Code<'a> {
+        match self {
+            Code::Source(_) => Code::Source(self.origin(token)),
+            Code::Expanded(expansion) => Code::Expanded(Expansion {
+                text: token.to_string(),
+                pieces: {
+                    let (origin, body) = expansion.pieces(token);
+                    vec![(0..token.len(), origin, body)]
+                },
+                definition: expansion.definition,
+                call: Rc::clone(&expansion.call),
+            }),
+        }
+    }
+
+    fn call(&self) -> Option<Rc<Call<'a>>> {
+        match self {
+            Code::Source(_) => None,
+            Code::Expanded(expansion) => Some(Rc::clone(&expansion.call)),
+        }
+    }
+}
+
+/// A line of code with macros expanded, in the order the first pass takes
+/// them
+pub(crate) struct Line<'a> {
+    /// 1-based number of the source line, the invocation for a line a macro
+    /// expanded to
+    pub(crate) number: usize,
+    /// The code of that source line
+    pub(crate) source: &'a str,
+    pub(crate) code: Code<'a>,
+    /// Whether the line is part of a macro definition, which the first pass
+    /// skips
+    pub(crate) definition: bool,
+    /// An error found while expanding, with the part of the text it is about
+    pub(crate) error: Option<(AssemblerError, Range<usize>)>,
+}
+
+/// Byte offset of `token` in `text`, which it must be a slice of
+fn offset(text: &str, token: &str) -> usize {
+    token.as_ptr() as usize - text.as_ptr() as usize
+}
+
+/// Whether `token` is a slice of `text`
+fn contains(text: &str, token: &str) -> bool {
+    let start = text.as_ptr() as usize;
+    let token_start = token.as_ptr() as usize;
+    start <= token_start && token_start + token.len() <= start + text.len()
+}
+
+/// Where to report something about `token`, a slice of the source or of the
+/// code of one of `lines`, in the source, with notes on the macros it was
+/// expanded from
+pub(crate) fn origin<'t>(lines: &[Line<'t>], token: &'t str) -> (&'t str, Vec<(String, &'t str)>) {
+    let expansion = lines.iter().find_map(|line| match &line.code {
+        Code::Expanded(expansion) if contains(&expansion.text, token) => Some(expansion),
+        _ => None,
+    });
+    match expansion {
+        Some(expansion) => expansion.trace(token),
+        None => (token, Vec::new()),
+    }
+}
+
+/// Takes the macro definitions out of the source and replaces each
+/// invocation by the lines of its macro.
+///
+/// A parameter is replaced wherever it is a whole token of the body. Labels
+/// defined in the body are renamed after the expansion they are in, `LOOP`
+/// to `LOOP__1` in the first expansion of the program, `LOOP__2` in the
+/// second and so on, so that a macro with a label can be used more than
+/// once.
+pub(crate) fn expand(source: &str) -> Vec<Line<'_>> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        lines: Vec::new(),
+        expansions: 0,
+        runaway: false,
+    };
+    let mut definition: Option<Definition> = None;
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let code = strip_comment(line).trim();
+        let number = index + 1;
+        let tokens = tokens(code);
+        let directive = tokens.first().map(|first| first.to_ascii_uppercase());
+        let definition_line = |error: Option<(AssemblerError, &str)>| Line {
+            number,
+            source: code,
+            code: Code::Source(code),
+            definition: true,
+            error: error.map(|(error, token)| {
+                let start = offset(code, token);
+                (error, start..start + token.len())
+            }),
+        };
+
+        if let Some(open) = &mut definition {
+            match directive.as_deref() {
+                Some(".ENDM") => {
+                    let error = (tokens.len() > 1).then(|| {
+                        let usage = AssemblerError::MacroUsage("Usage: .ENDM".to_string());
+                        (usage, tokens[0])
+                    });
+                    expander.lines.push(definition_line(error));
+                    if let Some(open) = definition.take().filter(|open| open.valid) {
+                        let name = open.name.to_ascii_uppercase();
+                        let definition = Macro {
+                            name: open.name,
+                            parameters: open.parameters,
+                            body: open.body,
+                        };
+                        expander.macros.insert(name, Rc::new(definition));
+                    }
+                }
+                Some(".MACRO") => {
+                    let error = AssemblerError::MacroUsage(format!(
+                        "A macro can't be defined inside another, {} is missing .ENDM",
+                        open.name
+                    ));
+                    expander
+                        .lines
+                        .push(definition_line(Some((error, tokens[0]))));
+                }
+                _ => {
+                    open.body.push(code);
+                    expander.lines.push(definition_line(None));
+                }
+            }
+            continue;
+        }
+
+        match directive.as_deref() {
+            Some(".MACRO") => {
+                let header = expander.lines.len();
+                let (open, error) = expander.header(&tokens, header);
+                expander.lines.push(definition_line(error));
+                definition = Some(open);
+            }
+            Some(".ENDM") => {
+                let error = AssemblerError::MacroUsage(".ENDM without .MACRO".to_string());
+                expander
+                    .lines
+                    .push(definition_line(Some((error, tokens[0]))));
+            }
+            _ => expander.code(number, code, Code::Source(code), 0),
+        }
+    }
+
+    if let Some(open) = definition.filter(|open| open.valid) {
+        let line = &mut expander.lines[open.header];
+        let start = offset(line.source, open.name);
+        let error = AssemblerError::MacroUsage(format!("Missing .ENDM for {}", open.name));
+        line.error = Some((error, start..start + open.name.len()));
+    }
+    expander.lines
+}
+
+struct Expander<'a> {
+    /// The macros defined so far, by their name in upper case
+    macros: HashMap<String, Rc<Macro<'a>>>,
+    lines: Vec<Line<'a>>,
+    /// Invocations expanded so far, to number local labels
+    expansions: usize,
+    /// Whether the invocation in the current source line hit the depth limit,
+    /// which stops the rest of its expansion
+    runaway: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn is_macro(&self, token: &str) -> bool {
+        self.macros.contains_key(&token.to_ascii_uppercase())
+    }
+
+    /// Starts a definition from the tokens of its `.MACRO` line, and the
+    /// first error in it
+    fn header(
+        &self,
+        tokens: &[&'a str],
+        header: usize,
+    ) -> (Definition<'a>, Option<(AssemblerError, &'a str)>) {
+        let usage = |message: String, token| Some((AssemblerError::MacroUsage(message), token));
+        let name = tokens.get(1).copied().unwrap_or(tokens[0]);
+        let parameters = tokens.get(2..).unwrap_or_default().to_vec();
+        let error = if tokens.len() < 2 {
+            usage(
+                "Usage: .MACRO <name> [parameter, ...]".to_string(),
+                tokens[0],
+            )
+        } else if !encode::is_label(name) {
+            usage(format!("{name} can't be the name of a macro"), name)
+        } else if self.is_macro(name) {
+            usage(format!("Macro defined twice: {name}"), name)
+        } else {
+            parameters
+                .iter()
+                .enumerate()
+                .find_map(|(index, &parameter)| {
+                    if !encode::is_label(parameter) {
+                        usage(
+                            format!("{parameter} can't be the name of a parameter"),
+                            parameter,
+                        )
+                    } else if parameters[..index]
+                        .iter()
+                        .any(|other| other.eq_ignore_ascii_case(parameter))
+                    {
+                        usage(format!("Parameter named twice: {parameter}"), parameter)
+                    } else {
+                        None
+                    }
+                })
+        };
+        let definition = Definition {
+            name,
+            parameters,
+            body: Vec::new(),
+            header,
+            valid: error.is_none(),
+        };
+        (definition, error)
+    }
+
+    /// The label and the macro name of a line that invokes a macro, by index
+    /// in its tokens
+    fn invocation(&self, tokens: &[&str]) -> Option<(Option<usize>, usize)> {
+        let is_operation =
+            |token: &str| token.starts_with('.') || Operation::parse(token).is_some();
+        match tokens {
+            [first, ..] if self.is_macro(first) => Some((None, 0)),
+            [first, second, ..] if !is_operation(first) && self.is_macro(second) => {
+                Some((Some(0), 1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds a line of code, or the lines of the macro it invokes. `depth` is
+    /// the number of invocations it is inside of.
+    fn code(&mut self, number: usize, source: &'a str, code: Code<'a>, depth: usize) {
+        if depth == 0 {
+            self.runaway = false;
+        }
+        let words = tokens(code.text());
+        let Some((label, name)) = self.invocation(&words) else {
+            self.push(number, source, code, None);
+            return;
+        };
+        let definition = Rc::clone(&self.macros[&words[name].to_ascii_uppercase()]);
+        let invocation = code.origin(words[name]);
+        let arguments: Vec<(String, &'a str)> = words[name + 1..]
+            .iter()
+            .map(|&argument| (argument.to_string(), code.origin(argument)))
+            .collect();
+        let label = label.map(|label| code.only(words[label]));
+        let name_range = {
+            let start = offset(code.text(), words[name]);
+            start..start + words[name].len()
+        };
+
+        if depth >= MACRO_DEPTH_LIMIT {
+            let error = AssemblerError::MacroRecursion(format!(
+                "{} expands to more than {MACRO_DEPTH_LIMIT} levels of macros",
+                definition.name
+            ));
+            self.push(number, source, code, Some((error, name_range)));
+            // A macro that invokes itself more than once would otherwise
+            // report every one of its exponentially many invocations
+            self.runaway = true;
+            return;
+        }
+        if arguments.len() != definition.parameters.len() {
+            let expected = definition.parameters.len();
+            let error = AssemblerError::MacroArguments(format!(
+                "{} takes {expected} argument{}, found {}",
+                definition.name,
+                if expected == 1 { "" } else { "s" },
+                arguments.len()
+            ));
+            self.push(number, source, code, Some((error, name_range)));
+            return;
+        }
+        if let Some(label) = label {
+            self.push(number, source, label, None);
+        }
+
+        self.expansions += 1;
+        let suffix = format!("__{}", self.expansions);
+        let call = Rc::new(Call {
+            name: definition.name,
+            invocation,
+            parent: code.call(),
+        });
+        let locals: Vec<&str> = definition
+            .body
+            .iter()
+            .filter_map(|&line| tokens(line).first().copied())
+            .filter(|&first| {
+                encode::is_label(first)
+                    && !self.is_macro(first)
+                    && !definition
+                        .parameters
+                        .iter()
+                        .any(|parameter| parameter.eq_ignore_ascii_case(first))
+            })
+            .collect();
+
+        for &line in &definition.body {
+            if self.runaway {
+                return;
+            }
+            let mut text = String::new();
+            let mut pieces = Vec::new();
+            for token in tokens(line) {
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                let start = text.len();
+                // Parameters match in any case, like opcodes and registers
+                let parameter = definition
+                    .parameters
+                    .iter()
+                    .position(|parameter| parameter.eq_ignore_ascii_case(token));
+                let piece = match parameter {
+                    Some(index) => {
+                        let (argument, piece) = &arguments[index];
+                        text.push_str(argument);
+                        piece
+                    }
+                    None => {
+                        text.push_str(token);
+                        if locals.iter().any(|local| local.eq_ignore_ascii_case(token)) {
+                            text.push_str(&suffix);
+                        }
+                        token
+                    }
+                };
+                pieces.push((start..text.len(), piece, token));
+            }
+            let expansion = Expansion {
+                text,
+                pieces,
+                definition: line,
+                call: Rc::clone(&call),
+            };
+            self.code(number, source, Code::Expanded(expansion), depth + 1);
+        }
+    }
+
+    fn push(
+        &mut self,
+        number: usize,
+        source: &'a str,
+        code: Code<'a>,
+        error: Option<(AssemblerError, Range<usize>)>,
+    ) {
+        self.lines.push(Line {
+            number,
+            source,
+            code,
+            definition: false,
+            error,
+        });
+    }
+}
@@ -3,6 +3,8 @@ use std::env;
 use std::fs;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -11,81 +13,39 @@ fn main() {
     }
 
     let file = &args[1];
-    let program = fs::read_to_string(file).expect("Could not read file {file}");
+    let source = fs::read_to_string(file).expect("Could not read file {file}");
 
-    let output = assembler::assemble(program);
-    match output {
-        Ok(binary) => {
+    let report = assembler::assemble_report(&source);
+    for warning in &report.warnings {
+        eprintln!("{}", warning.display(file));
+    }
+    for error in &report.errors {
+        eprintln!("{}", error.display(file));
+    }
+    if !report.errors.is_empty() || !report.warnings.is_empty() {
+        eprintln!("{}", report.summary(file));
+    }
+
+    match report.program {
+        Some(program) => {
             // TODO: output assembled file to same directory as input file
-            let _ = write_file(&binary, "test.obj");
+            let _ = write_file(&program.words, "test.obj");
+
+            let outputs = [
+                ("lst", program.listing(&source).to_string()),
+                ("sym", program.symbols.sym_file().to_string()),
+            ];
+            for (extension, contents) in outputs {
+                let path = Path::new(file).with_extension(extension);
+                if let Err(err) = fs::write(&path, contents) {
+                    eprintln!("Could not write {}: {err}", path.display());
+                }
+            }
         }
-        Err(err) => eprintln!("Error: {}", err),
+        None => process::exit(1),
     }
 }
 
-// fn encode_line(parts: Vec<String>) -> u16 {
-//     let mut instruction: u16 = 0;
-//
-//     let op_code = OpCode::from_str(parts[0].as_str()).unwrap();
-//     println!("Encoded line:");
-//     println!("op: {:?}", &op_code);
-//
-//     instruction += (op_code as u16) << 12;
-//
-//     match op_code {
-//         //OpCode::BR => ,
-//         OpCode::ADD => {
-//             assert!(parts.len() == 4);
-//             let dr = Register::from_str(parts[1].as_str()).unwrap();
-//             let sr1 = Register::from_str(parts[2].as_str()).unwrap();
-//             println!("dr: {}", dr as u16);
-//             println!("sr1: {}", sr1 as u16);
-//
-//             instruction += ((dr as u16) << 9) + ((sr1 as u16) << 6);
-//
-//             // TODO: figure out a better way to do this
-//             match parts[3]
-//                 .chars()
-//                 .nth(0)
-//                 .expect("Missing ADD instruction argument")
-//             {
-//                 'x' | 'X' | 'b' | 'B' | '#' => {
-//                     let imm5_flag = 1;
-//                     let imm5 = encode_numeric(&parts[3]).unwrap();
-//                     instruction += (imm5_flag << 5) + imm5;
-//                     println!("imm5 flag: {}", imm5_flag);
-//                     println!("imm5: {}", imm5);
-//                 }
-//                 'R' => {
-//                     let sr2 = Register::from_str(parts[3].as_str()).unwrap();
-//                     instruction += sr2 as u16;
-//                     println!("sr2: {}", sr2 as u16);
-//                 }
-//                 _ => panic!("Unknown symbol {}", parts[3]),
-//             }
-//         }
-//         //OpCode::LD => ld(vm, instruction),
-//         //OpCode::ST => st(vm, instruction),
-//         //OpCode::JSR => jsr(vm, instruction),
-//         //OpCode::AND => and(vm, instruction),
-//         //OpCode::LDR => ldr(vm, instruction),
-//         //OpCode::STR => str(vm, instruction),
-//         //OpCode::RTI => rti(vm, instruction),
-//         //OpCode::NOT => not(vm, instruction),
-//         //OpCode::LDI => ldi(vm, instruction),
-//         //OpCode::STI => sti(vm, instruction),
-//         //OpCode::JMP => jmp(vm, instruction),
-//         //OpCode::RES => res(vm, instruction),
-//         //OpCode::LEA => lea(vm, instruction),
-//         OpCode::TRAP => {
-//             instruction = 0xFFFF;
-//         }
-//         _ => panic!("Op code not implemented {:?}", op_code),
-//     }
-//
-//     instruction
-// }
-
 fn write_file(data: &[u16], filename: &str) -> std::io::Result<()> {
     let mut buffer = Vec::new();
     for value in data {
@@ -0,0 +1,52 @@
+use std::fmt;
+
+use vm::Symbols;
+
+use crate::assembler::SymbolTable;
+
+impl SymbolTable {
+    /// Formats the table as a `.sym` file in the format lc3as writes, which
+    /// `vm::Symbols` reads back for the debugger, disassembler and profiler
+    ///
+    /// ```
+    /// let program = assembler::assemble_program(".ORIG x3000\nMAIN BR MAIN\n.END\n").unwrap();
+    /// assert_eq!(
+    ///     "// Symbol table
+    /// // Scope level 0:
+    /// //\tSymbol Name       Page Address
+    /// //\t----------------  ------------
+    /// //\tMAIN              3000
+    ///
+    /// ",
+    ///     program.symbols.sym_file().to_string()
+    /// );
+    /// ```
+    pub fn sym_file(&self) -> impl fmt::Display + '_ {
+        DisplaySymFile(self)
+    }
+}
+
+struct DisplaySymFile<'a>(&'a SymbolTable);
+
+impl fmt::Display for DisplaySymFile<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "// Symbol table")?;
+        writeln!(f, "// Scope level 0:")?;
+        writeln!(f, "//\tSymbol Name       Page Address")?;
+        writeln!(f, "//\t----------------  ------------")?;
+        for (label, address) in self.0.iter() {
+            writeln!(f, "//\t{label:<16}  {address:04X}")?;
+        }
+        writeln!(f)
+    }
+}
+
+impl From<&SymbolTable> for Symbols {
+    fn from(table: &SymbolTable) -> Self {
+        let mut symbols = Symbols::new();
+        for (label, address) in table.iter() {
+            symbols.insert(label, address);
+        }
+        symbols
+    }
+}
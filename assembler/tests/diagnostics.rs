@@ -0,0 +1,252 @@
+use assembler::{assemble_program, AssemblerError, Diagnostic, Span};
+
+fn diagnostic(program: &str) -> Diagnostic {
+    assemble_program(program).unwrap_err().remove(0)
+}
+
+fn span(line: usize, column: usize, length: usize) -> Span {
+    Span {
+        line,
+        column,
+        length,
+    }
+}
+
+#[test]
+fn errors_point_at_their_token() {
+    let cases = [
+        // Operands, found by the second pass
+        (".ORIG x3000\nADD R0, R8, #1\n.END", span(2, 9, 2)),
+        (
+            ".ORIG x3000\n  LDR R0, R1, #40 ; offset\n.END",
+            span(2, 15, 3),
+        ),
+        (".ORIG x3000\nLOOP BRz DONE\n.END", span(2, 10, 4)),
+        (".ORIG x3000\n.FILL NOWHERE\n.END", span(2, 7, 7)),
+        // Operations and labels, found by the first pass
+        (".ORIG x3000\nLOOP MOV R0, R1\n.END", span(2, 6, 3)),
+        (".ORIG x3000\nA ADD R0, R0, #1\nA RET\n.END", span(3, 1, 1)),
+        (".ORIG x3000\n.WORD 1\n.END", span(2, 1, 5)),
+        (".ORIG x3000\n.STRINGZ \"a, b\\q\"\n.END", span(2, 10, 8)),
+        (".ORIG x3000\n.BLKW 0\n.END", span(2, 7, 1)),
+        // Whole lines
+        ("ADD R0, R0, #1\n.ORIG x3000\n.END", span(1, 1, 14)),
+        (".ORIG x3000\n.END\nRET ; late\n", span(3, 1, 3)),
+    ];
+    for (program, expected) in cases {
+        assert_eq!(expected, diagnostic(program).span, "{program:?}");
+    }
+}
+
+#[test]
+fn missing_end_points_after_the_last_line() {
+    let diagnostic = diagnostic(".ORIG x3000\nHALT ; done\n\n; nothing more\n");
+    assert_eq!(
+        AssemblerError::EndUsage("Missing .END".to_string()),
+        diagnostic.error
+    );
+    assert_eq!(span(2, 5, 0), diagnostic.span);
+    assert_eq!("HALT ; done", diagnostic.source_line);
+}
+
+#[test]
+fn after_end_shows_the_line() {
+    let diagnostic = diagnostic(".ORIG x3000\n.END\nADD R0, R0, #1\n");
+    assert_eq!(
+        AssemblerError::EndUsage("ADD R0, R0, #1 after .END".to_string()),
+        diagnostic.error
+    );
+}
+
+#[test]
+fn renders_like_rustc() {
+    let program = ".ORIG x3000\n\tAND R0, R0, #0\n\tADD\tR0, R0, #16\t; too big\n.END\n";
+    assert_eq!(
+        "\
+error[E019]: Out of range: #16 does not fit in 5 bits
+ --> add.asm:3:14
+  |
+3 |     ADD    R0, R0, #16    ; too big
+  |                    ^^^
+",
+        diagnostic(program).display("add.asm").to_string()
+    );
+
+    let mut program = ".ORIG x3000\n".to_string();
+    program.push_str(&"ADD R0, R0, #1\n".repeat(10));
+    program.push_str("JMP R9\n.END\n");
+    assert_eq!(
+        "\
+error[E017]: Invalid register: R9
+  --> big.asm:12:5
+   |
+12 | JMP R9
+   |     ^^
+",
+        diagnostic(&program).display("big.asm").to_string()
+    );
+    assert_eq!(
+        "12:5: error[E017]: Invalid register: R9",
+        diagnostic(&program).to_string()
+    );
+}
+
+#[test]
+fn codes_are_unique() {
+    let errors = [
+        AssemblerError::InvalidBinary(String::new()),
+        AssemblerError::InvalidDecimal(String::new()),
+        AssemblerError::InvalidHex(String::new()),
+        AssemblerError::InvalidNumber(String::new()),
+        AssemblerError::UnknownPseudoOp(String::new()),
+        AssemblerError::OrigUsage(String::new()),
+        AssemblerError::EndUsage(String::new()),
+        AssemblerError::IsaUsage(String::new()),
+        AssemblerError::FillUsage(String::new()),
+        AssemblerError::BlkwUsage(String::new()),
+        AssemblerError::StringzUsage(String::new()),
+        AssemblerError::UnknownOpcode(String::new()),
+        AssemblerError::WrongIsa(String::new()),
+        AssemblerError::InvalidLabel(String::new()),
+        AssemblerError::DuplicateLabel(String::new()),
+        AssemblerError::UndefinedLabel(String::new()),
+        AssemblerError::InvalidRegister(String::new()),
+        AssemblerError::InvalidOperands(String::new()),
+        AssemblerError::OutOfRange(String::new()),
+        AssemblerError::MacroUsage(String::new()),
+        AssemblerError::MacroArguments(String::new()),
+        AssemblerError::MacroRecursion(String::new()),
+        AssemblerError::EndOfMemory(String::new()),
+    ];
+    let mut codes: Vec<_> = errors.iter().map(AssemblerError::code).collect();
+    codes.sort();
+    codes.dedup();
+    assert_eq!(errors.len(), codes.len());
+}
+
+#[test]
+fn every_error_is_reported() {
+    let program = "
+.ORIG   x3000
+START   ADD     R0, R0, R9
+        MOV     R1, R2
+START   BRz     NOWHERE
+        .FILL   #70000
+        LDR     R0, R1, #-40
+DONE    HALT
+";
+    let errors = assemble_program(program).unwrap_err();
+    let found: Vec<_> = errors
+        .iter()
+        .map(|error| (error.span.line, error.error.code()))
+        .collect();
+    assert_eq!(
+        vec![
+            (3, "E017"),
+            (4, "E012"),
+            (5, "E015"),
+            (5, "E016"),
+            (6, "E002"),
+            (7, "E019"),
+            (8, "E007"),
+        ],
+        found
+    );
+}
+
+#[test]
+fn addresses_survive_bad_lines() {
+    let program = "
+.ORIG   x3000
+        MOV     R1, R2
+AGAIN   ADD     R0, R0, #1
+AGAIN   ADD     R0, R0, #2
+        BRnzp   AGAIN
+.END
+";
+    let report = assembler::assemble_report(program);
+    assert_eq!(None, report.program);
+    assert_eq!(2, report.errors.len());
+    // The unknown opcode still takes a word, and the first AGAIN is kept
+    let program = program.replace("MOV     R1, R2", "ADD R1, R1, R2");
+    let program = program.replacen(
+        "AGAIN   ADD     R0, R0, #2",
+        "        ADD     R0, R0, #2",
+        1,
+    );
+    let words = assembler::assemble(program).unwrap();
+    assert_eq!(0x0FFD, words[3]);
+}
+
+#[test]
+fn warnings_do_not_stop_assembly() {
+    let program = "
+.ORIG   3000
+        ADD     R0, R0, 12
+        .FILL   100
+        .BLKW   2
+.END
+";
+    let report = assembler::assemble_report(program);
+    assert!(report.errors.is_empty());
+    assert_eq!(
+        Some(vec![0x102C, 0x0064, 0x0000, 0x0000]),
+        report.program.as_ref().map(|program| program.words.clone())
+    );
+    let warnings: Vec<_> = report
+        .warnings
+        .iter()
+        .map(|warning| (warning.span, warning.warning.code()))
+        .collect();
+    assert_eq!(
+        vec![(span(2, 9, 4), "W001"), (span(2, 9, 4), "W002")],
+        warnings
+    );
+    assert_eq!(
+        "\
+warning[W001]: 3000 is read as decimal, write #3000 or x3000 to be clear
+ --> add.asm:2:9
+  |
+2 | .ORIG   3000
+  |         ^^^^
+",
+        report.warnings[0].display("add.asm").to_string()
+    );
+    assert_eq!(
+        "warning: 2 warnings emitted",
+        report.summary("add.asm").to_string()
+    );
+}
+
+#[test]
+fn implicit_decimal_too_long_for_hex() {
+    let report = assembler::assemble_report(".ORIG 12288\n.END\n");
+    assert_eq!(
+        "12288 is read as decimal, write #12288 to be clear",
+        report.warnings[0].warning.to_string()
+    );
+}
+
+#[test]
+fn empty_source() {
+    let errors = assemble_program("").unwrap_err();
+    assert_eq!(1, errors.len());
+    assert_eq!(span(1, 1, 0), errors[0].span);
+}
+
+#[test]
+fn binary_exits_with_failure() {
+    let path = std::env::temp_dir().join(format!("diagnostics-{}.asm", std::process::id()));
+    std::fs::write(&path, ".ORIG x3000\nADD R0, R0\nJMP R8\n.END\n").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_assembler"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error[E018]: ADD takes 3 operands, found 2"));
+    assert!(stderr.contains("error[E017]: Invalid register: R8"));
+    assert!(stderr.ends_with("due to 2 errors\n"), "{stderr}");
+}
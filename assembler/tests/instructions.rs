@@ -0,0 +1,276 @@
+use assembler::{assemble, assemble_program, AssemblerError};
+use vm::{HaltReason, Harness, Register};
+
+fn words(body: &str) -> Result<Vec<u16>, AssemblerError> {
+    assemble(format!(".ORIG x3000\n{body}\n.END\n")).map_err(|errors| errors[0].error.clone())
+}
+
+#[test]
+fn operate_instructions() {
+    let program = "
+        ADD R1, R2, R3
+        ADD R1, R2, #-16
+        AND r7, r0, x0F
+        NOT R4, R5
+    ";
+    assert_eq!(Ok(vec![0x1283, 0x12B0, 0x5E2F, 0x997F]), words(program));
+}
+
+#[test]
+fn fixture() {
+    let output = assemble(include_str!("add_number_to_register.asm").to_string());
+    assert_eq!(
+        Ok(vec![0x1023, 0x1263, 0x14A5, 0x16E5, 0x1927, 0x1A01, 0xF025]),
+        output
+    );
+}
+
+#[test]
+fn branches_to_labels() {
+    let program = "
+TOP     BRn     TOP
+        BRz     NEXT
+        BRp     TOP
+NEXT    BR      TOP
+        BRnzp   NEXT
+        BRzp    #0
+        BRnp    #-1
+    ";
+    assert_eq!(
+        Ok(vec![0x09FF, 0x0401, 0x03FD, 0x0FFC, 0x0FFE, 0x0600, 0x0BFF]),
+        words(program)
+    );
+}
+
+#[test]
+fn subroutines_and_jumps() {
+    let program = "
+        JSR     SUB
+        JSRR    R3
+        JMP     R2
+SUB     RET
+        RTI
+    ";
+    assert_eq!(
+        Ok(vec![0x4802, 0x40C0, 0xC080, 0xC1C0, 0x8000]),
+        words(program)
+    );
+}
+
+#[test]
+fn memory_instructions() {
+    let program = "
+DATA    LD      R0, DATA
+        LDI     R1, DATA
+        LEA     R2, AFTER
+        ST      R3, DATA
+        STI     R4, AFTER
+        LDR     R5, R6, #-32
+        STR     R7, R0, #31
+AFTER   LD      R0, x0005
+    ";
+    assert_eq!(
+        Ok(vec![
+            0x21FF, 0xA3FE, 0xE404, 0x37FC, 0xB802, 0x6BA0, 0x7E1F, 0x2005
+        ]),
+        words(program)
+    );
+}
+
+#[test]
+fn traps() {
+    let program = "
+        GETC
+        OUT
+        PUTS
+        IN
+        PUTSP
+        HALT
+        TRAP x30
+    ";
+    assert_eq!(
+        Ok(vec![0xF020, 0xF021, 0xF022, 0xF023, 0xF024, 0xF025, 0xF030]),
+        words(program)
+    );
+}
+
+#[test]
+fn symbol_table() {
+    let program = assemble_program(
+        "
+        .ORIG x4000
+MAIN    ADD R0, R0, #1
+LOOP
+        BRnzp LOOP
+DONE    HALT
+        .END
+",
+    )
+    .unwrap();
+
+    assert_eq!(0x4000, program.origin);
+    assert_eq!(vec![0x1021, 0x0FFF, 0xF025], program.words);
+    let symbols: Vec<_> = program.symbols.iter().collect();
+    assert_eq!(
+        vec![("MAIN", 0x4000), ("LOOP", 0x4001), ("DONE", 0x4002)],
+        symbols
+    );
+    assert_eq!(Some(0x4001), program.symbols.get("LOOP"));
+}
+
+#[test]
+fn assembled_program_runs() {
+    // Multiplies 6 by 7 in a subroutine
+    let program = "
+        AND     R0, R0, #0
+        ADD     R1, R0, #6
+        ADD     R2, R0, #7
+        JSR     MULTIPLY
+        HALT
+MULTIPLY
+        AND     R3, R3, #0
+AGAIN   ADD     R3, R3, R1
+        ADD     R2, R2, #-1
+        BRp     AGAIN
+        RET
+    ";
+    let result = Harness::new().run(&words(program).unwrap());
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+    assert_eq!(42, result.registers.get(Register::R3));
+}
+
+#[test]
+fn undefined_label() {
+    assert_eq!(
+        Err(AssemblerError::UndefinedLabel("NOWHERE".to_string())),
+        words("BR NOWHERE")
+    );
+}
+
+#[test]
+fn duplicate_label() {
+    assert_eq!(
+        Err(AssemblerError::DuplicateLabel("AGAIN".to_string())),
+        words("AGAIN ADD R0, R0, #1\nAGAIN ADD R0, R0, #1")
+    );
+}
+
+#[test]
+fn invalid_label() {
+    assert!(matches!(
+        words("R3 ADD R0, R0, #1"),
+        Err(AssemblerError::InvalidLabel(_))
+    ));
+}
+
+#[test]
+fn unknown_opcode() {
+    assert_eq!(
+        Err(AssemblerError::UnknownOpcode("MOV".to_string())),
+        words("START MOV R0, R1")
+    );
+}
+
+#[test]
+fn invalid_register() {
+    assert_eq!(
+        Err(AssemblerError::InvalidRegister("R8".to_string())),
+        words("ADD R8, R0, R0")
+    );
+    assert_eq!(
+        Err(AssemblerError::InvalidRegister("PC".to_string())),
+        words("JMP PC")
+    );
+}
+
+#[test]
+fn wrong_operand_count() {
+    assert!(matches!(
+        words("ADD R0, R1"),
+        Err(AssemblerError::InvalidOperands(_))
+    ));
+    assert!(matches!(
+        words("RET R7"),
+        Err(AssemblerError::InvalidOperands(_))
+    ));
+}
+
+#[test]
+fn out_of_range() {
+    assert!(matches!(
+        words("ADD R0, R0, #16"),
+        Err(AssemblerError::OutOfRange(_))
+    ));
+    assert!(matches!(
+        words("TRAP x100"),
+        Err(AssemblerError::OutOfRange(_))
+    ));
+    assert!(matches!(
+        words("LDR R0, R0, #32"),
+        Err(AssemblerError::OutOfRange(_))
+    ));
+
+    // 256 words forward is one too many for PCoffset9
+    let far = format!("BR FAR\n{}FAR HALT", "ADD R0, R0, #0\n".repeat(256));
+    assert!(matches!(words(&far), Err(AssemblerError::OutOfRange(_))));
+    let near = format!("BR NEAR\n{}NEAR HALT", "ADD R0, R0, #0\n".repeat(255));
+    assert!(words(&near).is_ok());
+}
+
+#[test]
+fn invalid_numbers() {
+    assert_eq!(
+        Err(AssemblerError::InvalidDecimal("#1x".to_string())),
+        words("ADD R0, R0, #1x")
+    );
+    assert_eq!(
+        Err(AssemblerError::InvalidDecimal("12a".to_string())),
+        words("ADD R0, R0, 12a")
+    );
+}
+
+#[cfg(feature = "lc3b")]
+#[test]
+fn lc3b_instructions() {
+    let program = "
+        .ISA    LC3B
+        LEA     R1, DATA
+        LDB     R2, R1, #1
+        STB     R2, R1, #-1
+        LDW     R3, R1, #0
+        STW     R3, R1, #1
+        XOR     R4, R3, #-1
+        LSHF    R5, R3, #2
+        RSHFL   R5, R5, #1
+        RSHFA   R6, R4, #15
+        HALT
+DATA    BR      DATA
+    ";
+    assert_eq!(
+        Ok(vec![
+            0xE209, 0x2441, 0x347F, 0x6640, 0x7641, 0x98FF, 0xDAC2, 0xDB51, 0xDD3F, 0xF025, 0x0FFF
+        ]),
+        words(program)
+    );
+
+    assert_eq!(
+        Err(AssemblerError::WrongIsa("LDR".to_string())),
+        words(".ISA LC3B\nLDR R0, R0, #0")
+    );
+    assert_eq!(
+        Err(AssemblerError::WrongIsa("LDB".to_string())),
+        words("LDB R0, R0, #0")
+    );
+}
+
+#[test]
+fn data_fixture_runs() {
+    let words = assemble(include_str!("add_two_registers.asm").to_string()).unwrap();
+    assert_eq!(
+        vec![0x2204, 0x2404, 0x1642, 0x3603, 0xF025, 0x0005, 0x0003, 0x0000],
+        words
+    );
+    let result = Harness::new().run(&words);
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+    assert_eq!(8, result.registers.get(Register::R3));
+}
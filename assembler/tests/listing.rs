@@ -0,0 +1,120 @@
+use assembler::{assemble_program, SourceLine};
+
+#[test]
+fn fixture_listing() {
+    let source = include_str!("add_two_registers.asm");
+    let program = assemble_program(source).unwrap();
+    let listing = program.listing(source).to_string();
+    let expected = "\
+Address  Hex    Binary                Line  Source
+                                         1          .ORIG   x3000      ; Starting address
+x3000    x2204  0010 0010 0000 0100      2          LD      R1, NUM1   ; Load the first number into R1
+x3001    x2404  0010 0100 0000 0100      3          LD      R2, NUM2   ; Load the second number into R2
+x3002    x1642  0001 0110 0100 0010      4          ADD     R3, R1, R2 ; Add R1 and R2, store the result in R3
+x3003    x3603  0011 0110 0000 0011      5          ST      R3, RESULT ; Store the result in memory
+x3004    xF025  1111 0000 0010 0101      6          HALT               ; Halt the program
+                                         7
+x3005    x0005  0000 0000 0000 0101      8  NUM1    .FILL   x0005      ; First number (5)
+x3006    x0003  0000 0000 0000 0011      9  NUM2    .FILL   x0003      ; Second number (3)
+x3007    x0000  0000 0000 0000 0000     10  RESULT  .BLKW   1          ; Reserve one memory location for the result
+                                        11          .END               ; End of program
+
+Symbol  Address
+NUM1    x3005
+NUM2    x3006
+RESULT  x3007
+";
+    assert_eq!(expected, listing);
+}
+
+#[test]
+fn words_of_a_line_go_below_it() {
+    let source = "\
+; greeting
+.ORIG x3000
+LONGER_NAME
+MSG .STRINGZ \"Hi\"
+    .BLKW #2, MSG
+.END
+";
+    let program = assemble_program(source).unwrap();
+    assert_eq!(
+        vec![
+            SourceLine {
+                number: 2,
+                address: None,
+                words: 0
+            },
+            SourceLine {
+                number: 3,
+                address: Some(0x3000),
+                words: 0
+            },
+            SourceLine {
+                number: 4,
+                address: Some(0x3000),
+                words: 3
+            },
+            SourceLine {
+                number: 5,
+                address: Some(0x3003),
+                words: 2
+            },
+            SourceLine {
+                number: 6,
+                address: None,
+                words: 0
+            },
+        ],
+        program.lines
+    );
+    let expected = "\
+Address  Hex    Binary                Line  Source
+                                         1  ; greeting
+                                         2  .ORIG x3000
+x3000                                    3  LONGER_NAME
+x3000    x0048  0000 0000 0100 1000      4  MSG .STRINGZ \"Hi\"
+x3001    x0069  0000 0000 0110 1001
+x3002    x0000  0000 0000 0000 0000
+x3003    x3000  0011 0000 0000 0000      5      .BLKW #2, MSG
+x3004    x3000  0011 0000 0000 0000
+                                         6  .END
+
+Symbol       Address
+LONGER_NAME  x3000
+MSG          x3000
+";
+    assert_eq!(expected, program.listing(source).to_string());
+}
+
+#[cfg(feature = "lc3b")]
+#[test]
+fn lc3b_addresses_count_bytes() {
+    let source = ".ORIG x3000\n.ISA LC3B\n.BLKW 2 x1\nHALT\n.END\n";
+    let program = assemble_program(source).unwrap();
+    let listing = program.listing(source).to_string();
+    assert!(listing
+        .contains("\nx3000    x0001  0000 0000 0000 0001      3  .BLKW 2 x1\nx3002    x0001"));
+    assert!(listing.contains("\nx3004    xF025  1111 0000 0010 0101      4  HALT\n"));
+}
+
+#[test]
+fn fixture_sym_file() {
+    let program = assemble_program(include_str!("add_two_registers.asm")).unwrap();
+    let sym_file = program.symbols.sym_file().to_string();
+    let expected = "\
+// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tNUM1              3005
+//\tNUM2              3006
+//\tRESULT            3007
+
+";
+    assert_eq!(expected, sym_file);
+
+    let parsed: vm::Symbols = sym_file.parse().unwrap();
+    assert_eq!(vm::Symbols::from(&program.symbols), parsed);
+    assert_eq!("RESULT", parsed.symbolize(0x3007));
+}
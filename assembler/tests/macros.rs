@@ -0,0 +1,336 @@
+use assembler::{
+    assemble, assemble_program, AssemblerError, Diagnostic, SourceLine, Span, MACRO_DEPTH_LIMIT,
+};
+use vm::{HaltReason, Harness, Register};
+
+const STACK: &str = "\
+.MACRO PUSH REG
+        ADD R6, R6, #-1
+        STR REG, R6, #0
+.ENDM
+.MACRO POP REG
+        LDR REG, R6, #0 ; top of the stack
+        ADD R6, R6, #1
+.ENDM
+";
+
+fn errors(program: &str) -> Vec<Diagnostic> {
+    assemble_program(program).unwrap_err()
+}
+
+fn span(line: usize, column: usize, length: usize) -> Span {
+    Span {
+        line,
+        column,
+        length,
+    }
+}
+
+#[test]
+fn parameters_are_replaced_by_arguments() {
+    let program = format!("{STACK}.ORIG x3000\nPUSH R1\nPOP r2\n.END\n");
+    let expected = assemble(
+        "\
+.ORIG x3000
+ADD R6, R6, #-1
+STR R1, R6, #0
+LDR r2, R6, #0
+ADD R6, R6, #1
+.END
+"
+        .to_string(),
+    );
+    assert_eq!(expected, assemble(program));
+}
+
+#[test]
+fn names_are_matched_in_any_case() {
+    let program = "\
+.MACRO PUSH reg
+        ADD R6, R6, #-1
+        STR REG, R6, #0
+.ENDM
+        .ORIG x3000
+        PUSH R1
+        .END
+";
+    assert_eq!(
+        vec![0x1DBF, 0x7380],
+        assemble_program(program).unwrap().words
+    );
+}
+
+#[test]
+fn macros_can_invoke_macros() {
+    let program = format!(
+        "{STACK}\
+.MACRO SWAP A, B
+        PUSH A
+        PUSH B
+        POP A
+        POP B
+.ENDM
+        .ORIG x3000
+        LD R6, STACK
+        AND R1, R1, #0
+        ADD R2, R1, #7
+SWAPPED SWAP R1, R2
+        HALT
+STACK   .FILL x4000
+        .END
+"
+    );
+    let program = assemble_program(&program).unwrap();
+    assert_eq!(Some(0x3003), program.symbols.get("SWAPPED"));
+
+    let result = Harness::new().run(&program.words);
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+    assert_eq!(7, result.registers.get(Register::R1));
+    assert_eq!(0, result.registers.get(Register::R2));
+    assert_eq!(0x4000, result.registers.get(Register::R6));
+}
+
+#[test]
+fn labels_are_local_to_each_expansion() {
+    let program = "\
+.MACRO COUNTDOWN REG, FROM
+        ADD REG, REG, FROM
+LOOP    ADD REG, REG, #-1
+        BRp LOOP
+.ENDM
+        .ORIG x3000
+LOOP    COUNTDOWN R0, #3
+        COUNTDOWN R1, #2
+        BR LOOP
+        .END
+";
+    let program = assemble_program(program).unwrap();
+    let labels: Vec<_> = program.symbols.iter().collect();
+    assert_eq!(
+        vec![("LOOP", 0x3000), ("LOOP__1", 0x3001), ("LOOP__2", 0x3004)],
+        labels
+    );
+    assert_eq!(
+        vec![0x1023, 0x103F, 0x03FE, 0x1262, 0x127F, 0x03FE, 0x0FF9],
+        program.words
+    );
+}
+
+#[test]
+fn an_invocation_is_one_line_of_the_listing() {
+    let source = format!("{STACK}.ORIG x3000\nPUSH R1\nHALT\n.END\n");
+    let program = assemble_program(&source).unwrap();
+    assert_eq!(
+        vec![
+            SourceLine {
+                number: 9,
+                address: None,
+                words: 0
+            },
+            SourceLine {
+                number: 10,
+                address: Some(0x3000),
+                words: 2
+            },
+            SourceLine {
+                number: 11,
+                address: Some(0x3002),
+                words: 1
+            },
+            SourceLine {
+                number: 12,
+                address: None,
+                words: 0
+            },
+        ],
+        program.lines
+    );
+}
+
+#[test]
+fn errors_point_at_the_invocation_and_the_definition() {
+    let program = format!("{STACK}.ORIG x3000\nPOP R9\n.END\n");
+    assert_eq!(
+        "\
+error[E017]: Invalid register: R9
+  --> stack.asm:10:5
+   |
+10 | POP R9
+   |     ^^
+note: in the definition of POP
+ --> stack.asm:6:13
+  |
+6 |         LDR REG, R6, #0 ; top of the stack
+  |             ^^^
+note: in this expansion of POP
+  --> stack.asm:10:1
+   |
+10 | POP R9
+   | ^^^
+",
+        errors(&program)[0].display("stack.asm").to_string()
+    );
+
+    // A mistake in the definition itself is shown there
+    let program = "\
+.MACRO CLEAR REG
+        AND REG, REG, #32
+.ENDM
+.ORIG x3000
+CLEAR R0
+.END
+";
+    let error = &errors(program)[0];
+    assert_eq!(span(2, 23, 3), error.span);
+    assert_eq!(1, error.notes.len());
+    assert_eq!("in this expansion of CLEAR", error.notes[0].message);
+    assert_eq!(span(5, 1, 5), error.notes[0].span);
+}
+
+#[test]
+fn nested_expansions_note_every_invocation() {
+    let program = "\
+.MACRO INNER
+        JMP R8
+.ENDM
+.MACRO OUTER
+        INNER
+.ENDM
+.ORIG x3000
+        OUTER
+.END
+";
+    let error = &errors(program)[0];
+    assert_eq!(
+        AssemblerError::InvalidRegister("R8".to_string()),
+        error.error
+    );
+    assert_eq!(span(2, 13, 2), error.span);
+    let notes: Vec<_> = error
+        .notes
+        .iter()
+        .map(|note| (note.message.as_str(), note.span))
+        .collect();
+    assert_eq!(
+        vec![
+            ("in this expansion of INNER", span(5, 9, 5)),
+            ("in this expansion of OUTER", span(8, 9, 5)),
+        ],
+        notes
+    );
+}
+
+#[test]
+fn recursion_is_limited() {
+    let program = "\
+.MACRO FOREVER
+        ADD R0, R0, #1
+        FOREVER
+.ENDM
+.ORIG x3000
+        FOREVER
+.END
+";
+    let errors = errors(program);
+    assert_eq!(1, errors.len());
+    assert_eq!(
+        AssemblerError::MacroRecursion(format!(
+            "FOREVER expands to more than {MACRO_DEPTH_LIMIT} levels of macros"
+        )),
+        errors[0].error
+    );
+    assert_eq!(span(3, 9, 7), errors[0].span);
+    let notes: Vec<_> = errors[0].notes.iter().map(|note| note.span).collect();
+    assert_eq!(vec![span(6, 9, 7)], notes);
+
+    // Invoking itself twice stops at the first error, not after 2^16
+    let program = ".MACRO M\nM\nM\n.ENDM\n.ORIG x3000\nM\n.END\n";
+    let errors = assemble_program(program).unwrap_err();
+    assert_eq!(1, errors.len());
+    assert_eq!(span(2, 1, 1), errors[0].span);
+}
+
+#[test]
+fn argument_count_must_match() {
+    let program = format!("{STACK}.ORIG x3000\nPUSH R1, R2\nPOP\n.END\n");
+    let errors: Vec<_> = errors(&program)
+        .into_iter()
+        .map(|error| (error.error, error.span))
+        .collect();
+    assert_eq!(
+        vec![
+            (
+                AssemblerError::MacroArguments("PUSH takes 1 argument, found 2".to_string()),
+                span(10, 1, 4)
+            ),
+            (
+                AssemblerError::MacroArguments("POP takes 1 argument, found 0".to_string()),
+                span(11, 1, 3)
+            ),
+        ],
+        errors
+    );
+}
+
+#[test]
+fn definitions_are_checked() {
+    let cases = [
+        (
+            ".MACRO\n.ENDM",
+            "Usage: .MACRO <name> [parameter, ...]",
+            span(1, 1, 6),
+        ),
+        (
+            ".MACRO ADD\n.ENDM",
+            "ADD can't be the name of a macro",
+            span(1, 8, 3),
+        ),
+        (
+            ".MACRO M R1\n.ENDM",
+            "R1 can't be the name of a parameter",
+            span(1, 10, 2),
+        ),
+        (
+            ".MACRO M A, A\n.ENDM",
+            "Parameter named twice: A",
+            span(1, 13, 1),
+        ),
+        (
+            ".MACRO M a, A\n.ENDM",
+            "Parameter named twice: A",
+            span(1, 13, 1),
+        ),
+        (
+            ".MACRO M\n.ENDM\n.macro m\n.ENDM",
+            "Macro defined twice: m",
+            span(3, 8, 1),
+        ),
+        (".MACRO M\n.ENDM M", "Usage: .ENDM", span(2, 1, 5)),
+        (".ENDM", ".ENDM without .MACRO", span(1, 1, 5)),
+        (
+            ".MACRO M\n.MACRO N\n.ENDM",
+            "A macro can't be defined inside another, M is missing .ENDM",
+            span(2, 1, 6),
+        ),
+        (".MACRO M\nHALT", "Missing .ENDM for M", span(1, 8, 1)),
+    ];
+    for (definition, message, expected) in cases {
+        let program = format!("{definition}\n.ORIG x3000\n.END\n");
+        let error = &errors(&program)[0];
+        assert_eq!(
+            AssemblerError::MacroUsage(message.to_string()),
+            error.error,
+            "{definition:?}"
+        );
+        assert_eq!(expected, error.span, "{definition:?}");
+    }
+}
+
+#[test]
+fn definitions_after_end_are_errors() {
+    let program = ".ORIG x3000\n.END\n.MACRO LATE\n.ENDM\n";
+    assert_eq!(
+        AssemblerError::EndUsage(".MACRO LATE after .END".to_string()),
+        errors(program)[0].error
+    );
+}
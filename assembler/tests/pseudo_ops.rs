@@ -3,6 +3,7 @@
 // .FILL
 // .BLKW
 // .STRINGZ
+// .ISA
 // .END
 
 #[test]
@@ -64,3 +65,217 @@ fn orig_missing_numeric() {
     let output = assembler::assemble(program.to_string());
     assert!(output.is_err());
 }
+
+#[test]
+fn isa_lc3() {
+    let program = "
+.ORIG   x3000
+.ISA    LC3
+.END
+";
+    let output = assembler::assemble(program.to_string());
+    assert!(output.is_ok_and(|binary| binary.is_empty()));
+}
+
+#[cfg(feature = "lc3b")]
+#[test]
+fn isa_lc3b() {
+    let program = "
+.ORIG   x3000
+.ISA    LC3B
+.END
+";
+    let output = assembler::assemble(program.to_string());
+    assert!(output.is_ok_and(|binary| binary.is_empty()));
+}
+
+#[test]
+fn isa_unknown() {
+    let program = "
+.ORIG   x3000
+.ISA    LC4
+.END
+";
+    let output = assembler::assemble(program.to_string());
+    assert!(output.is_err());
+}
+
+#[test]
+fn isa_twice() {
+    let program = "
+.ORIG   x3000
+.ISA    LC3
+.ISA    LC3
+.END
+";
+    let output = assembler::assemble(program.to_string());
+    assert!(output.is_err());
+}
+
+#[test]
+fn fill_numbers_and_labels() {
+    let program = "
+.ORIG   x3000
+START   .FILL   x1234
+        .FILL   #-1
+        .FILL   b101
+        .FILL   65535
+        .FILL   START
+        .FILL   LATER
+LATER   .FILL   0
+.END
+";
+    let output = assembler::assemble(program.to_string());
+    assert_eq!(
+        Ok(vec![0x1234, 0xFFFF, 0x0005, 0xFFFF, 0x3000, 0x3006, 0x0000]),
+        output
+    );
+}
+
+#[test]
+fn fill_errors() {
+    for operand in ["", "x1 x2", "#65536", "#-32769", "#1x", "3LOOP"] {
+        let program = format!(".ORIG x3000\n.FILL {operand}\n.END\n");
+        assert!(
+            assembler::assemble(program).is_err(),
+            ".FILL {operand} should not assemble"
+        );
+    }
+    let program = "
+.ORIG   x3000
+.FILL   NOWHERE
+.END
+";
+    let output = assembler::assemble(program.to_string());
+    assert_eq!(
+        Err(assembler::AssemblerError::UndefinedLabel(
+            "NOWHERE".to_string()
+        )),
+        output.map_err(|errors| errors[0].error.clone())
+    );
+}
+
+#[test]
+fn blkw_with_and_without_fill() {
+    let program = "
+.ORIG   x3000
+        .BLKW   2
+        .BLKW   3 xFFFF
+        .BLKW   #1, AFTER
+AFTER   .FILL   x7
+.END
+";
+    let output = assembler::assemble(program.to_string());
+    assert_eq!(Ok(vec![0, 0, 0xFFFF, 0xFFFF, 0xFFFF, 0x3006, 0x7]), output);
+}
+
+#[test]
+fn blkw_errors() {
+    for operands in ["", "0", "#-1", "LOTS", "1 2 3", "2 #70000"] {
+        let program = format!(".ORIG x3000\n.BLKW {operands}\n.END\n");
+        assert!(
+            assembler::assemble(program).is_err(),
+            ".BLKW {operands} should not assemble"
+        );
+    }
+
+    // Only x1000 words are left after xF000
+    let program = ".ORIG xF000\n.BLKW x1000\n.END\n";
+    assert!(assembler::assemble(program.to_string()).is_ok());
+    let program = ".ORIG xF000\n.BLKW x1001\n.END\n";
+    assert!(assembler::assemble(program.to_string()).is_err());
+}
+
+#[test]
+fn stringz_with_escapes() {
+    let program = r#"
+.ORIG   x3000
+HELLO   .STRINGZ "Hi, you; \"A\\B\"\n\t"   ; comment with "quotes"
+        .STRINGZ ""
+        .FILL    HELLO
+.END
+"#;
+    let mut expected: Vec<u16> = "Hi, you; \"A\\B\"\n\t".bytes().map(u16::from).collect();
+    expected.extend([0, 0, 0x3000]);
+    let output = assembler::assemble(program.to_string());
+    assert_eq!(Ok(expected), output);
+}
+
+#[test]
+fn stringz_errors() {
+    for operand in [
+        "",
+        "Hello",
+        "\"Hello",
+        "\"Hello\\\"",
+        "\"a\" \"b\"",
+        "\"a\"b\"",
+        "\"\\q\"",
+        "\"caf\u{e9}\"",
+    ] {
+        let program = format!(".ORIG x3000\n.STRINGZ {operand}\n.END\n");
+        assert!(
+            matches!(
+                assembler::assemble(program).map_err(|errors| errors[0].error.clone()),
+                Err(assembler::AssemblerError::StringzUsage(_))
+            ),
+            ".STRINGZ {operand} should not assemble"
+        );
+    }
+}
+
+#[test]
+fn nothing_fits_after_xffff() {
+    for (program, token) in [
+        (".ORIG xFFFF\nHALT\nHALT\n.END\n", "HALT"),
+        (".ORIG xFFFF\n.FILL 1\n.FILL 2\n.END\n", ".FILL"),
+        (".ORIG x3000\n.BLKW xD000\n.FILL 1\n.END\n", ".FILL"),
+        (".ORIG x3000\n.BLKW xD000\n.BLKW 1\n.END\n", ".BLKW"),
+        (
+            ".ORIG x3000\n.BLKW xD000\n.STRINGZ \"\"\n.END\n",
+            ".STRINGZ",
+        ),
+    ] {
+        assert_eq!(
+            Err(assembler::AssemblerError::EndOfMemory(token.to_string())),
+            assembler::assemble(program.to_string()).map_err(|errors| errors[0].error.clone()),
+            "{program}"
+        );
+    }
+
+    // The last word of memory is still usable
+    let program = ".ORIG x3000\n.BLKW xCFFF\n.FILL 1\n.END\n";
+    assert!(assembler::assemble(program.to_string()).is_ok());
+}
+
+#[test]
+fn stringz_too_long() {
+    // With the terminator, x1000 characters need x1001 words after xF000
+    for length in [0x1000, 70_000] {
+        let program = format!(".ORIG xF000\n.STRINGZ \"{}\"\n.END\n", "a".repeat(length));
+        assert!(
+            matches!(
+                assembler::assemble(program).map_err(|errors| errors[0].error.clone()),
+                Err(assembler::AssemblerError::StringzUsage(_))
+            ),
+            "{length} characters should not fit"
+        );
+    }
+
+    let program = format!(".ORIG xF000\n.STRINGZ \"{}\"\n.END\n", "a".repeat(0x0FFF));
+    assert!(assembler::assemble(program).is_ok());
+}
+
+#[cfg(feature = "lc3b")]
+#[test]
+fn stringz_too_long_lc3b() {
+    // LC-3b words take two bytes, so only x800 words fit after xF000
+    let program = format!(
+        ".ORIG xF000\n.ISA LC3B\n.STRINGZ \"{}\"\n.END\n",
+        "a".repeat(40_000)
+    );
+    assert!(matches!(
+        assembler::assemble(program).map_err(|errors| errors[0].error.clone()),
+        Err(assembler::AssemblerError::StringzUsage(_))
+    ));
+}
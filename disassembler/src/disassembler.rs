@@ -1,3 +1,191 @@
-fn main() {
-    println!("Disassembler");
+use vm::{Instruction, Isa, Operand, Register, Symbols};
+
+/// Turns one instruction word at `address` back into assembly. Branch,
+/// load and store targets are shown as labels from `symbols`. Where no label
+/// is right at the target, the offset is written out instead, with the target
+/// in a comment.
+///
+/// ```
+/// use disassembler::disassemble;
+/// use vm::{Isa, Symbols};
+///
+/// let mut symbols = Symbols::new();
+/// symbols.insert("LOOP", 0x3000);
+///
+/// assert_eq!("BRp LOOP", disassemble(0x03FD, 0x3002, Isa::Lc3, &symbols));
+/// assert_eq!("ADD R1, R2, #-2", disassemble(0x12BE, 0x3003, Isa::Lc3, &symbols));
+/// assert_eq!("LD R0, #1 ; LOOP+5", disassemble(0x2001, 0x3003, Isa::Lc3, &symbols));
+/// ```
+pub fn disassemble(word: u16, address: u16, isa: Isa, symbols: &Symbols) -> String {
+    // The assembler takes a label or a number, so LABEL+3 or a bare address
+    // would not assemble again
+    let target = |offset: u16| {
+        let target = address.wrapping_add(isa.word_size()).wrapping_add(offset);
+        match symbols.lookup(target) {
+            Some((name, 0)) => name.to_string(),
+            _ => {
+                let words = offset as i16 / isa.word_size() as i16;
+                format!("#{words} ; {}", symbols.symbolize(target))
+            }
+        }
+    };
+    let operand = |operand: Operand| match operand {
+        Operand::Register(register) => format!("{register:?}"),
+        Operand::Immediate(value) => format!("#{}", value as i16),
+    };
+    let offset = |offset: u16| format!("#{}", offset as i16);
+
+    match isa.decode(word) {
+        // Without flags it never branches, but the assembler reads BR as BRnzp
+        Instruction::Br { flags: 0, .. } => format!(".FILL x{word:04X}"),
+        Instruction::Br { flags, offset } => {
+            let n = if flags & 0b100 != 0 { "n" } else { "" };
+            let z = if flags & 0b010 != 0 { "z" } else { "" };
+            let p = if flags & 0b001 != 0 { "p" } else { "" };
+            format!("BR{n}{z}{p} {}", target(offset))
+        }
+        Instruction::Add {
+            dr,
+            sr1,
+            operand: o,
+        } => format!("ADD {dr:?}, {sr1:?}, {}", operand(o)),
+        Instruction::And {
+            dr,
+            sr1,
+            operand: o,
+        } => format!("AND {dr:?}, {sr1:?}, {}", operand(o)),
+        Instruction::Not { dr, sr } => format!("NOT {dr:?}, {sr:?}"),
+        Instruction::Ld { dr, offset } => format!("LD {dr:?}, {}", target(offset)),
+        Instruction::Ldi { dr, offset } => format!("LDI {dr:?}, {}", target(offset)),
+        Instruction::Lea { dr, offset } => format!("LEA {dr:?}, {}", target(offset)),
+        Instruction::St { sr, offset } => format!("ST {sr:?}, {}", target(offset)),
+        Instruction::Sti { sr, offset } => format!("STI {sr:?}, {}", target(offset)),
+        Instruction::Ldr {
+            dr,
+            base,
+            offset: o,
+        } => {
+            let (mnemonic, o) = memory_offset(isa, "LDR", o);
+            format!("{mnemonic} {dr:?}, {base:?}, {}", offset(o))
+        }
+        Instruction::Str {
+            sr,
+            base,
+            offset: o,
+        } => {
+            let (mnemonic, o) = memory_offset(isa, "STR", o);
+            format!("{mnemonic} {sr:?}, {base:?}, {}", offset(o))
+        }
+        Instruction::Jsr { offset } => format!("JSR {}", target(offset)),
+        Instruction::Jsrr { base } => format!("JSRR {base:?}"),
+        Instruction::Jmp { base: Register::R7 } => "RET".to_string(),
+        Instruction::Jmp { base } => format!("JMP {base:?}"),
+        Instruction::Rti => "RTI".to_string(),
+        Instruction::Trap { vector } => match vector {
+            0x20 => "GETC".to_string(),
+            0x21 => "OUT".to_string(),
+            0x22 => "PUTS".to_string(),
+            0x23 => "IN".to_string(),
+            0x24 => "PUTSP".to_string(),
+            0x25 => "HALT".to_string(),
+            _ => format!("TRAP x{vector:02X}"),
+        },
+        Instruction::Res => format!(".FILL x{word:04X}"),
+        #[cfg(feature = "lc3b")]
+        Instruction::Ldb {
+            dr,
+            base,
+            offset: o,
+        } => format!("LDB {dr:?}, {base:?}, {}", offset(o)),
+        #[cfg(feature = "lc3b")]
+        Instruction::Stb {
+            sr,
+            base,
+            offset: o,
+        } => format!("STB {sr:?}, {base:?}, {}", offset(o)),
+        #[cfg(feature = "lc3b")]
+        Instruction::Xor {
+            dr,
+            sr1,
+            operand: o,
+        } => format!("XOR {dr:?}, {sr1:?}, {}", operand(o)),
+        #[cfg(feature = "lc3b")]
+        Instruction::Shf {
+            dr,
+            sr,
+            shift,
+            amount,
+        } => {
+            let mnemonic = match shift {
+                vm::Shift::Left => "LSHF",
+                vm::Shift::RightLogical => "RSHFL",
+                vm::Shift::RightArithmetic => "RSHFA",
+            };
+            format!("{mnemonic} {dr:?}, {sr:?}, #{amount}")
+        }
+        // LC-3b instructions, when another crate turns on vm's LC-3b and
+        // this crate's feature is off
+        #[allow(unreachable_patterns)]
+        _ => format!(".FILL x{word:04X}"),
+    }
+}
+
+/// LDR and STR are the LC-3b's LDW and STW, whose offsets are decoded in
+/// bytes but written in words
+fn memory_offset(isa: Isa, mnemonic: &'static str, offset: u16) -> (&'static str, u16) {
+    if isa == Isa::Lc3 {
+        return (mnemonic, offset);
+    }
+    let mnemonic = if mnemonic == "LDR" { "LDW" } else { "STW" };
+    (mnemonic, (offset as i16 / 2) as u16)
+}
+
+/// Disassembles `words` loaded at `origin`, one line per word with its
+/// address, the word in hex, the label at that address and the instruction
+///
+/// ```
+/// use disassembler::disassemble_program;
+/// use vm::{Isa, Symbols};
+///
+/// let mut symbols = Symbols::new();
+/// symbols.insert("MAIN", 0x3000);
+/// symbols.insert("DATA", 0x3002);
+/// assert_eq!(
+///     "x3000  x2201  MAIN  LD R1, DATA
+/// x3001  xF025        HALT
+/// x3002  x0005  DATA  .FILL x0005
+/// ",
+///     disassemble_program(0x3000, &[0x2201, 0xF025, 0x0005], Isa::Lc3, &symbols)
+/// );
+/// ```
+pub fn disassemble_program(origin: u16, words: &[u16], isa: Isa, symbols: &Symbols) -> String {
+    let width = symbols
+        .iter()
+        .map(|(_, name)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut output = String::new();
+    for (index, &word) in words.iter().enumerate() {
+        let address = origin.wrapping_add(index as u16 * isa.word_size());
+        let label = match symbols.lookup(address) {
+            Some((name, 0)) => name,
+            _ => "",
+        };
+        // A labelled word that isn't an instruction is most likely data
+        let text = match isa.decode(word) {
+            Instruction::Res => format!(".FILL x{word:04X}"),
+            _ if !label.is_empty() && is_data(word) => format!(".FILL x{word:04X}"),
+            _ => disassemble(word, address, isa, symbols),
+        };
+        let line = format!("x{address:04X}  x{word:04X}  {label:<width$}  {text}");
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// Small numbers decode as branches that never happen, which are far more
+/// often data than code
+fn is_data(word: u16) -> bool {
+    word >> 9 == 0
 }
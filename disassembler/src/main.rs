@@ -1 +1,59 @@
-fn main() {}
+use std::env;
+use std::fs;
+
+use vm::{Isa, Symbols};
+
+const USAGE: &str = "[--isa <lc3|lc3b>] [--origin <address>] [--sym <file.sym>] <file.obj>";
+
+struct Options {
+    file_path: String,
+    isa: Isa,
+    origin: u16,
+    symbols: Symbols,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Some(options) => options,
+        None => panic!("Usage: {} {USAGE}", args[0]),
+    };
+
+    let bytes = fs::read(&options.file_path).expect("Error reading file");
+    let words: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    print!(
+        "{}",
+        disassembler::disassemble_program(options.origin, &words, options.isa, &options.symbols)
+    );
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut file_path = None;
+    let mut options = Options {
+        file_path: String::new(),
+        isa: Isa::Lc3,
+        origin: 0x3000,
+        symbols: Symbols::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--isa" => options.isa = args.next()?.parse().ok()?,
+            "--origin" => {
+                let origin = args.next()?;
+                let digits = origin.strip_prefix(['x', 'X']).unwrap_or(origin);
+                options.origin = u16::from_str_radix(digits, 16).ok()?;
+            }
+            "--sym" => options.symbols = fs::read_to_string(args.next()?).ok()?.parse().ok()?,
+            _ if file_path.is_none() => file_path = Some(arg.clone()),
+            _ => return None,
+        }
+    }
+
+    options.file_path = file_path?;
+    Some(options)
+}
@@ -0,0 +1,144 @@
+use disassembler::{disassemble, disassemble_program};
+use vm::{Isa, Symbols};
+
+/// Every LC-3 instruction, with labels before and after the code that uses
+/// them
+const PROGRAM: &str = "
+        .ORIG   x3000
+MAIN    ADD     R1, R2, R3
+        ADD     R1, R2, #-16
+        AND     R7, R0, #15
+        NOT     R4, R5
+        BRn     MAIN
+        BRzp    DATA
+        BRnzp   MAIN
+        JMP     R2
+        RET
+        JSR     SUB
+        JSRR    R3
+        LD      R0, DATA
+        LDI     R1, DATA
+        LDR     R5, R6, #-32
+        LEA     R2, MAIN
+        ST      R3, DATA
+        STI     R4, DATA
+        STR     R7, R0, #31
+        RTI
+        GETC
+        OUT
+        PUTS
+        IN
+        PUTSP
+        TRAP    x30
+SUB     HALT
+DATA    ADD     R0, R0, #0
+        .FILL   x0000
+        .END
+";
+
+/// Disassembles the words of `PROGRAM` with `symbols` and assembles them again
+fn round_trip(symbols: &Symbols) {
+    let program = assembler::assemble_program(PROGRAM).unwrap();
+
+    let mut source = String::from(".ORIG x3000\n");
+    for (index, &word) in program.words.iter().enumerate() {
+        let address = 0x3000 + index as u16;
+        let label = match symbols.lookup(address) {
+            Some((name, 0)) => name,
+            _ => "",
+        };
+        let text = disassemble(word, address, Isa::Lc3, symbols);
+        source.push_str(&format!("{label} {text}\n"));
+    }
+    source.push_str(".END\n");
+
+    let reassembled = assembler::assemble_program(&source).unwrap();
+    assert_eq!(program.words, reassembled.words, "{source}");
+}
+
+#[test]
+fn disassembly_assembles_to_the_same_words() {
+    let program = assembler::assemble_program(PROGRAM).unwrap();
+    round_trip(&Symbols::from(&program.symbols));
+}
+
+#[test]
+fn targets_without_labels_assemble_to_the_same_words() {
+    round_trip(&Symbols::new());
+
+    // Every target after MAIN is MAIN+offset
+    let mut symbols = Symbols::new();
+    symbols.insert("MAIN", 0x3000);
+    round_trip(&symbols);
+}
+
+#[test]
+fn symbols_from_a_sym_file() {
+    let program = assembler::assemble_program(PROGRAM).unwrap();
+    let symbols: Symbols = program.symbols.sym_file().to_string().parse().unwrap();
+
+    let listing = disassemble_program(0x3000, &program.words[..6], Isa::Lc3, &symbols);
+    assert_eq!(
+        "\
+x3000  x1283  MAIN  ADD R1, R2, R3
+x3001  x12B0        ADD R1, R2, #-16
+x3002  x5E2F        AND R7, R0, #15
+x3003  x997F        NOT R4, R5
+x3004  x09FB        BRn MAIN
+x3005  x0614        BRzp DATA
+",
+        listing
+    );
+
+    // Without symbols, targets are offsets, with the address as a comment
+    assert_eq!(
+        "BRzp #20 ; x301A",
+        disassemble(0x0614, 0x3005, Isa::Lc3, &Symbols::new())
+    );
+    // Before the first label, and between labels
+    assert_eq!(
+        "LEA R2, #-2 ; x2FFF",
+        disassemble(0xE5FE, 0x3000, Isa::Lc3, &symbols)
+    );
+    assert_eq!(
+        "LD R0, #2 ; MAIN+3",
+        disassemble(0x2002, 0x3000, Isa::Lc3, &symbols)
+    );
+}
+
+#[test]
+fn data_and_reserved_words() {
+    let symbols = Symbols::new();
+    assert_eq!(
+        ".FILL x0000",
+        disassemble(0x0000, 0x3000, Isa::Lc3, &symbols)
+    );
+    assert_eq!(
+        ".FILL xD000",
+        disassemble(0xD000, 0x3000, Isa::Lc3, &symbols)
+    );
+}
+
+#[cfg(feature = "lc3b")]
+#[test]
+fn lc3b_offsets() {
+    let symbols = Symbols::new();
+    // LDW R3, R1, #1 is one word, two bytes, on
+    assert_eq!(
+        "LDW R3, R1, #1",
+        disassemble(0x6641, 0x3000, Isa::Lc3b, &symbols)
+    );
+    assert_eq!(
+        "LDB R2, R1, #-1",
+        disassemble(0x247F, 0x3000, Isa::Lc3b, &symbols)
+    );
+    assert_eq!(
+        "RSHFA R6, R4, #15",
+        disassemble(0xDD3F, 0x3000, Isa::Lc3b, &symbols)
+    );
+    // BR -1 goes back one word from the incremented PC
+    assert_eq!(
+        "BRnzp #-1 ; x3000",
+        disassemble(0x0FFF, 0x3000, Isa::Lc3b, &symbols)
+    );
+}
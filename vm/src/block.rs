@@ -0,0 +1,505 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::decode::{Instruction, Operand};
+use crate::instruction;
+use crate::memory::{Memory, MEMORY_SIZE};
+use crate::register::{PrivilegeMode, Register};
+use crate::vm::VirtualMachine;
+
+/// Longest run of instructions translated into a single block
+pub const MAX_BLOCK_LENGTH: u16 = 64;
+
+/// One translated instruction, with its operands and any address it uses
+/// worked out when the block was built. `condition` is false where the
+/// condition codes would be set again by a later instruction of the block
+/// before anything could see them.
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    AddImmediate {
+        dr: Register,
+        sr1: Register,
+        imm: u16,
+        condition: bool,
+    },
+    AddRegister {
+        dr: Register,
+        sr1: Register,
+        sr2: Register,
+        condition: bool,
+    },
+    AndImmediate {
+        dr: Register,
+        sr1: Register,
+        imm: u16,
+        condition: bool,
+    },
+    AndRegister {
+        dr: Register,
+        sr1: Register,
+        sr2: Register,
+        condition: bool,
+    },
+    Not {
+        dr: Register,
+        sr: Register,
+        condition: bool,
+    },
+    Lea {
+        dr: Register,
+        address: u16,
+    },
+    /// LD from memory that is not a device register. `privileged` addresses
+    /// fall back to the interpreter in user mode, to raise the exception.
+    Ld {
+        dr: Register,
+        address: u16,
+        privileged: bool,
+    },
+    St {
+        sr: Register,
+        address: u16,
+        privileged: bool,
+    },
+    /// BR, testing the condition codes of `result` rather than PSR when it
+    /// is the register the last instruction set them for
+    Br {
+        flags: u16,
+        target: u16,
+        result: Option<Register>,
+    },
+    /// Any other instruction, run by the interpreter
+    Synced(Instruction),
+}
+
+impl Op {
+    /// Sets the condition codes without reading them or leaving the block
+    fn only_sets_condition(self) -> bool {
+        matches!(
+            self,
+            Op::AddImmediate { .. }
+                | Op::AddRegister { .. }
+                | Op::AndImmediate { .. }
+                | Op::AndRegister { .. }
+                | Op::Not { .. }
+        )
+    }
+
+    /// The register an op that sets the condition codes sets them for
+    fn destination(self) -> Option<Register> {
+        match self {
+            Op::AddImmediate { dr, .. }
+            | Op::AddRegister { dr, .. }
+            | Op::AndImmediate { dr, .. }
+            | Op::AndRegister { dr, .. }
+            | Op::Not { dr, .. } => Some(dr),
+            _ => None,
+        }
+    }
+
+    fn without_condition(self) -> Op {
+        match self {
+            Op::AddImmediate { dr, sr1, imm, .. } => Op::AddImmediate {
+                dr,
+                sr1,
+                imm,
+                condition: false,
+            },
+            Op::AddRegister { dr, sr1, sr2, .. } => Op::AddRegister {
+                dr,
+                sr1,
+                sr2,
+                condition: false,
+            },
+            Op::AndImmediate { dr, sr1, imm, .. } => Op::AndImmediate {
+                dr,
+                sr1,
+                imm,
+                condition: false,
+            },
+            Op::AndRegister { dr, sr1, sr2, .. } => Op::AndRegister {
+                dr,
+                sr1,
+                sr2,
+                condition: false,
+            },
+            Op::Not { dr, sr, .. } => Op::Not {
+                dr,
+                sr,
+                condition: false,
+            },
+            op => op,
+        }
+    }
+}
+
+/// A straight-line run of instructions ending at BR, JMP, JSR, TRAP or RTI
+///
+/// Register-only instructions, LD and ST are specialized for their operands
+/// and don't touch PC or IR, which are only brought up to date when the block
+/// is left. Every other instruction, including any other last one in the
+/// block, first brings PC and IR up to date and then runs through the same
+/// handler as the interpreter, so the state observed after it is identical.
+pub(crate) struct Block {
+    start: u16,
+    ops: Vec<Op>,
+    /// The instruction word of each op, for IR
+    words: Vec<u16>,
+}
+
+impl Block {
+    pub(crate) fn len(&self) -> u64 {
+        self.ops.len() as u64
+    }
+
+    /// Runs the block and returns how many instructions were executed. A
+    /// block that branches back to its own start runs again, for as long as
+    /// it fits in `budget` instructions.
+    pub(crate) fn run(&self, vm: &mut VirtualMachine, budget: u64) -> u64 {
+        let mut executed = 0;
+        'block: loop {
+            executed += self.len();
+            for (index, op) in self.ops.iter().enumerate() {
+                match *op {
+                    Op::AddImmediate {
+                        dr,
+                        sr1,
+                        imm,
+                        condition,
+                    } => {
+                        let value = vm.registers.get(sr1).wrapping_add(imm);
+                        result(vm, dr, value, condition);
+                    }
+                    Op::AddRegister {
+                        dr,
+                        sr1,
+                        sr2,
+                        condition,
+                    } => {
+                        let value = vm.registers.get(sr1).wrapping_add(vm.registers.get(sr2));
+                        result(vm, dr, value, condition);
+                    }
+                    Op::AndImmediate {
+                        dr,
+                        sr1,
+                        imm,
+                        condition,
+                    } => {
+                        let value = vm.registers.get(sr1) & imm;
+                        result(vm, dr, value, condition);
+                    }
+                    Op::AndRegister {
+                        dr,
+                        sr1,
+                        sr2,
+                        condition,
+                    } => {
+                        let value = vm.registers.get(sr1) & vm.registers.get(sr2);
+                        result(vm, dr, value, condition);
+                    }
+                    Op::Not { dr, sr, condition } => {
+                        let value = !vm.registers.get(sr);
+                        result(vm, dr, value, condition);
+                    }
+                    Op::Lea { dr, address } => vm.registers.set(dr, address),
+                    Op::Ld { privileged, .. } | Op::St { privileged, .. }
+                        if privileged && vm.get_mode() == PrivilegeMode::User =>
+                    {
+                        let instruction = Instruction::decode(self.words[index]);
+                        if !self.synced(vm, index, instruction) {
+                            return executed - self.after(index);
+                        }
+                    }
+                    Op::Ld { dr, address, .. } => {
+                        let value = vm.read(address);
+                        vm.registers.set(dr, value);
+                        vm.registers.set_condition_codes(dr);
+                    }
+                    Op::St { sr, address, .. } => {
+                        let value = vm.registers.get(sr);
+                        vm.write(address, value);
+                        // The rest of the block may be what was just overwritten
+                        if vm.memory.has_invalidated() {
+                            self.leave(vm, index);
+                            return executed - self.after(index);
+                        }
+                    }
+                    Op::Br {
+                        flags,
+                        target,
+                        result: deferred,
+                    } => {
+                        let taken = match deferred {
+                            Some(dr) => flags & condition(vm.registers.get(dr)) != 0,
+                            None => flags & vm.registers.get(Register::PSR) != 0,
+                        };
+                        if taken && target == self.start && executed + self.len() <= budget {
+                            continue 'block;
+                        }
+                        if let Some(dr) = deferred {
+                            vm.registers.set_condition_codes(dr);
+                        }
+                        self.leave(vm, index);
+                        if taken {
+                            vm.registers.set(Register::PC, target);
+                        }
+                        return executed;
+                    }
+                    Op::Synced(instruction) => {
+                        if !self.synced(vm, index, instruction) {
+                            return executed - self.after(index);
+                        }
+                    }
+                }
+            }
+            // Only a block that was cut short ends in anything but BR, and
+            // its last op ran through the interpreter
+            return executed;
+        }
+    }
+
+    /// Sets PC and IR as if the op at `index` had just been fetched
+    fn leave(&self, vm: &mut VirtualMachine, index: usize) {
+        let next = self.start.wrapping_add(index as u16 + 1);
+        vm.registers.set(Register::PC, next);
+        vm.registers.set(Register::IR, self.words[index]);
+    }
+
+    /// Runs the op at `index` through the interpreter. Returns false to leave
+    /// the block if the instruction halted the machine, wrote to translated
+    /// code or jumped.
+    fn synced(&self, vm: &mut VirtualMachine, index: usize, instruction: Instruction) -> bool {
+        self.leave(vm, index);
+        let next = vm.registers.get(Register::PC);
+        instruction::execute_decoded(vm, instruction);
+        vm.check_machine_control();
+
+        vm.halt_reason().is_none()
+            && !vm.memory.has_invalidated()
+            && vm.registers.get(Register::PC) == next
+    }
+
+    /// Ops of the block after the one at `index`
+    fn after(&self, index: usize) -> u64 {
+        self.len() - index as u64 - 1
+    }
+}
+
+/// The condition code flag for `value`, as PSR\[2:0\] would have it
+fn condition(value: u16) -> u16 {
+    if value == 0 {
+        0b010
+    } else if value >> 15 != 0 {
+        0b100
+    } else {
+        0b001
+    }
+}
+
+/// Writes the result of an ALU op, and its condition codes if they are seen
+fn result(vm: &mut VirtualMachine, dr: Register, value: u16, condition: bool) {
+    vm.registers.set(dr, value);
+    if condition {
+        vm.registers.set_condition_codes(dr);
+    }
+}
+
+/// Translated blocks by start address
+pub struct BlockEngine {
+    blocks: Box<[Option<Box<Block>>]>,
+}
+
+impl BlockEngine {
+    pub fn new() -> Self {
+        Self {
+            blocks: (0..MEMORY_SIZE).map(|_| None).collect(),
+        }
+    }
+
+    /// Returns the block starting at `address`, translating it if needed.
+    /// Device registers are never translated since reading them has side effects.
+    pub(crate) fn get(&mut self, memory: &mut Memory, address: u16) -> Option<&Block> {
+        if memory.is_device(address) {
+            return None;
+        }
+
+        if memory.has_invalidated() {
+            for invalidated in memory.take_invalidated() {
+                self.invalidate(invalidated);
+            }
+        }
+
+        let block = self.blocks[address as usize]
+            .get_or_insert_with(|| Box::new(translate(memory, address)));
+        Some(block)
+    }
+
+    /// Drops every block that contains `address`
+    fn invalidate(&mut self, address: u16) {
+        let first = address.saturating_sub(MAX_BLOCK_LENGTH - 1);
+        for start in first..=address {
+            let covers = self.blocks[start as usize]
+                .as_ref()
+                .is_some_and(|block| start as u64 + block.len() > address as u64);
+            if covers {
+                self.blocks[start as usize] = None;
+            }
+        }
+    }
+}
+
+impl Default for BlockEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn translate(memory: &mut Memory, start: u16) -> Block {
+    let mut ops = Vec::new();
+    let mut words = Vec::new();
+    let mut address = start;
+
+    loop {
+        let word = memory.read(address);
+        memory.mark_translated(address);
+
+        let decoded = Instruction::decode(word);
+        let next = address.wrapping_add(1);
+        let last = is_terminator(decoded)
+            || ops.len() as u16 + 1 == MAX_BLOCK_LENGTH
+            || memory.is_device(next);
+
+        ops.push(match decoded {
+            Instruction::Br { flags, offset } => Op::Br {
+                flags,
+                target: next.wrapping_add(offset),
+                result: None,
+            },
+            _ if last => Op::Synced(decoded),
+            _ => translate_instruction(memory, decoded, next),
+        });
+        words.push(word);
+
+        if last {
+            break;
+        }
+        address = next;
+    }
+
+    // Where nothing but register instructions come before BR, nothing can see
+    // the condition codes until the branch, which works them out from the
+    // last result itself and only sets them when it leaves the block
+    let register_only = |op: &Op| op.only_sets_condition() || matches!(op, Op::Lea { .. });
+    if let [body @ .., last, Op::Br { result, .. }] = ops.as_mut_slice() {
+        if body.iter().all(register_only) {
+            if let Some(dr) = last.destination() {
+                *result = Some(dr);
+                for op in body.iter_mut().chain([last]) {
+                    *op = op.without_condition();
+                }
+            }
+        }
+    }
+
+    for index in 0..ops.len() {
+        let overwritten = ops[index + 1..]
+            .iter()
+            .find(|op| !matches!(op, Op::Lea { .. }))
+            .is_some_and(|op| op.only_sets_condition());
+        if ops[index].only_sets_condition() && overwritten {
+            ops[index] = ops[index].without_condition();
+        }
+    }
+
+    Block { start, ops, words }
+}
+
+fn is_terminator(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Br { .. }
+            | Instruction::Jmp { .. }
+            | Instruction::Jsr { .. }
+            | Instruction::Jsrr { .. }
+            | Instruction::Trap { .. }
+            | Instruction::Rti
+            | Instruction::Res
+    )
+}
+
+fn translate_instruction(memory: &Memory, instruction: Instruction, next: u16) -> Op {
+    match instruction {
+        Instruction::Add {
+            dr,
+            sr1,
+            operand: Operand::Immediate(imm),
+        } => Op::AddImmediate {
+            dr,
+            sr1,
+            imm,
+            condition: true,
+        },
+        Instruction::Add {
+            dr,
+            sr1,
+            operand: Operand::Register(sr2),
+        } => Op::AddRegister {
+            dr,
+            sr1,
+            sr2,
+            condition: true,
+        },
+        Instruction::And {
+            dr,
+            sr1,
+            operand: Operand::Immediate(imm),
+        } => Op::AndImmediate {
+            dr,
+            sr1,
+            imm,
+            condition: true,
+        },
+        Instruction::And {
+            dr,
+            sr1,
+            operand: Operand::Register(sr2),
+        } => Op::AndRegister {
+            dr,
+            sr1,
+            sr2,
+            condition: true,
+        },
+        Instruction::Not { dr, sr } => Op::Not {
+            dr,
+            sr,
+            condition: true,
+        },
+        Instruction::Lea { dr, offset } => Op::Lea {
+            dr,
+            address: next.wrapping_add(offset),
+        },
+        Instruction::Ld { dr, offset } => {
+            let address = next.wrapping_add(offset);
+            if memory.is_device(address) {
+                return Op::Synced(instruction);
+            }
+            Op::Ld {
+                dr,
+                address,
+                privileged: memory.is_privileged(address),
+            }
+        }
+        Instruction::St { sr, offset } => {
+            let address = next.wrapping_add(offset);
+            if memory.is_device(address) {
+                return Op::Synced(instruction);
+            }
+            Op::St {
+                sr,
+                address,
+                privileged: memory.is_privileged(address),
+            }
+        }
+        _ => Op::Synced(instruction),
+    }
+}
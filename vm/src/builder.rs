@@ -0,0 +1,294 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::cache::Cache;
+use crate::console::{default_console, Console};
+use crate::isa::Isa;
+use crate::memory::{Device, Memory, KBSR};
+use crate::observer::Observer;
+use crate::profiler::Profiler;
+use crate::register::{PrivilegeMode, Register};
+use crate::replay::{Clock, InputLog, InputRecorder, RecordingConsole, ReplayConsole};
+use crate::vm::VirtualMachine;
+
+/// How memory is initialized before the OS image and programs are loaded.
+/// The trap and interrupt vector tables are always zeroed, so that vectors
+/// without an OS routine fall back to the built-in behavior.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryInit {
+    /// Every word is zero
+    Zero,
+    /// Every word holds the given value
+    Fill(u16),
+    /// Every word holds a pseudo-random value generated from the given seed.
+    /// Useful for catching programs that read memory they never wrote.
+    Random(u32),
+}
+
+/// Configures and creates a `VirtualMachine`
+///
+/// ```
+/// use vm::{HaltReason, PrivilegeMode, Register, ScriptedConsole, VirtualMachine};
+///
+/// // GETC; OUT; HALT
+/// let console = ScriptedConsole::new(b"x");
+/// let output = console.output();
+/// let mut vm = VirtualMachine::builder()
+///     .program(0x4000, &[0xF020, 0xF021, 0xF025])
+///     .mode(PrivilegeMode::Privileged)
+///     .console(Box::new(console))
+///     .instruction_limit(100)
+///     .build();
+///
+/// assert_eq!(HaltReason::Halt, vm.run());
+/// assert_eq!(b"x", output.borrow().as_slice());
+/// assert_eq!(0x4003, vm.registers().get(Register::PC));
+/// ```
+pub struct VirtualMachineBuilder {
+    entry_point: u16,
+    psr: u16,
+    isa: Isa,
+    console: Option<Box<dyn Console>>,
+    devices: Vec<Box<dyn Device>>,
+    observers: Vec<Box<dyn Observer>>,
+    recorder: Option<InputRecorder>,
+    replay: Option<InputLog>,
+    images: Vec<(u16, Vec<u16>)>,
+    memory_init: MemoryInit,
+    instruction_limit: Option<u64>,
+    decode_cache: bool,
+    block_engine: bool,
+    instruction_cache: Option<Cache>,
+    data_cache: Option<Cache>,
+    breakpoints: Vec<u16>,
+}
+
+impl VirtualMachineBuilder {
+    /// Same defaults as `VirtualMachine::new`: user mode at x3000 with zeroed
+    /// memory and the console on stdin and stdout
+    pub fn new() -> Self {
+        Self {
+            entry_point: 0x3000,
+            psr: 0x8002,
+            isa: Isa::Lc3,
+            console: None,
+            devices: Vec::new(),
+            observers: Vec::new(),
+            recorder: None,
+            replay: None,
+            images: Vec::new(),
+            memory_init: MemoryInit::Zero,
+            instruction_limit: None,
+            decode_cache: false,
+            block_engine: false,
+            instruction_cache: None,
+            data_cache: None,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    /// Initial PC
+    pub fn entry_point(mut self, address: u16) -> Self {
+        self.entry_point = address;
+        self
+    }
+
+    /// Initial PSR, including privilege mode, priority and condition codes
+    pub fn psr(mut self, psr: u16) -> Self {
+        self.psr = psr;
+        self
+    }
+
+    /// Privilege mode to start in. Programs that use the device registers
+    /// directly have to run in supervisor mode.
+    pub fn mode(mut self, mode: PrivilegeMode) -> Self {
+        self.psr = (self.psr & 0x7FFF) | ((mode as u16) << 15);
+        self
+    }
+
+    /// Priority level to start at. Only the low three bits are used.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.psr = (self.psr & !0x0700) | (((priority & 0x7) as u16) << 8);
+        self
+    }
+
+    /// Instruction set to execute. Programs and images are loaded one word
+    /// of the instruction set apart.
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    /// Host side of the keyboard and display
+    pub fn console(mut self, console: Box<dyn Console>) -> Self {
+        self.console = Some(console);
+        self
+    }
+
+    /// Attaches a memory-mapped device
+    pub fn device(mut self, device: Box<dyn Device>) -> Self {
+        self.devices.push(device);
+        self
+    }
+
+    /// Registers an execution observer
+    pub fn observer(mut self, observer: Box<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Counts the instructions executed at each address into `profiler`.
+    /// Like any observer, this turns off the block engine.
+    pub fn profile(self, profiler: &Profiler) -> Self {
+        self.observer(profiler.observer())
+    }
+
+    /// Records every byte of input the program consumes into `recorder`.
+    /// Like any observer, this turns off the block engine.
+    pub fn record_input(mut self, recorder: &InputRecorder) -> Self {
+        self.recorder = Some(recorder.clone());
+        self
+    }
+
+    /// Takes input from a recorded log instead of the console, delivering each
+    /// byte at the instruction it was originally consumed at. The console still
+    /// receives the output.
+    pub fn replay_input(mut self, log: InputLog) -> Self {
+        self.replay = Some(log);
+        self
+    }
+
+    /// Operating system image loaded at x0000, starting with the trap and
+    /// interrupt vector tables
+    pub fn os_image(self, image: &[u16]) -> Self {
+        self.load(0x0000, image)
+    }
+
+    /// Loads `words` at `origin`. Later images overwrite earlier ones.
+    pub fn load(mut self, origin: u16, words: &[u16]) -> Self {
+        self.images.push((origin, words.to_vec()));
+        self
+    }
+
+    /// Loads a program at `origin` and starts executing there
+    pub fn program(self, origin: u16, words: &[u16]) -> Self {
+        self.load(origin, words).entry_point(origin)
+    }
+
+    pub fn memory_init(mut self, memory_init: MemoryInit) -> Self {
+        self.memory_init = memory_init;
+        self
+    }
+
+    /// Stops `run` with `HaltReason::InstructionLimit` after `limit` instructions
+    pub fn instruction_limit(mut self, limit: u64) -> Self {
+        self.instruction_limit = Some(limit);
+        self
+    }
+
+    /// Enables the decoded instruction cache
+    pub fn decode_cache(mut self) -> Self {
+        self.decode_cache = true;
+        self
+    }
+
+    /// Enables the basic-block translation engine
+    pub fn block_engine(mut self) -> Self {
+        self.block_engine = true;
+        self
+    }
+
+    /// Simulates `cache` on instruction fetches
+    pub fn instruction_cache(mut self, cache: Cache) -> Self {
+        self.instruction_cache = Some(cache);
+        self
+    }
+
+    /// Simulates `cache` on data reads and writes
+    pub fn data_cache(mut self, cache: Cache) -> Self {
+        self.data_cache = Some(cache);
+        self
+    }
+
+    /// Stops `run` with `HaltReason::Breakpoint` before the instruction at
+    /// `address` executes
+    pub fn breakpoint(mut self, address: u16) -> Self {
+        self.breakpoints.push(address);
+        self
+    }
+
+    pub fn build(self) -> VirtualMachine {
+        let mut console = self.console.unwrap_or_else(default_console);
+        let clock = Clock::default();
+        let timed = self.replay.is_some() || self.recorder.is_some();
+        if let Some(log) = self.replay {
+            console = Box::new(ReplayConsole::new(log, clock.clone(), console));
+        }
+        if let Some(recorder) = &self.recorder {
+            console = Box::new(RecordingConsole::new(console, clock.clone(), recorder));
+        }
+        let mut memory = Memory::with_console(console);
+        memory.set_isa(self.isa);
+        // Before attaching devices, so that only RAM is filled
+        initialize(&mut memory, self.memory_init);
+        for device in self.devices {
+            memory.attach(device);
+        }
+        for (origin, words) in &self.images {
+            memory.load(*origin, words);
+        }
+        if self.decode_cache {
+            memory.enable_decode_cache();
+        }
+        // After loading, so that the program image isn't counted
+        memory.set_instruction_cache(self.instruction_cache);
+        memory.set_data_cache(self.data_cache);
+
+        let mut vm = VirtualMachine::with_memory(memory);
+        vm.set_mode(PrivilegeMode::from_psr(self.psr));
+        vm.registers_mut().set(Register::PSR, self.psr);
+        vm.registers_mut().set(Register::PC, self.entry_point);
+        vm.set_instruction_limit(self.instruction_limit);
+        if self.block_engine {
+            vm.enable_block_engine();
+        }
+        for address in self.breakpoints {
+            vm.set_breakpoint(address);
+        }
+        if timed {
+            vm.add_observer(Box::new(clock));
+        }
+        for observer in self.observers {
+            vm.add_observer(observer);
+        }
+        vm
+    }
+}
+
+impl Default for VirtualMachineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn initialize(memory: &mut Memory, memory_init: MemoryInit) {
+    let mut seed = match memory_init {
+        MemoryInit::Zero => return,
+        MemoryInit::Random(seed) => seed.max(1),
+        MemoryInit::Fill(_) => 0,
+    };
+
+    for address in memory.isa().vector_tables_end()..KBSR {
+        let value = match memory_init {
+            MemoryInit::Fill(value) => value,
+            _ => {
+                // xorshift32
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                (seed >> 8) as u16
+            }
+        };
+        memory.write(address, value);
+    }
+}
@@ -0,0 +1,358 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+
+/// What happens on a write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Writes allocate a line and mark it dirty. Dirty lines are written back
+    /// to memory when they are evicted.
+    WriteBack,
+    /// Writes always go to memory and only update lines that are already
+    /// cached. A write miss does not allocate.
+    WriteThrough,
+}
+
+/// Which line of a full set is evicted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Replacement {
+    /// Least recently used
+    Lru,
+    /// Oldest line first
+    Fifo,
+    /// Pseudo-random, from the given seed
+    Random(u32),
+}
+
+/// Shape and policies of a cache. Sizes are in words and must be powers of
+/// two.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Total words the cache holds
+    pub size: usize,
+    /// Lines per set. A cache with as many ways as lines is fully associative.
+    pub ways: usize,
+    /// Words per line
+    pub line_size: usize,
+    pub write_policy: WritePolicy,
+    pub replacement: Replacement,
+}
+
+impl CacheConfig {
+    /// A write-back LRU cache
+    pub fn new(size: usize, ways: usize, line_size: usize) -> Self {
+        Self {
+            size,
+            ways,
+            line_size,
+            write_policy: WritePolicy::WriteBack,
+            replacement: Replacement::Lru,
+        }
+    }
+
+    fn sets(&self) -> usize {
+        self.size / (self.ways * self.line_size)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CacheConfigError(pub String);
+
+impl fmt::Display for CacheConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid cache configuration: {}", self.0)
+    }
+}
+
+impl FromStr for CacheConfig {
+    type Err = CacheConfigError;
+
+    /// Parses a comma separated list of settings, for example
+    /// `size=64,ways=2,line=4,write=through,replace=fifo`. `size` is required,
+    /// the rest default to one way, one word lines, write-back and LRU.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |message: &str| CacheConfigError(String::from(message));
+        let number = |value: &str| value.parse::<usize>().map_err(|_| error(value));
+
+        let mut size = None;
+        let mut config = CacheConfig::new(0, 1, 1);
+        for setting in s.split(',') {
+            let (key, value) = setting.split_once('=').ok_or_else(|| error(setting))?;
+            match key.trim() {
+                "size" => size = Some(number(value)?),
+                "ways" => config.ways = number(value)?,
+                "line" => config.line_size = number(value)?,
+                "write" => {
+                    config.write_policy = match value {
+                        "back" => WritePolicy::WriteBack,
+                        "through" => WritePolicy::WriteThrough,
+                        _ => return Err(error(value)),
+                    }
+                }
+                "replace" => {
+                    config.replacement = match value {
+                        "lru" => Replacement::Lru,
+                        "fifo" => Replacement::Fifo,
+                        "random" => Replacement::Random(1),
+                        _ => return Err(error(value)),
+                    }
+                }
+                _ => return Err(error(key)),
+            }
+        }
+        config.size = size.ok_or_else(|| error("missing size"))?;
+        Cache::check(&config)?;
+        Ok(config)
+    }
+}
+
+/// Counts for the accesses to part of memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Valid lines replaced to make room for a miss
+    pub evictions: u64,
+    /// Evicted lines that were dirty and had to be written to memory
+    pub write_backs: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// Fraction of accesses that hit, or zero without accesses
+    pub fn hit_rate(&self) -> f64 {
+        match self.accesses() {
+            0 => 0.0,
+            accesses => self.hits as f64 / accesses as f64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: usize,
+    /// Time of the last use for LRU, of the fill for FIFO
+    stamp: u64,
+}
+
+/// A named range of addresses that statistics are kept for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheRegion {
+    pub name: String,
+    pub addresses: RangeInclusive<u16>,
+    pub stats: CacheStats,
+}
+
+/// Cache model that counts hits, misses and evictions. It only keeps tags,
+/// memory always has the data, so a program runs the same with or without it.
+///
+/// Statistics are kept for all accesses and for each region. Evictions are
+/// counted in the region of the access that caused them.
+///
+/// ```
+/// use vm::{Cache, CacheConfig};
+///
+/// // Two lines of four words, direct mapped
+/// let mut cache = Cache::new(CacheConfig::new(8, 1, 4)).region("array", 0x4000..=0x40FF);
+/// for address in 0x4000..0x4008 {
+///     cache.read(address);
+/// }
+/// let array = &cache.regions()[0].stats;
+/// assert_eq!((6, 2), (array.hits, array.misses));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cache {
+    config: CacheConfig,
+    /// `ways` lines for every set
+    lines: Vec<Line>,
+    clock: u64,
+    random: u32,
+    total: CacheStats,
+    regions: Vec<CacheRegion>,
+}
+
+impl Cache {
+    /// # Panics
+    ///
+    /// If a size is not a power of two or the lines don't fit the cache
+    pub fn new(config: CacheConfig) -> Self {
+        if let Err(error) = Self::check(&config) {
+            panic!("{error}");
+        }
+        Self {
+            lines: vec![Line::default(); config.size / config.line_size],
+            clock: 0,
+            random: match config.replacement {
+                Replacement::Random(seed) => seed.max(1),
+                _ => 1,
+            },
+            config,
+            total: CacheStats::default(),
+            regions: Vec::new(),
+        }
+    }
+
+    fn check(config: &CacheConfig) -> Result<(), CacheConfigError> {
+        let sizes = [config.size, config.ways, config.line_size];
+        if sizes.iter().any(|size| !size.is_power_of_two()) {
+            return Err(CacheConfigError(String::from(
+                "sizes must be powers of two",
+            )));
+        }
+        if config.ways * config.line_size > config.size {
+            return Err(CacheConfigError(String::from(
+                "a set is larger than the cache",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Also keeps statistics for the accesses to `addresses`
+    pub fn region(mut self, name: &str, addresses: RangeInclusive<u16>) -> Self {
+        self.regions.push(CacheRegion {
+            name: String::from(name),
+            addresses,
+            stats: CacheStats::default(),
+        });
+        self
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Statistics for every access
+    pub fn total(&self) -> &CacheStats {
+        &self.total
+    }
+
+    pub fn regions(&self) -> &[CacheRegion] {
+        &self.regions
+    }
+
+    pub fn read(&mut self, address: u16) {
+        self.access(address, false);
+    }
+
+    pub fn write(&mut self, address: u16) {
+        self.access(address, true);
+    }
+
+    fn access(&mut self, address: u16, write: bool) {
+        self.clock += 1;
+        let line = address as usize / self.config.line_size;
+        let sets = self.config.sets();
+        let tag = line / sets;
+        let first = (line % sets) * self.config.ways;
+        let set = first..first + self.config.ways;
+
+        let mut outcome = CacheStats::default();
+        let hit = self.lines[set.clone()]
+            .iter()
+            .position(|line| line.valid && line.tag == tag);
+        match hit {
+            Some(way) => {
+                outcome.hits = 1;
+                let line = &mut self.lines[first + way];
+                if self.config.replacement == Replacement::Lru {
+                    line.stamp = self.clock;
+                }
+                line.dirty |= write && self.config.write_policy == WritePolicy::WriteBack;
+            }
+            None => {
+                outcome.misses = 1;
+                if !(write && self.config.write_policy == WritePolicy::WriteThrough) {
+                    let victim = first + self.victim(set);
+                    let line = &mut self.lines[victim];
+                    outcome.evictions = line.valid as u64;
+                    outcome.write_backs = (line.valid && line.dirty) as u64;
+                    *line = Line {
+                        valid: true,
+                        dirty: write,
+                        tag,
+                        stamp: self.clock,
+                    };
+                }
+            }
+        }
+
+        add(&mut self.total, outcome);
+        for region in &mut self.regions {
+            if region.addresses.contains(&address) {
+                add(&mut region.stats, outcome);
+            }
+        }
+    }
+
+    /// Way of the line to replace in `set`, an invalid one if there is one
+    fn victim(&mut self, set: core::ops::Range<usize>) -> usize {
+        let lines = &self.lines[set];
+        if let Some(way) = lines.iter().position(|line| !line.valid) {
+            return way;
+        }
+        match self.config.replacement {
+            Replacement::Random(_) => {
+                // xorshift32
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 17;
+                self.random ^= self.random << 5;
+                self.random as usize % lines.len()
+            }
+            _ => {
+                let oldest = lines.iter().enumerate().min_by_key(|(_, line)| line.stamp);
+                oldest.map_or(0, |(way, _)| way)
+            }
+        }
+    }
+}
+
+fn add(stats: &mut CacheStats, outcome: CacheStats) {
+    stats.hits += outcome.hits;
+    stats.misses += outcome.misses;
+    stats.evictions += outcome.evictions;
+    stats.write_backs += outcome.write_backs;
+}
+
+impl fmt::Display for Cache {
+    /// Table of the statistics for all accesses and each region
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let config = &self.config;
+        writeln!(
+            f,
+            "{} words, {}-way, {} words per line, {:?}, {:?}",
+            config.size, config.ways, config.line_size, config.write_policy, config.replacement
+        )?;
+        writeln!(
+            f,
+            "{:<12} {:>9} {:>9} {:>9} {:>9} {:>11} {:>8}",
+            "region", "accesses", "hits", "misses", "evictions", "write-backs", "hit rate"
+        )?;
+        let total = ("all", &self.total);
+        let regions = self
+            .regions
+            .iter()
+            .map(|region| (region.name.as_str(), &region.stats));
+        for (name, stats) in core::iter::once(total).chain(regions) {
+            writeln!(
+                f,
+                "{:<12} {:>9} {:>9} {:>9} {:>9} {:>11} {:>7.1}%",
+                name,
+                stats.accesses(),
+                stats.hits,
+                stats.misses,
+                stats.evictions,
+                stats.write_backs,
+                stats.hit_rate() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::symbols::Symbols;
+use crate::vm::Exception;
+
+/// Frames kept before the oldest ones are dropped, so that JSR used as a jump
+/// doesn't grow the stack without bound
+pub const MAX_CALL_DEPTH: usize = 4096;
+
+/// How a routine was entered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entry {
+    /// JSR or JSRR
+    Call,
+    /// TRAP with a service routine
+    Trap(u8),
+    /// An exception with a service routine
+    Exception(Exception),
+    /// A device interrupt, by its vector
+    Interrupt(u8),
+}
+
+/// A routine that has been entered and not yet returned from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    pub entry: Entry,
+    /// The calling instruction, the one that raised the exception, or the one
+    /// an interrupt was taken before
+    pub site: u16,
+    /// Address of the routine
+    pub routine: u16,
+    /// Where RET or RTI goes back to
+    pub return_address: u16,
+}
+
+/// Shadow stack of the routines the program is in, kept by the machine as it
+/// executes JSR, JSRR, RET, TRAP, RTI and takes interrupts. It doesn't look
+/// at the program's own stack, so it stays right when R6 doesn't.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallStack {
+    frames: VecDeque<StackFrame>,
+    truncated: bool,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames from the outermost to the innermost
+    pub fn frames(&self) -> impl DoubleEndedIterator<Item = &StackFrame> {
+        self.frames.iter()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// True if outer frames were dropped to stay within `MAX_CALL_DEPTH`
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub(crate) fn push(&mut self, frame: StackFrame) {
+        if self.frames.len() == MAX_CALL_DEPTH {
+            self.frames.pop_front();
+            self.truncated = true;
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// RET to `address` leaves the innermost call that returns there, and any
+    /// calls made from it that never returned. A RET that matches no call in
+    /// the current service routine is a computed jump and leaves the stack.
+    pub(crate) fn ret(&mut self, address: u16) {
+        let calls = self
+            .frames
+            .iter()
+            .rev()
+            .take_while(|frame| frame.entry == Entry::Call);
+        if let Some(depth) = calls
+            .enumerate()
+            .find(|(_, frame)| frame.return_address == address)
+            .map(|(depth, _)| depth)
+        {
+            self.frames.truncate(self.frames.len() - depth - 1);
+        }
+    }
+
+    /// RTI leaves the innermost service routine, with any calls made from it
+    pub(crate) fn rti(&mut self) {
+        if let Some(index) = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.entry != Entry::Call)
+        {
+            self.frames.truncate(index);
+        }
+    }
+}
+
+/// The call stack at the point a machine stopped
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Backtrace {
+    /// Address of the instruction the machine is at
+    pub pc: u16,
+    /// Frames from the innermost to the outermost
+    pub frames: Vec<StackFrame>,
+    /// True if outer frames are missing
+    pub truncated: bool,
+}
+
+impl Backtrace {
+    /// Formats the backtrace with addresses shown as labels
+    ///
+    /// ```
+    /// use vm::{Symbols, VirtualMachine};
+    ///
+    /// // x3000: JSR #1; HALT; x3002: TRAP x30
+    /// let mut vm = VirtualMachine::builder()
+    ///     .program(0x3000, &[0x4801, 0xF025, 0xF030])
+    ///     .build();
+    /// vm.run();
+    ///
+    /// let mut symbols = Symbols::new();
+    /// symbols.insert("MAIN", 0x3000);
+    /// symbols.insert("PRINT", 0x3002);
+    /// assert_eq!(
+    ///     "#0 x3002 PRINT\n#1 x3000 MAIN, JSR to PRINT\n",
+    ///     vm.backtrace().display(&symbols).to_string()
+    /// );
+    /// ```
+    pub fn display<'a>(&'a self, symbols: &'a Symbols) -> impl fmt::Display + 'a {
+        DisplayBacktrace {
+            backtrace: self,
+            symbols,
+        }
+    }
+}
+
+struct DisplayBacktrace<'a> {
+    backtrace: &'a Backtrace,
+    symbols: &'a Symbols,
+}
+
+impl DisplayBacktrace<'_> {
+    fn location(&self, f: &mut fmt::Formatter, number: usize, address: u16) -> fmt::Result {
+        write!(f, "#{number} x{address:04X}")?;
+        if !self.symbols.is_empty() {
+            write!(f, " {}", self.symbols.symbolize(address))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DisplayBacktrace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.location(f, 0, self.backtrace.pc)?;
+        writeln!(f)?;
+        for (number, frame) in self.backtrace.frames.iter().enumerate() {
+            self.location(f, number + 1, frame.site)?;
+            let routine = self.symbols.symbolize(frame.routine);
+            match frame.entry {
+                Entry::Call => writeln!(f, ", JSR to {routine}")?,
+                Entry::Trap(vector) => writeln!(f, ", TRAP x{vector:02X} to {routine}")?,
+                Entry::Exception(exception) => writeln!(f, ", {exception:?} to {routine}")?,
+                Entry::Interrupt(vector) => writeln!(f, ", interrupt x{vector:02X} to {routine}")?,
+            }
+        }
+        if self.backtrace.truncated {
+            writeln!(f, "...")?;
+        }
+        Ok(())
+    }
+}
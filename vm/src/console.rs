@@ -0,0 +1,113 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+/// Host side of the keyboard and display devices
+pub trait Console {
+    /// Returns true if a key press is waiting to be read
+    fn poll(&mut self) -> bool;
+
+    /// Reads the next key press, blocking until one is available.
+    /// Returns `None` once the input is exhausted.
+    fn read(&mut self) -> Option<u8>;
+
+    /// Writes a character to the display
+    fn write(&mut self, byte: u8);
+
+    /// Flushes any buffered output
+    fn flush(&mut self) {}
+}
+
+/// Console used when none is given: stdin and stdout with `std`, otherwise a
+/// console with no input that discards its output
+pub(crate) fn default_console() -> Box<dyn Console> {
+    #[cfg(feature = "std")]
+    return Box::new(StdConsole::new());
+    #[cfg(not(feature = "std"))]
+    return Box::new(ScriptedConsole::default());
+}
+
+/// Console backed by the process's stdin and stdout
+///
+/// Input is line-buffered by the host, so polling blocks until a full line
+/// has been typed or stdin is closed.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct StdConsole {
+    pending: Option<u8>,
+}
+
+#[cfg(feature = "std")]
+impl StdConsole {
+    pub fn new() -> Self {
+        Self { pending: None }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Console for StdConsole {
+    fn poll(&mut self) -> bool {
+        if self.pending.is_none() {
+            let mut buffer = [0; 1];
+            if let Ok(1) = io::stdin().read(&mut buffer) {
+                self.pending = Some(buffer[0]);
+            }
+        }
+        self.pending.is_some()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.poll();
+        self.pending.take()
+    }
+
+    fn write(&mut self, byte: u8) {
+        let _ = io::stdout().write_all(&[byte]);
+    }
+
+    fn flush(&mut self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Console fed from a fixed input string that captures everything written to it
+///
+/// The captured output is shared, so it can still be read after the console
+/// has been handed to a `VirtualMachine`.
+#[derive(Default)]
+pub struct ScriptedConsole {
+    input: VecDeque<u8>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl ScriptedConsole {
+    pub fn new(input: &[u8]) -> Self {
+        Self {
+            input: input.iter().copied().collect(),
+            output: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Handle to the captured output
+    pub fn output(&self) -> Rc<RefCell<Vec<u8>>> {
+        Rc::clone(&self.output)
+    }
+}
+
+impl Console for ScriptedConsole {
+    fn poll(&mut self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.output.borrow_mut().push(byte);
+    }
+}
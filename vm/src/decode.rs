@@ -0,0 +1,406 @@
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::isa::Isa;
+use crate::memory::MEMORY_SIZE;
+use crate::register::Register;
+
+/// An instruction word split into its fields. Offsets and immediates are
+/// already sign-extended to 16 bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Br {
+        flags: u16,
+        offset: u16,
+    },
+    Add {
+        dr: Register,
+        sr1: Register,
+        operand: Operand,
+    },
+    Ld {
+        dr: Register,
+        offset: u16,
+    },
+    St {
+        sr: Register,
+        offset: u16,
+    },
+    Jsr {
+        offset: u16,
+    },
+    Jsrr {
+        base: Register,
+    },
+    And {
+        dr: Register,
+        sr1: Register,
+        operand: Operand,
+    },
+    Ldr {
+        dr: Register,
+        base: Register,
+        offset: u16,
+    },
+    Str {
+        sr: Register,
+        base: Register,
+        offset: u16,
+    },
+    Rti,
+    Not {
+        dr: Register,
+        sr: Register,
+    },
+    Ldi {
+        dr: Register,
+        offset: u16,
+    },
+    Sti {
+        sr: Register,
+        offset: u16,
+    },
+    Jmp {
+        base: Register,
+    },
+    Res,
+    Lea {
+        dr: Register,
+        offset: u16,
+    },
+    Trap {
+        vector: u8,
+    },
+    /// LC-3b load byte. The offset is in bytes.
+    #[cfg(feature = "lc3b")]
+    Ldb {
+        dr: Register,
+        base: Register,
+        offset: u16,
+    },
+    /// LC-3b store byte. The offset is in bytes.
+    #[cfg(feature = "lc3b")]
+    Stb {
+        sr: Register,
+        base: Register,
+        offset: u16,
+    },
+    /// LC-3b exclusive or, which also covers NOT as XOR with -1
+    #[cfg(feature = "lc3b")]
+    Xor {
+        dr: Register,
+        sr1: Register,
+        operand: Operand,
+    },
+    /// LC-3b shift
+    #[cfg(feature = "lc3b")]
+    Shf {
+        dr: Register,
+        sr: Register,
+        shift: Shift,
+        amount: u8,
+    },
+}
+
+/// Second source operand of ADD and AND
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Immediate(u16),
+}
+
+impl Instruction {
+    pub fn decode(instruction: u16) -> Self {
+        let dr = Register::gpr(instruction >> 9);
+        let sr1 = Register::gpr(instruction >> 6);
+        let offset6 = sign_extend(instruction & 0x3F, 6);
+        let offset9 = sign_extend(instruction & 0x1FF, 9);
+        let operand = if (instruction >> 5) & 0x1 == 1 {
+            Operand::Immediate(sign_extend(instruction & 0x1F, 5))
+        } else {
+            Operand::Register(Register::gpr(instruction))
+        };
+
+        match instruction >> 12 {
+            0 => Instruction::Br {
+                flags: (instruction >> 9) & 0x7,
+                offset: offset9,
+            },
+            1 => Instruction::Add { dr, sr1, operand },
+            2 => Instruction::Ld {
+                dr,
+                offset: offset9,
+            },
+            3 => Instruction::St {
+                sr: dr,
+                offset: offset9,
+            },
+            4 if (instruction >> 11) & 0x1 == 1 => Instruction::Jsr {
+                offset: sign_extend(instruction & 0x7FF, 11),
+            },
+            4 => Instruction::Jsrr { base: sr1 },
+            5 => Instruction::And { dr, sr1, operand },
+            6 => Instruction::Ldr {
+                dr,
+                base: sr1,
+                offset: offset6,
+            },
+            7 => Instruction::Str {
+                sr: dr,
+                base: sr1,
+                offset: offset6,
+            },
+            8 => Instruction::Rti,
+            9 => Instruction::Not { dr, sr: sr1 },
+            10 => Instruction::Ldi {
+                dr,
+                offset: offset9,
+            },
+            11 => Instruction::Sti {
+                sr: dr,
+                offset: offset9,
+            },
+            12 => Instruction::Jmp { base: sr1 },
+            13 => Instruction::Res,
+            14 => Instruction::Lea {
+                dr,
+                offset: offset9,
+            },
+            _ => Instruction::Trap {
+                vector: (instruction & 0xFF) as u8,
+            },
+        }
+    }
+
+    /// Decodes an LC-3b instruction. PC-relative offsets and the LDW/STW
+    /// offsets are scaled to bytes, so they are added to addresses unchanged.
+    #[cfg(feature = "lc3b")]
+    pub fn decode_lc3b(instruction: u16) -> Self {
+        let dr = Register::gpr(instruction >> 9);
+        let sr1 = Register::gpr(instruction >> 6);
+        let offset6 = sign_extend(instruction & 0x3F, 6);
+        let word_offset6 = offset6 << 1;
+        let word_offset9 = sign_extend(instruction & 0x1FF, 9) << 1;
+        let operand = if (instruction >> 5) & 0x1 == 1 {
+            Operand::Immediate(sign_extend(instruction & 0x1F, 5))
+        } else {
+            Operand::Register(Register::gpr(instruction))
+        };
+
+        match instruction >> 12 {
+            0 => Instruction::Br {
+                flags: (instruction >> 9) & 0x7,
+                offset: word_offset9,
+            },
+            2 => Instruction::Ldb {
+                dr,
+                base: sr1,
+                offset: offset6,
+            },
+            3 => Instruction::Stb {
+                sr: dr,
+                base: sr1,
+                offset: offset6,
+            },
+            4 if (instruction >> 11) & 0x1 == 1 => Instruction::Jsr {
+                offset: sign_extend(instruction & 0x7FF, 11) << 1,
+            },
+            6 => Instruction::Ldr {
+                dr,
+                base: sr1,
+                offset: word_offset6,
+            },
+            7 => Instruction::Str {
+                sr: dr,
+                base: sr1,
+                offset: word_offset6,
+            },
+            9 => Instruction::Xor { dr, sr1, operand },
+            10 | 11 => Instruction::Res,
+            13 => Instruction::Shf {
+                dr,
+                sr: sr1,
+                shift: match (instruction >> 4) & 0x3 {
+                    0b01 => Shift::RightLogical,
+                    0b11 => Shift::RightArithmetic,
+                    _ => Shift::Left,
+                },
+                amount: (instruction & 0xF) as u8,
+            },
+            14 => Instruction::Lea {
+                dr,
+                offset: word_offset9,
+            },
+            // ADD, JSRR, AND, RTI, JMP and TRAP are encoded as in the LC-3
+            _ => Instruction::decode(instruction),
+        }
+    }
+}
+
+/// Direction of an LC-3b shift, from bits \[5:4\]
+#[cfg(feature = "lc3b")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shift {
+    Left,
+    RightLogical,
+    RightArithmetic,
+}
+
+/// Decoded instructions by address, so that hot code is only decoded once.
+/// Entries are dropped whenever the word they were decoded from is written.
+pub struct DecodeCache {
+    entries: Box<[Option<(u16, Instruction)>]>,
+    isa: Isa,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self::with_isa(Isa::Lc3)
+    }
+
+    /// Cache for instructions of `isa`
+    pub fn with_isa(isa: Isa) -> Self {
+        Self {
+            entries: vec![None; MEMORY_SIZE].into_boxed_slice(),
+            isa,
+        }
+    }
+
+    /// Returns the cached instruction at `address`, decoding `read` on a miss
+    pub fn get(&mut self, address: u16, read: impl FnOnce() -> u16) -> (u16, Instruction) {
+        *self.entries[address as usize].get_or_insert_with(|| {
+            let instruction = read();
+            (instruction, self.isa.decode(instruction))
+        })
+    }
+
+    /// The cached instruction at `address`, without decoding on a miss
+    pub fn cached(&self, address: u16) -> Option<(u16, Instruction)> {
+        self.entries[address as usize]
+    }
+
+    pub fn invalidate(&mut self, address: u16) {
+        self.entries[address as usize] = None;
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn sign_extend(mut x: u16, bit_count: u8) -> u16 {
+    // bit_count is the original number of bits
+    // that this binary value has. We want to take that
+    // and transform it into a 16 bits value.
+
+    // Then check if it's different than zero,
+    // if it is, it's signed as 1 (negative)
+    // Meaning we have to pad with ones instead of zeroes
+    if (x >> (bit_count - 1)) & 0x1 != 0 {
+        x |= 0xFFFF << bit_count;
+    }
+
+    // If it's positive, return as is, it will be padded
+    // with zeroes.
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sign_extend_positive() {
+        let x: u16 = 3;
+        let value = sign_extend(x, 5);
+        assert_eq!(3, value);
+    }
+
+    #[test]
+    fn sign_extend_negative() {
+        let x: i16 = -5;
+        let value = sign_extend(x as u16, 5);
+        assert_eq!(0xFFFB, value);
+    }
+
+    #[test]
+    fn decode_add_immediate() {
+        // 0001 001 010 1 11110 = ADD R1 R2 -2
+        let instruction = Instruction::decode(0x12BE);
+        assert_eq!(
+            Instruction::Add {
+                dr: Register::R1,
+                sr1: Register::R2,
+                operand: Operand::Immediate(0xFFFE)
+            },
+            instruction
+        );
+    }
+
+    #[test]
+    fn decode_jsr_and_jsrr() {
+        // 0100 1 11111111111 = JSR -1
+        assert_eq!(
+            Instruction::Jsr { offset: 0xFFFF },
+            Instruction::decode(0x4FFF)
+        );
+        // 0100 0 00 011 000000 = JSRR R3
+        assert_eq!(
+            Instruction::Jsrr { base: Register::R3 },
+            Instruction::decode(0x40C0)
+        );
+    }
+
+    #[cfg(feature = "lc3b")]
+    #[test]
+    fn decode_lc3b_scales_offsets() {
+        // 0000 111 111111111 = BRnzp -1, one word back
+        assert_eq!(
+            Instruction::Br {
+                flags: 0x7,
+                offset: 0xFFFE
+            },
+            Instruction::decode_lc3b(0x0FFF)
+        );
+        // 0010 001 010 111111 = LDB R1 R2 -1, one byte back
+        assert_eq!(
+            Instruction::Ldb {
+                dr: Register::R1,
+                base: Register::R2,
+                offset: 0xFFFF
+            },
+            Instruction::decode_lc3b(0x22BF)
+        );
+        // 1101 001 010 11 0011 = RSHFA R1 R2 3
+        assert_eq!(
+            Instruction::Shf {
+                dr: Register::R1,
+                sr: Register::R2,
+                shift: Shift::RightArithmetic,
+                amount: 3
+            },
+            Instruction::decode_lc3b(0xD2B3)
+        );
+    }
+
+    #[test]
+    fn cache_invalidation() {
+        let mut cache = DecodeCache::new();
+        assert_eq!(
+            (0x1021, Instruction::decode(0x1021)),
+            cache.get(0x3000, || 0x1021)
+        );
+        assert_eq!(
+            (0x1021, Instruction::decode(0x1021)),
+            cache.get(0x3000, || 0xF025)
+        );
+
+        cache.invalidate(0x3000);
+        assert_eq!(
+            (0xF025, Instruction::decode(0xF025)),
+            cache.get(0x3000, || 0xF025)
+        );
+    }
+}
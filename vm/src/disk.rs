@@ -0,0 +1,328 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "std")]
+use std::io::{self, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use crate::memory::{Device, Interrupt, Memory, KBSR, STATUS_READY};
+
+/// Words in a sector
+pub const SECTOR_WORDS: usize = 256;
+
+/// Command register. Writing a command starts it.
+pub const DCMD: u16 = 0xFE10;
+/// Sector register
+pub const DSEC: u16 = 0xFE12;
+/// Buffer address register, the first word of the sector in memory
+pub const DBUF: u16 = 0xFE14;
+/// Status register
+pub const DSTAT: u16 = 0xFE16;
+
+/// Copies the sector in DSEC to the buffer at DBUF
+pub const DISK_READ: u16 = 1;
+/// Copies the buffer at DBUF to the sector in DSEC
+pub const DISK_WRITE: u16 = 2;
+
+/// Status bit \[15\], set while no command is running
+pub const DISK_READY: u16 = STATUS_READY;
+/// Status bit \[14\], writable. Completing a command interrupts when set.
+pub const DISK_INTERRUPT_ENABLE: u16 = 1 << 14;
+/// Status bit \[0\], set when the last command failed
+pub const DISK_ERROR: u16 = 1 << 0;
+
+/// Interrupt vector and priority of the disk's completion interrupt
+pub const DISK_INTERRUPT: Interrupt = Interrupt {
+    vector: 0x81,
+    priority: 3,
+};
+
+/// Instructions a command takes by default before it completes
+pub const DISK_LATENCY: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageError;
+
+/// Sectors behind a `Disk`
+pub trait Storage {
+    fn sectors(&self) -> u16;
+
+    fn read_sector(
+        &mut self,
+        sector: u16,
+        buffer: &mut [u16; SECTOR_WORDS],
+    ) -> Result<(), StorageError>;
+
+    fn write_sector(
+        &mut self,
+        sector: u16,
+        buffer: &[u16; SECTOR_WORDS],
+    ) -> Result<(), StorageError>;
+}
+
+/// Storage held in memory. The contents are shared, so they can still be
+/// inspected after the disk has been handed to a `VirtualMachine`.
+pub struct MemoryStorage {
+    words: Rc<RefCell<Vec<u16>>>,
+}
+
+impl MemoryStorage {
+    /// Zeroed storage of `sectors` sectors
+    pub fn new(sectors: u16) -> Self {
+        Self::with_contents(vec![0; sectors as usize * SECTOR_WORDS])
+    }
+
+    /// Storage initialized with `words`. A partial sector at the end is not
+    /// addressable.
+    pub fn with_contents(words: Vec<u16>) -> Self {
+        Self {
+            words: Rc::new(RefCell::new(words)),
+        }
+    }
+
+    /// Handle to the contents
+    pub fn contents(&self) -> Rc<RefCell<Vec<u16>>> {
+        Rc::clone(&self.words)
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn sectors(&self) -> u16 {
+        (self.words.borrow().len() / SECTOR_WORDS).min(u16::MAX as usize) as u16
+    }
+
+    fn read_sector(
+        &mut self,
+        sector: u16,
+        buffer: &mut [u16; SECTOR_WORDS],
+    ) -> Result<(), StorageError> {
+        let start = sector as usize * SECTOR_WORDS;
+        let words = self.words.borrow();
+        let words = words.get(start..start + SECTOR_WORDS).ok_or(StorageError)?;
+        buffer.copy_from_slice(words);
+        Ok(())
+    }
+
+    fn write_sector(
+        &mut self,
+        sector: u16,
+        buffer: &[u16; SECTOR_WORDS],
+    ) -> Result<(), StorageError> {
+        let start = sector as usize * SECTOR_WORDS;
+        let mut words = self.words.borrow_mut();
+        let words = words
+            .get_mut(start..start + SECTOR_WORDS)
+            .ok_or(StorageError)?;
+        words.copy_from_slice(buffer);
+        Ok(())
+    }
+}
+
+/// Storage in a host file of little-endian words, the same layout as `.obj`
+/// files. Writes go straight to the file.
+#[cfg(feature = "std")]
+pub struct FileStorage {
+    file: File,
+    sectors: u16,
+}
+
+#[cfg(feature = "std")]
+impl FileStorage {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let sectors = file.metadata()?.len() / (2 * SECTOR_WORDS as u64);
+        Ok(Self {
+            file,
+            sectors: sectors.min(u16::MAX as u64) as u16,
+        })
+    }
+
+    fn seek(&mut self, sector: u16) -> Result<(), StorageError> {
+        if sector >= self.sectors {
+            return Err(StorageError);
+        }
+        let offset = sector as u64 * 2 * SECTOR_WORDS as u64;
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .map_err(|_| StorageError)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Storage for FileStorage {
+    fn sectors(&self) -> u16 {
+        self.sectors
+    }
+
+    fn read_sector(
+        &mut self,
+        sector: u16,
+        buffer: &mut [u16; SECTOR_WORDS],
+    ) -> Result<(), StorageError> {
+        self.seek(sector)?;
+        let mut bytes = [0; 2 * SECTOR_WORDS];
+        self.file.read_exact(&mut bytes).map_err(|_| StorageError)?;
+        for (word, chunk) in buffer.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(())
+    }
+
+    fn write_sector(
+        &mut self,
+        sector: u16,
+        buffer: &[u16; SECTOR_WORDS],
+    ) -> Result<(), StorageError> {
+        self.seek(sector)?;
+        let bytes: Vec<u8> = buffer.iter().flat_map(|word| word.to_le_bytes()).collect();
+        self.file.write_all(&bytes).map_err(|_| StorageError)
+    }
+}
+
+/// Disk controller with DMA
+///
+/// A program writes the sector to DSEC and the buffer address to DBUF, then
+/// writes `DISK_READ` or `DISK_WRITE` to DCMD. DISK_READY in the status
+/// register clears while the command runs and sets again once the sector has
+/// been copied, `latency` instructions later. If DISK_INTERRUPT_ENABLE is set
+/// the completion also requests `DISK_INTERRUPT`.
+///
+/// A command fails with DISK_ERROR if the sector does not exist, the buffer
+/// reaches into the device registers, or the command is unknown. DMA goes
+/// straight to memory and never reaches other devices.
+///
+/// The registers sit at even addresses, like the built-in device registers,
+/// so that LC-3b word accesses reach them. Like those, they can only be used
+/// in supervisor mode.
+pub struct Disk {
+    storage: Box<dyn Storage>,
+    latency: u64,
+    command: u16,
+    sector: u16,
+    buffer: u16,
+    interrupt_enable: bool,
+    error: bool,
+    /// Instructions left before the running command completes
+    busy: Option<u64>,
+    interrupt_pending: bool,
+}
+
+impl Disk {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        Self {
+            storage,
+            latency: DISK_LATENCY,
+            command: 0,
+            sector: 0,
+            buffer: 0,
+            interrupt_enable: false,
+            error: false,
+            busy: None,
+            interrupt_pending: false,
+        }
+    }
+
+    /// Instructions a command takes to complete
+    pub fn latency(mut self, instructions: u64) -> Self {
+        self.latency = instructions;
+        self
+    }
+
+    fn status(&self) -> u16 {
+        let mut status = 0;
+        if self.busy.is_none() {
+            status |= DISK_READY;
+        }
+        if self.interrupt_enable {
+            status |= DISK_INTERRUPT_ENABLE;
+        }
+        if self.error {
+            status |= DISK_ERROR;
+        }
+        status
+    }
+
+    /// Runs the current command against `memory`
+    fn transfer(&mut self, memory: &mut Memory) -> Result<(), StorageError> {
+        let end = self.buffer as usize + SECTOR_WORDS;
+        if end > KBSR as usize {
+            return Err(StorageError);
+        }
+
+        let mut sector = [0; SECTOR_WORDS];
+        let addresses = self.buffer..end as u16;
+        match self.command {
+            DISK_READ => {
+                self.storage.read_sector(self.sector, &mut sector)?;
+                for (address, word) in addresses.zip(sector) {
+                    memory.write(address, word);
+                }
+                Ok(())
+            }
+            DISK_WRITE => {
+                for (address, word) in addresses.zip(&mut sector) {
+                    *word = memory.read(address);
+                }
+                self.storage.write_sector(self.sector, &sector)
+            }
+            _ => Err(StorageError),
+        }
+    }
+}
+
+impl Device for Disk {
+    fn contains(&self, address: u16) -> bool {
+        matches!(address, DCMD | DSEC | DBUF | DSTAT)
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            DCMD => self.command,
+            DSEC => self.sector,
+            DBUF => self.buffer,
+            _ => self.status(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            // Commands written while one is running are ignored
+            DCMD if self.busy.is_none() => {
+                self.command = value;
+                self.busy = Some(self.latency);
+            }
+            DCMD => {}
+            DSEC => self.sector = value,
+            DBUF => self.buffer = value,
+            _ => self.interrupt_enable = value & DISK_INTERRUPT_ENABLE != 0,
+        }
+    }
+
+    fn tick(&mut self, memory: &mut Memory, instructions: u64) {
+        let Some(remaining) = self.busy else {
+            return;
+        };
+        if remaining > instructions {
+            self.busy = Some(remaining - instructions);
+            return;
+        }
+
+        self.error = self.transfer(memory).is_err();
+        self.busy = None;
+        self.interrupt_pending = self.interrupt_enable;
+    }
+
+    fn interrupt(&self) -> Option<Interrupt> {
+        self.interrupt_pending.then_some(DISK_INTERRUPT)
+    }
+
+    fn acknowledge(&mut self) {
+        self.interrupt_pending = false;
+    }
+}
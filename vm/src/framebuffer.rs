@@ -0,0 +1,213 @@
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::memory::{Device, KBSR};
+
+/// First address of the framebuffer. Pixels are stored row by row, so the
+/// pixel at (x, y) is at `FRAMEBUFFER + y * FRAMEBUFFER_WIDTH + x`.
+pub const FRAMEBUFFER: u16 = 0xC000;
+pub const FRAMEBUFFER_WIDTH: usize = 128;
+pub const FRAMEBUFFER_HEIGHT: usize = 124;
+
+/// The pixels of a framebuffer. Each pixel is a 15-bit color with five bits
+/// each of red, green and blue: x\[14:10\] red, x\[9:5\] green, x\[4:0\] blue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pixels: Vec<u16>,
+}
+
+impl Frame {
+    /// An all-black frame
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![0; FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT],
+        }
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * FRAMEBUFFER_WIDTH + x]
+    }
+
+    /// Color of the pixel at (x, y) scaled to eight bits per channel
+    pub fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        let pixel = self.pixel(x, y);
+        [pixel >> 10, pixel >> 5, pixel].map(|channel| {
+            let channel = (channel & 0x1F) as u8;
+            (channel << 3) | (channel >> 2)
+        })
+    }
+
+    /// Binary PPM (P6) image of the frame
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm =
+            alloc::format!("P6\n{FRAMEBUFFER_WIDTH} {FRAMEBUFFER_HEIGHT}\n255\n").into_bytes();
+        for y in 0..FRAMEBUFFER_HEIGHT {
+            for x in 0..FRAMEBUFFER_WIDTH {
+                ppm.extend_from_slice(&self.rgb(x, y));
+            }
+        }
+        ppm
+    }
+
+    /// PNG image of the frame. The image data is stored uncompressed.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut scanlines = Vec::with_capacity(FRAMEBUFFER_HEIGHT * (1 + 3 * FRAMEBUFFER_WIDTH));
+        for y in 0..FRAMEBUFFER_HEIGHT {
+            // Filter type 0, none
+            scanlines.push(0);
+            for x in 0..FRAMEBUFFER_WIDTH {
+                scanlines.extend_from_slice(&self.rgb(x, y));
+            }
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(FRAMEBUFFER_WIDTH as u32).to_be_bytes());
+        header.extend_from_slice(&(FRAMEBUFFER_HEIGHT as u32).to_be_bytes());
+        // 8-bit RGB, deflate, no filtering method extensions, not interlaced
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Monochrome preview for a terminal. Every character covers two rows of
+    /// pixels with half blocks, and any pixel that is not black is drawn lit.
+    pub fn preview(&self) -> String {
+        let mut preview = String::new();
+        for y in (0..FRAMEBUFFER_HEIGHT).step_by(2) {
+            for x in 0..FRAMEBUFFER_WIDTH {
+                let top = self.pixel(x, y) & 0x7FFF != 0;
+                let bottom = self.pixel(x, y + 1) & 0x7FFF != 0;
+                preview.push(match (top, bottom) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                });
+            }
+            preview.push('\n');
+        }
+        preview
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Video device covering x\[C000:FDFF\], one word per pixel. It sits in user
+/// space, so programs can draw without a service routine.
+///
+/// ```
+/// use vm::{Framebuffer, VirtualMachine};
+///
+/// // STR R0, R1, #0; HALT
+/// let framebuffer = Framebuffer::new();
+/// let frame = framebuffer.frame();
+/// let mut vm = VirtualMachine::builder()
+///     .program(0x3000, &[0x7040, 0xF025])
+///     .device(Box::new(framebuffer))
+///     .build();
+/// vm.registers_mut().set(vm::Register::R0, 0x7FFF);
+/// vm.registers_mut().set(vm::Register::R1, 0xC000);
+/// vm.run();
+///
+/// assert_eq!([255, 255, 255], frame.borrow().rgb(0, 0));
+/// ```
+pub struct Framebuffer {
+    frame: Rc<RefCell<Frame>>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            frame: Rc::new(RefCell::new(Frame::new())),
+        }
+    }
+
+    /// Handle to the current frame
+    pub fn frame(&self) -> Rc<RefCell<Frame>> {
+        Rc::clone(&self.frame)
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Framebuffer {
+    fn contains(&self, address: u16) -> bool {
+        (FRAMEBUFFER..KBSR).contains(&address)
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        self.frame.borrow().pixels[(address - FRAMEBUFFER) as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.frame.borrow_mut().pixels[(address - FRAMEBUFFER) as usize] = value;
+    }
+}
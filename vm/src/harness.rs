@@ -0,0 +1,254 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::cache::Cache;
+use crate::call_stack::Backtrace;
+use crate::console::ScriptedConsole;
+use crate::framebuffer::{Frame, Framebuffer};
+use crate::isa::Isa;
+use crate::microarchitecture::Microarchitecture;
+use crate::profiler::Profiler;
+use crate::register::{PrivilegeMode, Registers};
+use crate::replay::InputLog;
+use crate::vm::{HaltReason, VirtualMachine};
+
+/// Runs programs without a terminal, feeding them a fixed input string and
+/// capturing everything they write to the display
+///
+/// ```
+/// use vm::{HaltReason, Harness};
+///
+/// // LEA R0, #2; PUTS; HALT; "Hi"
+/// let program = [0xE002, 0xF022, 0xF025, 0x0048, 0x0069, 0x0000];
+/// let result = Harness::new().run(&program);
+///
+/// assert_eq!(b"Hi", result.output.as_slice());
+/// assert_eq!(HaltReason::Halt, result.halt_reason);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Harness {
+    origin: u16,
+    mode: PrivilegeMode,
+    os_image: Option<Vec<u16>>,
+    isa: Isa,
+    input: Vec<u8>,
+    replay: Option<InputLog>,
+    instruction_limit: Option<u64>,
+    decode_cache: bool,
+    block_engine: bool,
+    microarchitecture: bool,
+    framebuffer: bool,
+    instruction_cache: Option<Cache>,
+    data_cache: Option<Cache>,
+    breakpoints: Vec<u16>,
+    profiler: Option<Profiler>,
+}
+
+/// Result of a headless run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunOutput {
+    /// Everything written by OUT, PUTS, PUTSP, IN and the display device
+    pub output: Vec<u8>,
+    pub registers: Registers,
+    pub halt_reason: HaltReason,
+    pub instruction_count: u64,
+    /// Clock cycles taken, when run through the microsequencer
+    pub cycles: Option<u64>,
+    /// Final contents of the framebuffer, if one was attached
+    pub frame: Option<Frame>,
+    /// Instruction cache with the statistics of the run, if one was simulated
+    pub instruction_cache: Option<Cache>,
+    /// Data cache with the statistics of the run, if one was simulated
+    pub data_cache: Option<Cache>,
+    /// Call stack where the program stopped
+    pub backtrace: Backtrace,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Self {
+            origin: 0x3000,
+            mode: PrivilegeMode::User,
+            os_image: None,
+            isa: Isa::Lc3,
+            input: Vec::new(),
+            replay: None,
+            instruction_limit: None,
+            decode_cache: false,
+            block_engine: false,
+            microarchitecture: false,
+            framebuffer: false,
+            instruction_cache: None,
+            data_cache: None,
+            breakpoints: Vec::new(),
+            profiler: None,
+        }
+    }
+
+    /// Address the program is loaded at and started from
+    pub fn origin(mut self, origin: u16) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Privilege mode the program starts in. Programs that use the device
+    /// registers directly have to run in supervisor mode.
+    pub fn mode(mut self, mode: PrivilegeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Operating system image loaded at x0000 underneath the program
+    pub fn os_image(mut self, image: &[u16]) -> Self {
+        self.os_image = Some(image.to_vec());
+        self
+    }
+
+    /// Instruction set the program is written for
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    /// Bytes returned by GETC, IN and the keyboard device
+    pub fn input(mut self, input: &[u8]) -> Self {
+        self.input = input.to_vec();
+        self
+    }
+
+    /// Takes input from a recorded log instead, replacing `input`
+    pub fn replay(mut self, log: InputLog) -> Self {
+        self.replay = Some(log);
+        self
+    }
+
+    /// Stops the program after `limit` instructions
+    pub fn instruction_limit(mut self, limit: u64) -> Self {
+        self.instruction_limit = Some(limit);
+        self
+    }
+
+    /// Runs with the decoded instruction cache enabled
+    pub fn decode_cache(mut self) -> Self {
+        self.decode_cache = true;
+        self
+    }
+
+    /// Runs with the basic-block translation engine enabled
+    pub fn block_engine(mut self) -> Self {
+        self.block_engine = true;
+        self
+    }
+
+    /// Runs through the microsequencer, one clock cycle at a time, and
+    /// reports the cycles taken
+    pub fn microarchitecture(mut self) -> Self {
+        self.microarchitecture = true;
+        self
+    }
+
+    /// Attaches a framebuffer at xC000
+    pub fn framebuffer(mut self) -> Self {
+        self.framebuffer = true;
+        self
+    }
+
+    /// Simulates `cache` on instruction fetches
+    pub fn instruction_cache(mut self, cache: Cache) -> Self {
+        self.instruction_cache = Some(cache);
+        self
+    }
+
+    /// Simulates `cache` on data reads and writes
+    pub fn data_cache(mut self, cache: Cache) -> Self {
+        self.data_cache = Some(cache);
+        self
+    }
+
+    /// Stops the program with `HaltReason::Breakpoint` at `address`
+    pub fn breakpoint(mut self, address: u16) -> Self {
+        self.breakpoints.push(address);
+        self
+    }
+
+    /// Counts the instructions executed at each address into `profiler`
+    pub fn profile(mut self, profiler: &Profiler) -> Self {
+        self.profiler = Some(profiler.clone());
+        self
+    }
+
+    pub fn run(&self, program: &[u16]) -> RunOutput {
+        let console = ScriptedConsole::new(&self.input);
+        let output = console.output();
+
+        let mut builder = VirtualMachine::builder();
+        if let Some(image) = &self.os_image {
+            builder = builder.os_image(image);
+        }
+        builder = builder
+            .program(self.origin, program)
+            .mode(self.mode)
+            .isa(self.isa)
+            .console(Box::new(console));
+        if let Some(limit) = self.instruction_limit {
+            builder = builder.instruction_limit(limit);
+        }
+        if let Some(log) = &self.replay {
+            builder = builder.replay_input(log.clone());
+        }
+        if self.decode_cache {
+            builder = builder.decode_cache();
+        }
+        if self.block_engine {
+            builder = builder.block_engine();
+        }
+        if let Some(cache) = &self.instruction_cache {
+            builder = builder.instruction_cache(cache.clone());
+        }
+        if let Some(cache) = &self.data_cache {
+            builder = builder.data_cache(cache.clone());
+        }
+        for address in &self.breakpoints {
+            builder = builder.breakpoint(*address);
+        }
+        if let Some(profiler) = &self.profiler {
+            builder = builder.profile(profiler);
+        }
+        let mut frame = None;
+        if self.framebuffer {
+            let framebuffer = Framebuffer::new();
+            frame = Some(framebuffer.frame());
+            builder = builder.device(Box::new(framebuffer));
+        }
+        let mut vm = builder.build();
+
+        let mut cycles = None;
+        let halt_reason = if self.microarchitecture {
+            let mut microarchitecture = Microarchitecture::new();
+            let halt_reason = microarchitecture.run(&mut vm);
+            cycles = Some(microarchitecture.cycles());
+            halt_reason
+        } else {
+            vm.run()
+        };
+        let output = output.borrow().clone();
+
+        RunOutput {
+            output,
+            registers: vm.registers().clone(),
+            halt_reason,
+            instruction_count: vm.instruction_count(),
+            cycles,
+            frame: frame.map(|frame| frame.borrow().clone()),
+            instruction_cache: vm.memory().instruction_cache().cloned(),
+            data_cache: vm.memory().data_cache().cloned(),
+            backtrace: vm.backtrace(),
+        }
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
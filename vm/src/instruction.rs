@@ -1,8 +1,10 @@
-use std::str::FromStr;
+use alloc::string::{String, ToString};
+use core::str::FromStr;
 
-use crate::{PrivilegeMode, Register, VirtualMachine};
-
-// TODO: Write tests for instructions
+#[cfg(feature = "lc3b")]
+use crate::decode::{sign_extend, Shift};
+use crate::decode::{Instruction, Operand};
+use crate::{Entry, Exception, HaltReason, PrivilegeMode, Register, VirtualMachine};
 
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
@@ -77,7 +79,7 @@ impl FromStr for Opcode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TrapCode {
     /// Get character from keyboard
     GETC = 0x20,
@@ -93,30 +95,66 @@ pub enum TrapCode {
     HALT = 0x25,
 }
 
+impl TryFrom<u8> for TrapCode {
+    type Error = u8;
+    fn try_from(vector: u8) -> Result<Self, Self::Error> {
+        match vector {
+            0x20 => Ok(TrapCode::GETC),
+            0x21 => Ok(TrapCode::OUT),
+            0x22 => Ok(TrapCode::PUTS),
+            0x23 => Ok(TrapCode::IN),
+            0x24 => Ok(TrapCode::PUTSP),
+            0x25 => Ok(TrapCode::HALT),
+            _ => Err(vector),
+        }
+    }
+}
+
 pub fn execute(vm: &mut VirtualMachine, instruction: u16) {
-    let opcode = instruction >> 12;
-
-    match opcode {
-        0 => br(vm, instruction),
-        1 => add(vm, instruction),
-        2 => ld(vm, instruction),
-        3 => st(vm, instruction),
-        4 => jsr(vm, instruction),
-        5 => and(vm, instruction),
-        6 => ldr(vm, instruction),
-        7 => str(vm, instruction),
-        8 => rti(vm, instruction),
-        9 => not(vm, instruction),
-        10 => ldi(vm, instruction),
-        11 => sti(vm, instruction),
-        12 => jmp(vm, instruction),
-        13 => res(vm, instruction),
-        14 => lea(vm, instruction),
-        15 => trap(vm, instruction),
-        _ => panic!("Unknown opcode {opcode}"),
+    let decoded = vm.isa().decode(instruction);
+    execute_decoded(vm, decoded);
+}
+
+pub fn execute_decoded(vm: &mut VirtualMachine, instruction: Instruction) {
+    match instruction {
+        Instruction::Br { flags, offset } => br(vm, flags, offset),
+        Instruction::Add { dr, sr1, operand } => add(vm, dr, sr1, operand),
+        Instruction::Ld { dr, offset } => ld(vm, dr, offset),
+        Instruction::St { sr, offset } => st(vm, sr, offset),
+        Instruction::Jsr { offset } => jsr(vm, JsrTarget::Offset(offset)),
+        Instruction::Jsrr { base } => jsr(vm, JsrTarget::Register(base)),
+        Instruction::And { dr, sr1, operand } => and(vm, dr, sr1, operand),
+        Instruction::Ldr { dr, base, offset } => ldr(vm, dr, base, offset),
+        Instruction::Str { sr, base, offset } => str(vm, sr, base, offset),
+        Instruction::Rti => rti(vm),
+        Instruction::Not { dr, sr } => not(vm, dr, sr),
+        Instruction::Ldi { dr, offset } => ldi(vm, dr, offset),
+        Instruction::Sti { sr, offset } => sti(vm, sr, offset),
+        Instruction::Jmp { base } => jmp(vm, base),
+        Instruction::Res => res(vm),
+        Instruction::Lea { dr, offset } => lea(vm, dr, offset),
+        Instruction::Trap { vector } => trap(vm, vector),
+        #[cfg(feature = "lc3b")]
+        Instruction::Ldb { dr, base, offset } => ldb(vm, dr, base, offset),
+        #[cfg(feature = "lc3b")]
+        Instruction::Stb { sr, base, offset } => stb(vm, sr, base, offset),
+        #[cfg(feature = "lc3b")]
+        Instruction::Xor { dr, sr1, operand } => xor(vm, dr, sr1, operand),
+        #[cfg(feature = "lc3b")]
+        Instruction::Shf {
+            dr,
+            sr,
+            shift,
+            amount,
+        } => shf(vm, dr, sr, shift, amount),
     }
 }
 
+enum JsrTarget {
+    Offset(u16),
+    Register(Register),
+}
+
 /// Branch
 /// The condition codes specified by bits [11:9] are tested. If bit [11] is 1, N is tested;
 /// if bit [11] is 0, N is not tested. If bit [10] is 1, Z is tested, etc. If any of the condition
@@ -127,15 +165,12 @@ pub fn execute(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───┼───┼───┼───────────────────────────────────┐
 /// │      0000     │ N │ Z │ P │             PCOffset9             │
 /// └───────────────┴───┴───┴───┴───────────────────────────────────┘
-fn br(vm: &mut VirtualMachine, instruction: u16) {
-    let flags = (instruction >> 9) & 0x7;
-
-    if flags & vm.registers.get(Register::PSR.into()) != 0 {
-        let offset = sign_extend(instruction & 0x1FF, 9);
-        let pc = vm.registers.get(Register::PC.into());
+fn br(vm: &mut VirtualMachine, flags: u16, offset: u16) {
+    if flags & vm.registers.get(Register::PSR) != 0 {
+        let pc = vm.registers.get(Register::PC);
         let address = (pc as u32 + offset as u32) as u16;
 
-        vm.registers.set(Register::PC.into(), address);
+        vm.registers.set(Register::PC, address);
     }
 }
 
@@ -155,19 +190,16 @@ fn br(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────┼───┼───────────────────┐
 /// │      0001     │     DR    │  SR1      │ 1 │       IMM5        │
 /// └───────────────┴───────────┴───────────┴───┴───────────────────┘
-fn add(vm: &mut VirtualMachine, instruction: u16) {
-    let dr = (instruction >> 9) & 0x7;
-    let sr1 = (instruction >> 6) & 0x7;
-    let imm_flag = (instruction >> 5) & 0x1;
-
-    if imm_flag == 1 {
-        let imm5 = sign_extend(instruction & 0x1F, 5);
-        let result = (vm.registers.get(sr1) as u32 + imm5 as u32) as u16;
-        vm.registers.set(dr, result);
-    } else {
-        let sr2 = instruction & 0x7;
-        let result = vm.registers.get(sr1) + vm.registers.get(sr2);
-        vm.registers.set(dr, result);
+pub(crate) fn add(vm: &mut VirtualMachine, dr: Register, sr1: Register, operand: Operand) {
+    match operand {
+        Operand::Immediate(imm5) => {
+            let result = (vm.registers.get(sr1) as u32 + imm5 as u32) as u16;
+            vm.registers.set(dr, result);
+        }
+        Operand::Register(sr2) => {
+            let result = vm.registers.get(sr1).wrapping_add(vm.registers.get(sr2));
+            vm.registers.set(dr, result);
+        }
     }
 
     vm.registers.set_condition_codes(dr);
@@ -184,16 +216,14 @@ fn add(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────────────────────────────┐
 /// │      0010     │     DR    │            PCOffset9              │
 /// └───────────────┴───────────┴───────────────────────────────────┘
-fn ld(vm: &mut VirtualMachine, instruction: u16) {
-    let dr = (instruction >> 9) & 0x7;
-    let offset = sign_extend(instruction & 0x1FF, 9);
-    let pc = vm.registers.get(Register::PC.into());
+fn ld(vm: &mut VirtualMachine, dr: Register, offset: u16) {
+    let pc = vm.registers.get(Register::PC);
 
     let address = (pc as u32 + offset as u32) as u16;
     if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
-        todo!("Initiate ACV exception");
+        vm.raise_exception(Exception::AccessViolation);
     } else {
-        let value = vm.memory.read(address);
+        let value = vm.read(address);
         vm.registers.set(dr, value);
         vm.registers.set_condition_codes(dr);
     }
@@ -209,17 +239,15 @@ fn ld(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────────────────────────────┐
 /// │      0011     │     SR    │            PCOffset9              │
 /// └───────────────┴───────────┴───────────────────────────────────┘
-fn st(vm: &mut VirtualMachine, instruction: u16) {
-    let sr = (instruction >> 9) & 0x7;
-    let offset = sign_extend(instruction & 0x1FF, 9);
-    let pc = vm.registers.get(Register::PC.into());
+fn st(vm: &mut VirtualMachine, sr: Register, offset: u16) {
+    let pc = vm.registers.get(Register::PC);
 
     let value = vm.registers.get(sr);
     let address = (pc as u32 + offset as u32) as u16;
     if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
-        todo!("Initiate ACV exception");
+        vm.raise_exception(Exception::AccessViolation);
     } else {
-        vm.memory.write(address, value);
+        vm.write(address, value);
     }
 }
 
@@ -241,20 +269,16 @@ fn st(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───┼───────┼───────┼───────────────────────────┐
 /// │      0100     │ 0 │   00  │ BaseR │           00000           │
 /// └───────────────┴───┴───────┴───────┴───────────────────────────┘
-fn jsr(vm: &mut VirtualMachine, instruction: u16) {
-    let flag = (instruction >> 11) & 0x1;
-    let pc = vm.registers.get(Register::PC.into());
-    vm.registers.set(Register::R7.into(), pc);
-
-    if flag == 1 {
-        let offset = sign_extend(instruction & 0x7FF, 11);
-        let address = (pc as u32 + offset as u32) as u16;
-        vm.registers.set(Register::PC.into(), address);
-    } else {
-        let reg = (instruction >> 6) & 0x7;
-        let address = vm.registers.get(reg);
-        vm.registers.set(Register::PC.into(), address);
-    }
+fn jsr(vm: &mut VirtualMachine, target: JsrTarget) {
+    let pc = vm.registers.get(Register::PC);
+    let address = match target {
+        JsrTarget::Offset(offset) => (pc as u32 + offset as u32) as u16,
+        JsrTarget::Register(reg) => vm.registers.get(reg),
+    };
+
+    vm.registers.set(Register::R7, pc);
+    vm.registers.set(Register::PC, address);
+    vm.enter_call(address);
 }
 
 /// Bit-wise logical AND
@@ -274,19 +298,16 @@ fn jsr(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────┼───┼───────────────────┐
 /// │      0101     │     DR    │  SR1      │ 1 │       IMM5        │
 /// └───────────────┴───────────┴───────────┴───┴───────────────────┘
-fn and(vm: &mut VirtualMachine, instruction: u16) {
-    let dr = (instruction >> 9) & 0x7;
-    let sr1 = (instruction >> 6) & 0x7;
-    let imm_flag = (instruction >> 5) & 0x1;
-
-    if imm_flag == 1 {
-        let imm5 = sign_extend(instruction & 0x1F, 5);
-        let result = vm.registers.get(sr1) & imm5;
-        vm.registers.set(dr, result);
-    } else {
-        let sr2 = instruction & 0x7;
-        let result = vm.registers.get(sr1) & vm.registers.get(sr2);
-        vm.registers.set(dr, result);
+pub(crate) fn and(vm: &mut VirtualMachine, dr: Register, sr1: Register, operand: Operand) {
+    match operand {
+        Operand::Immediate(imm5) => {
+            let result = vm.registers.get(sr1) & imm5;
+            vm.registers.set(dr, result);
+        }
+        Operand::Register(sr2) => {
+            let result = vm.registers.get(sr1) & vm.registers.get(sr2);
+            vm.registers.set(dr, result);
+        }
     }
 
     vm.registers.set_condition_codes(dr);
@@ -303,16 +324,19 @@ fn and(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────────┼───────────────────┐
 /// │      1010     │     DR    │     BaseR     │     PCOffset6     │
 /// └───────────────┴───────────┴───────────────┴───────────────────┘
-fn ldr(vm: &mut VirtualMachine, instruction: u16) {
-    let dr = (instruction >> 9) & 0x7;
-    let reg = (instruction >> 6) & 0x7;
-    let offset = sign_extend(instruction & 0x3F, 6);
-
-    let address = (vm.registers.get(reg) as u32 + offset as u32) as u16;
+///
+/// On the LC-3b this is LDW and the offset is scaled to words.
+fn ldr(vm: &mut VirtualMachine, dr: Register, base: Register, offset: u16) {
+    let address = (vm.registers.get(base) as u32 + offset as u32) as u16;
+    #[cfg(feature = "lc3b")]
+    if vm.is_unaligned(address) {
+        vm.raise_exception(Exception::UnalignedAccess);
+        return;
+    }
     if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
-        todo!("Initiate ACV exception");
+        vm.raise_exception(Exception::AccessViolation);
     } else {
-        let value = vm.memory.read(address);
+        let value = vm.read(address);
         vm.registers.set(dr, value);
         vm.registers.set_condition_codes(dr);
     }
@@ -328,17 +352,20 @@ fn ldr(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────┼───────────────────────┐
 /// │      0111     │     SR    │   BaseR   │        PCOffset6      │
 /// └───────────────┴───────────┴───────────┴───────────────────────┘
-fn str(vm: &mut VirtualMachine, instruction: u16) {
-    let sr = (instruction >> 9) & 0x7;
-    let reg = (instruction >> 6) & 0x7;
-    let offset = sign_extend(instruction & 0x3F, 6);
-
-    let address = (vm.registers.get(reg) as u32 + offset as u32) as u16;
+///
+/// On the LC-3b this is STW and the offset is scaled to words.
+fn str(vm: &mut VirtualMachine, sr: Register, base: Register, offset: u16) {
+    let address = (vm.registers.get(base) as u32 + offset as u32) as u16;
+    #[cfg(feature = "lc3b")]
+    if vm.is_unaligned(address) {
+        vm.raise_exception(Exception::UnalignedAccess);
+        return;
+    }
     if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
-        todo!("Initiate ACV exception");
+        vm.raise_exception(Exception::AccessViolation);
     } else {
         let value = vm.registers.get(sr);
-        vm.memory.write(address, value)
+        vm.write(address, value)
     }
 }
 
@@ -352,11 +379,16 @@ fn str(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────────────────────────────────────────┐
 /// │      1000     │                  000000000000                 │
 /// └───────────────┴───────────────────────────────────────────────┘
-fn rti(vm: &mut VirtualMachine, _instruction: u16) {
+fn rti(vm: &mut VirtualMachine) {
     if vm.get_mode() == PrivilegeMode::User {
-        todo!("Initiate privilege mode exception");
+        vm.raise_exception(Exception::PrivilegeViolation);
     } else {
-        todo!("Figure out how to implement this instruction");
+        let pc = vm.pop();
+        let psr = vm.pop();
+        vm.registers.set(Register::PC, pc);
+        vm.set_mode(PrivilegeMode::from_psr(psr));
+        vm.registers.set(Register::PSR, psr);
+        vm.leave_service_routine();
     }
 }
 
@@ -369,10 +401,7 @@ fn rti(vm: &mut VirtualMachine, _instruction: u16) {
 /// ┌───────────────┼───────────┼───────────┼───┼───────────────────┐
 /// │      1001     │     DR    │     SR    │ 1 │       1111        │
 /// └───────────────┴───────────┴───────────┴───┴───────────────────┘
-fn not(vm: &mut VirtualMachine, instruction: u16) {
-    let dr = (instruction >> 9) & 0x7;
-    let sr = (instruction >> 6) & 0x7;
-
+pub(crate) fn not(vm: &mut VirtualMachine, dr: Register, sr: Register) {
     let value = vm.registers.get(sr);
     vm.registers.set(dr, !value);
     vm.registers.set_condition_codes(dr);
@@ -388,21 +417,23 @@ fn not(vm: &mut VirtualMachine, instruction: u16) {
 ///
 ///  15           12│11        9│8                                 0
 /// ┌───────────────┼───────────┼───────────────────────────────────┐
-/// │      0010     │     DR    │            PCOffset9              │
+/// │      1010     │     DR    │            PCOffset9              │
 /// └───────────────┴───────────┴───────────────────────────────────┘
-fn ldi(vm: &mut VirtualMachine, instruction: u16) {
-    let dr = (instruction >> 9) & 0x7;
-    let offset = sign_extend(instruction & 0x1FF, 9);
-    let pc = vm.registers.get(Register::PC.into());
+fn ldi(vm: &mut VirtualMachine, dr: Register, offset: u16) {
+    let pc = vm.registers.get(Register::PC);
 
     let indirect_address = (pc as u32 + offset as u32) as u16;
-    let address = vm.memory.read(indirect_address);
-    if (vm.memory.is_privileged(indirect_address) | vm.memory.is_privileged(address))
-        && vm.get_mode() == PrivilegeMode::User
-    {
-        todo!("Initiate ACV exception");
+    if vm.memory.is_privileged(indirect_address) && vm.get_mode() == PrivilegeMode::User {
+        vm.raise_exception(Exception::AccessViolation);
+        return;
+    }
+
+    let address = vm.read(indirect_address);
+    if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
+        vm.raise_exception(Exception::AccessViolation);
     } else {
-        vm.registers.set(dr, address);
+        let value = vm.read(address);
+        vm.registers.set(dr, value);
         vm.registers.set_condition_codes(dr);
     }
 }
@@ -418,21 +449,22 @@ fn ldi(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────────────────────────────┐
 /// │      1011     │     SR    │            PCOffset9              │
 /// └───────────────┴───────────┴───────────────────────────────────┘
-fn sti(vm: &mut VirtualMachine, instruction: u16) {
-    let sr = (instruction >> 9) & 0x7;
-    let offset = sign_extend(instruction & 0x1FF, 9);
-    let pc = vm.registers.get(Register::PC.into());
+fn sti(vm: &mut VirtualMachine, sr: Register, offset: u16) {
+    let pc = vm.registers.get(Register::PC);
 
     let value = vm.registers.get(sr);
 
     let indirect_address = (pc as u32 + offset as u32) as u16;
-    let address = vm.memory.read(indirect_address);
-    if (vm.memory.is_privileged(indirect_address) | vm.memory.is_privileged(address))
-        && vm.get_mode() == PrivilegeMode::User
-    {
-        todo!("Initiate ACV exception");
+    if vm.memory.is_privileged(indirect_address) && vm.get_mode() == PrivilegeMode::User {
+        vm.raise_exception(Exception::AccessViolation);
+        return;
+    }
+
+    let address = vm.read(indirect_address);
+    if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
+        vm.raise_exception(Exception::AccessViolation);
     } else {
-        vm.memory.write(address, value);
+        vm.write(address, value);
     }
 }
 
@@ -453,10 +485,12 @@ fn sti(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────┼───────────────────────┐
 /// │      1100     │    000    │    111    │       00000           │
 /// └───────────────┴───────────┴───────────┴───────────────────────┘
-fn jmp(vm: &mut VirtualMachine, instruction: u16) {
-    let reg = (instruction >> 6) & 0x7;
-    let address = vm.registers.get(reg);
-    vm.registers.set(Register::PC.into(), address);
+fn jmp(vm: &mut VirtualMachine, base: Register) {
+    let address = vm.registers.get(base);
+    vm.registers.set(Register::PC, address);
+    if base == Register::R7 {
+        vm.leave_call(address);
+    }
 }
 
 /// Reserved (unused)
@@ -465,8 +499,8 @@ fn jmp(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────────────────────────────────────────┐
 /// │      1101     │                                               │
 /// └───────────────┴───────────────────────────────────────────────┘
-fn res(_vm: &mut VirtualMachine, _instruction: u16) {
-    todo!("Initiate an illegal opcode exception");
+fn res(vm: &mut VirtualMachine) {
+    vm.raise_exception(Exception::IllegalOpcode);
 }
 
 /// Load effective address
@@ -477,10 +511,8 @@ fn res(_vm: &mut VirtualMachine, _instruction: u16) {
 /// ┌───────────────┼───────────┼───────────────────────────────────┐
 /// │      1110     │     DR    │            PCOffset9              │
 /// └───────────────┴───────────┴───────────────────────────────────┘
-fn lea(vm: &mut VirtualMachine, instruction: u16) {
-    let dr = (instruction >> 9) & 0x7;
-    let offset = sign_extend(instruction & 0x1FF, 9);
-    let pc = vm.registers.get(Register::PC.into());
+fn lea(vm: &mut VirtualMachine, dr: Register, offset: u16) {
+    let pc = vm.registers.get(Register::PC);
 
     let address = (pc as u32 + offset as u32) as u16;
     vm.registers.set(dr, address);
@@ -500,45 +532,171 @@ fn lea(vm: &mut VirtualMachine, instruction: u16) {
 /// ┌───────────────┼───────────┼───────────────────────────────────┐
 /// │      1111     │    0000   │            trapvect8              │
 /// └───────────────┴───────────┴───────────────────────────────────┘
-fn trap(vm: &mut VirtualMachine, _instruction: u16) {
-    if vm.get_mode() == PrivilegeMode::User {
-        todo!("Save stack pointers");
+///
+/// When the trap vector table has no entry for trapvect8, the built-in service
+/// routine for it runs instead, as if an operating system routine had run and
+/// returned.
+fn trap(vm: &mut VirtualMachine, vector: u8) {
+    vm.notify_trap(vector);
+    let address = vm.isa().trap_vector(vector);
+    let routine = vm.read(address);
+    if routine != 0 {
+        vm.enter_service_routine(routine, Entry::Trap(vector));
+        return;
     }
-    todo!("Implement based on textbook explanation");
-}
-
-fn sign_extend(mut x: u16, bit_count: u8) -> u16 {
-    // bit_count is the original number of bits
-    // that this binary value has. We want to take that
-    // and transform it into a 16 bits value.
 
-    // Then check if it's different than zero,
-    // if it is, it's signed as 1 (negative)
-    // Meaning we have to pad with ones instead of zeroes
-    if (x >> (bit_count - 1)) & 0x1 != 0 {
-        x |= 0xFFFF << bit_count;
+    match TrapCode::try_from(vector) {
+        Ok(code) => trap_routine(vm, code),
+        Err(vector) => vm.halt(HaltReason::UnknownTrap(vector)),
     }
-
-    // If it's positive, return as is, it will be padded
-    // with zeroes.
-    x
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Built-in service routines for the standard trap vectors, used in place of an
+/// operating system image. The routines talk to the console directly.
+pub(crate) fn trap_routine(vm: &mut VirtualMachine, code: TrapCode) {
+    let word_size = vm.isa().word_size();
+    match code {
+        TrapCode::GETC => match vm.memory.console_mut().read() {
+            Some(byte) => vm.registers.set(Register::R0, byte as u16),
+            None => vm.halt(HaltReason::InputExhausted),
+        },
+        TrapCode::OUT => {
+            let byte = vm.registers.get(Register::R0) as u8;
+            vm.memory.console_mut().write(byte);
+        }
+        TrapCode::PUTS => {
+            let mut address = vm.registers.get(Register::R0);
+            loop {
+                let value = vm.read(address);
+                if value == 0 {
+                    break;
+                }
+                vm.memory.console_mut().write(value as u8);
+                address = address.wrapping_add(word_size);
+            }
+        }
+        TrapCode::IN => {
+            for &byte in b"\nInput a character> " {
+                vm.memory.console_mut().write(byte);
+            }
+            vm.memory.console_mut().flush();
+            match vm.memory.console_mut().read() {
+                Some(byte) => {
+                    vm.memory.console_mut().write(byte);
+                    vm.memory.console_mut().write(b'\n');
+                    vm.registers.set(Register::R0, byte as u16);
+                }
+                None => vm.halt(HaltReason::InputExhausted),
+            }
+        }
+        TrapCode::PUTSP => {
+            let mut address = vm.registers.get(Register::R0);
+            'string: loop {
+                let value = vm.read(address);
+                for byte in [value as u8, (value >> 8) as u8] {
+                    if byte == 0 {
+                        break 'string;
+                    }
+                    vm.memory.console_mut().write(byte);
+                }
+                address = address.wrapping_add(word_size);
+            }
+        }
+        TrapCode::HALT => vm.halt(HaltReason::Halt),
+    }
+    vm.memory.console_mut().flush();
+}
 
-    #[test]
-    fn sign_extend_positive() {
-        let x: u16 = 3;
-        let value = sign_extend(x, 5);
-        assert_eq!(3, value);
+/// Load byte (LC-3b)
+/// An address is computed by sign-extending bits [5:0] to 16 bits and adding this
+/// value to the contents of the register specified by bits [8:6]. If the computed address
+/// is to privileged memory and PSR[15]=1, initiate ACV exception. If not, the byte at
+/// this address is sign-extended to 16 bits and loaded into DR. The condition codes
+/// are set, based on whether the value loaded is negative, zero, or positive.
+///
+///  15           12│11        9│8             6│5                 0
+/// ┌───────────────┼───────────┼───────────────┼───────────────────┐
+/// │      0010     │     DR    │     BaseR     │     boffset6      │
+/// └───────────────┴───────────┴───────────────┴───────────────────┘
+#[cfg(feature = "lc3b")]
+fn ldb(vm: &mut VirtualMachine, dr: Register, base: Register, offset: u16) {
+    let address = vm.registers.get(base).wrapping_add(offset);
+    if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
+        vm.raise_exception(Exception::AccessViolation);
+    } else {
+        let value = vm.read_byte(address);
+        vm.registers.set(dr, sign_extend(value as u16, 8));
+        vm.registers.set_condition_codes(dr);
     }
+}
 
-    #[test]
-    fn sign_extend_negative() {
-        let x: i16 = -5;
-        let value = sign_extend(x as u16, 5);
-        assert_eq!(0xFFFB, value);
+/// Store byte (LC-3b)
+/// If the computed address is to privileged memory and PSR[15]=1, initiate ACV
+/// exception. If not, bits [7:0] of the register specified by SR are stored in the
+/// byte whose address is computed by sign-extending bits [5:0] to 16 bits and
+/// adding this value to the contents of the register specified by bits [8:6].
+///
+///  15           12│11        9│8         6│5                     0
+/// ┌───────────────┼───────────┼───────────┼───────────────────────┐
+/// │      0011     │     SR    │   BaseR   │       boffset6        │
+/// └───────────────┴───────────┴───────────┴───────────────────────┘
+#[cfg(feature = "lc3b")]
+fn stb(vm: &mut VirtualMachine, sr: Register, base: Register, offset: u16) {
+    let address = vm.registers.get(base).wrapping_add(offset);
+    if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
+        vm.raise_exception(Exception::AccessViolation);
+    } else {
+        let value = vm.registers.get(sr) as u8;
+        vm.write_byte(address, value);
     }
 }
+
+/// Bit-wise exclusive OR (LC-3b)
+/// If bit [5] is 0, the second source operand is obtained from SR2. If bit [5] is 1,
+/// the second source operand is obtained by sign-extending the imm5 field to 16
+/// bits. In either case, the second source operand and the contents of SR1 are bitwise
+/// XORed and the result stored in DR. The condition codes are set, based on
+/// whether the result is negative, zero, or positive. NOT is XOR with an imm5 of -1.
+///
+///  15           12│11        9│8         6│ 5 │4     3│2         0
+/// ┌───────────────┼───────────┼───────────┼───┼───────┼───────────┐
+/// │      1001     │     DR    │  SR1      │ 0 │  00   │    SR2    │
+/// └───────────────┴───────────┴───────────┴───┴───────┴───────────┘
+///
+///  15           12│11        9│8         6│ 5 │4                 0
+/// ┌───────────────┼───────────┼───────────┼───┼───────────────────┐
+/// │      1001     │     DR    │  SR1      │ 1 │       IMM5        │
+/// └───────────────┴───────────┴───────────┴───┴───────────────────┘
+#[cfg(feature = "lc3b")]
+fn xor(vm: &mut VirtualMachine, dr: Register, sr1: Register, operand: Operand) {
+    let value = match operand {
+        Operand::Immediate(imm5) => imm5,
+        Operand::Register(sr2) => vm.registers.get(sr2),
+    };
+    let result = vm.registers.get(sr1) ^ value;
+    vm.registers.set(dr, result);
+    vm.registers.set_condition_codes(dr);
+}
+
+/// Bit-wise shift (LC-3b)
+/// The contents of SR are shifted by the number of bits in amount4. Bit [4] selects
+/// the direction: 0 shifts left and 1 shifts right. Right shifts fill with zeros
+/// if bit [5] is 0 or with copies of SR[15] if bit [5] is 1. The result is stored in
+/// DR and the condition codes are set, based on whether the result is negative,
+/// zero, or positive.
+///
+///  15           12│11        9│8         6│ 5 │ 4 │3             0
+/// ┌───────────────┼───────────┼───────────┼───┼───┼───────────────┐
+/// │      1101     │     DR    │     SR    │ A │ D │    amount4    │
+/// └───────────────┴───────────┴───────────┴───┴───┴───────────────┘
+#[cfg(feature = "lc3b")]
+fn shf(vm: &mut VirtualMachine, dr: Register, sr: Register, shift: Shift, amount: u8) {
+    let value = vm.registers.get(sr);
+    let result = match shift {
+        Shift::Left => value << amount,
+        Shift::RightLogical => value >> amount,
+        Shift::RightArithmetic => ((value as i16) >> amount) as u16,
+    };
+    vm.registers.set(dr, result);
+    vm.registers.set_condition_codes(dr);
+}
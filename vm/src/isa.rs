@@ -0,0 +1,89 @@
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+
+use crate::decode::Instruction;
+use crate::vm::INTERRUPT_VECTOR_TABLE;
+
+/// Instruction set a machine executes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Isa {
+    /// LC-3, with word-addressed memory
+    #[default]
+    Lc3,
+    /// LC-3b, with byte-addressed memory
+    ///
+    /// Words live at even addresses and PC advances by two. LD and ST become
+    /// the byte loads and stores LDB and STB, LDR and STR become LDW and STW,
+    /// NOT becomes XOR and the reserved opcode becomes SHF. LDI and STI are
+    /// gone and raise an illegal opcode exception. PC-relative offsets and the
+    /// LDW/STW offsets are scaled to words, and a word access to an odd address
+    /// raises `Exception::UnalignedAccess`.
+    ///
+    /// The trap and interrupt vector tables and the stack hold words, so they
+    /// are spaced two bytes apart: the trap vector table is x0000-x01FF and the
+    /// interrupt vector table x0200-x03FF.
+    #[cfg(feature = "lc3b")]
+    Lc3b,
+}
+
+impl Isa {
+    /// Distance between the addresses of consecutive words
+    pub fn word_size(self) -> u16 {
+        match self {
+            Isa::Lc3 => 1,
+            #[cfg(feature = "lc3b")]
+            Isa::Lc3b => 2,
+        }
+    }
+
+    pub fn decode(self, instruction: u16) -> Instruction {
+        match self {
+            Isa::Lc3 => Instruction::decode(instruction),
+            #[cfg(feature = "lc3b")]
+            Isa::Lc3b => Instruction::decode_lc3b(instruction),
+        }
+    }
+
+    /// Address of the trap vector table entry for `vector`
+    pub fn trap_vector(self, vector: u8) -> u16 {
+        vector as u16 * self.word_size()
+    }
+
+    /// Address of the interrupt vector table entry for `vector`
+    pub fn interrupt_vector(self, vector: u8) -> u16 {
+        self.interrupt_vector_table() + vector as u16 * self.word_size()
+    }
+
+    /// First address after the trap and interrupt vector tables
+    pub fn vector_tables_end(self) -> u16 {
+        self.interrupt_vector(0xFF) + self.word_size()
+    }
+
+    fn interrupt_vector_table(self) -> u16 {
+        INTERRUPT_VECTOR_TABLE * self.word_size()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownIsa(pub String);
+
+impl fmt::Display for UnknownIsa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown instruction set `{}`", self.0)
+    }
+}
+
+impl FromStr for Isa {
+    type Err = UnknownIsa;
+
+    /// Parses `LC3` or `LC3b`, with or without the dash and in any case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().replace('-', "").as_str() {
+            "LC3" => Ok(Isa::Lc3),
+            #[cfg(feature = "lc3b")]
+            "LC3B" => Ok(Isa::Lc3b),
+            _ => Err(UnknownIsa(s.to_string())),
+        }
+    }
+}
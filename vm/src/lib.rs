@@ -1,9 +1,47 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod block;
+pub mod builder;
+pub mod cache;
+pub mod call_stack;
+pub mod console;
+pub mod decode;
+pub mod disk;
+pub mod framebuffer;
+pub mod harness;
 pub mod instruction;
+pub mod isa;
 pub mod memory;
+pub mod microarchitecture;
+pub mod observer;
+pub mod profiler;
 pub mod register;
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod socket;
+pub mod symbols;
 pub mod vm;
 
+pub use crate::block::*;
+pub use crate::builder::*;
+pub use crate::cache::*;
+pub use crate::call_stack::*;
+pub use crate::console::*;
+pub use crate::decode::*;
+pub use crate::disk::*;
+pub use crate::framebuffer::*;
+pub use crate::harness::*;
 pub use crate::instruction::*;
+pub use crate::isa::*;
 pub use crate::memory::*;
+pub use crate::microarchitecture::*;
+pub use crate::observer::*;
+pub use crate::profiler::*;
 pub use crate::register::*;
+pub use crate::replay::*;
+#[cfg(feature = "std")]
+pub use crate::socket::*;
+pub use crate::symbols::*;
 pub use crate::vm::*;
@@ -1,36 +1,412 @@
 use std::env;
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
 
-use vm::VirtualMachine;
+use vm::{
+    Backtrace, Cache, CacheConfig, Console, Disk, FileStorage, Frame, Framebuffer, HaltReason,
+    Harness, InputLog, InputRecorder, Isa, Microarchitecture, PrivilegeMode, Profiler,
+    SocketConsole, Symbols, VirtualMachine,
+};
+
+mod terminal;
 
 // TODO: add different file formats that can be passed to the Vm
 // - default is a binary file (.obj)
 // - also allow a text file with hex values to be passed in
 
+const USAGE: &str =
+    "[--headless] [--isa <lc3|lc3b>] [--mode <user|supervisor>] [--os <image.obj>] [--decode-cache] [--blocks] [--micro] [--input <text>] [--input-file <file>] [--record <file>] [--replay <file>] [--frame <file.png|file.ppm>] [--preview] [--disk <file>] [--console <unix:path|tcp:port>] [--limit <count>] [--icache <config>] [--dcache <config>] [--cache-region <name=xSTART-xEND>] [--break <address|label>] [--sym <file.sym>] [--profile] <file.obj>";
+
+#[derive(Default)]
+struct Options {
+    file_path: String,
+    headless: bool,
+    isa: Isa,
+    mode: Option<PrivilegeMode>,
+    os_image: Option<Vec<u16>>,
+    decode_cache: bool,
+    block_engine: bool,
+    micro: bool,
+    input: Vec<u8>,
+    record: Option<String>,
+    replay: Option<InputLog>,
+    frame: Option<String>,
+    preview: bool,
+    disk: Option<String>,
+    console: Option<String>,
+    limit: Option<u64>,
+    instruction_cache: Option<CacheConfig>,
+    data_cache: Option<CacheConfig>,
+    cache_regions: Vec<(String, RangeInclusive<u16>)>,
+    breakpoints: Vec<u16>,
+    symbols: Symbols,
+    profile: bool,
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Usage: {} <file.obj>", args[0]);
+    let options = match parse_args(&args[1..]) {
+        Some(options) => options,
+        None => panic!("Usage: {} {USAGE}", args[0]),
+    };
+
+    let file_path = &options.file_path;
+    let contents = read_file(file_path).expect("Error reading file");
+
+    if options.headless {
+        run_headless(&options, &contents);
+        return;
     }
 
-    let file_path = &args[1];
     println!("Loading {file_path}");
-
-    let contents = read_file(file_path).expect("Error reading file");
     for line in &contents {
         println!("{:?}", line);
     }
 
-    let mut vm = VirtualMachine::new();
+    // With a socket console the terminal stays in its normal mode
+    let (_raw_mode, console) = match &options.console {
+        Some(address) => (
+            None,
+            socket_console(address).expect("Error opening console socket"),
+        ),
+        None => terminal::console(),
+    };
+    let base_address = 0x3000; // TODO: Grab this from the first line of the asm file
+    let mut builder = VirtualMachine::builder();
+    if let Some(image) = &options.os_image {
+        builder = builder.os_image(image);
+    }
+    builder = builder
+        .program(base_address, &contents)
+        .isa(options.isa)
+        .console(console);
+    if let Some(mode) = options.mode {
+        builder = builder.mode(mode);
+    }
+    if let Some(limit) = options.limit {
+        builder = builder.instruction_limit(limit);
+    }
+    if options.decode_cache {
+        builder = builder.decode_cache();
+    }
+    if options.block_engine {
+        builder = builder.block_engine();
+    }
+    if let Some(log) = &options.replay {
+        builder = builder.replay_input(log.clone());
+    }
+    let recorder = InputRecorder::new();
+    if options.record.is_some() {
+        builder = builder.record_input(&recorder);
+    }
+    let framebuffer = Framebuffer::new();
+    let frame = framebuffer.frame();
+    if options.frame.is_some() || options.preview {
+        builder = builder.device(Box::new(framebuffer));
+    }
+    if let Some(path) = &options.disk {
+        let storage = FileStorage::open(path).expect("Error opening disk image");
+        builder = builder.device(Box::new(Disk::new(Box::new(storage))));
+    }
+
+    let (instruction_cache, data_cache) = caches(&options);
+    if let Some(cache) = instruction_cache {
+        builder = builder.instruction_cache(cache);
+    }
+    if let Some(cache) = data_cache {
+        builder = builder.data_cache(cache);
+    }
 
-    let mut base_address = 0x3000; // TODO: Grab this from the first line of the asm file
-    for line in &contents {
-        vm.memory.write(base_address, *line);
-        base_address += 1;
+    for address in &options.breakpoints {
+        builder = builder.breakpoint(*address);
+    }
+    let profiler = Profiler::new();
+    if options.profile {
+        builder = builder.profile(&profiler);
+    }
+
+    let mut vm = builder.build();
+    let halt_reason = if options.micro {
+        let mut microarchitecture = Microarchitecture::new();
+        let halt_reason = microarchitecture.run(&mut vm);
+        print_cycles(&microarchitecture);
+        halt_reason
+    } else {
+        vm.run()
+    };
+    println!("Halt reason: {halt_reason:?}");
+    print_backtrace(halt_reason, &vm.backtrace(), &options.symbols);
+    print_profile(&options, &profiler);
+    print_caches(vm.memory().instruction_cache(), vm.memory().data_cache());
+    export_frame(&options, &frame.borrow());
+
+    if let Some(path) = &options.record {
+        fs::write(path, recorder.log().to_string()).expect("Error writing replay file");
+    }
+}
+
+/// Runs the program against the given input and prints its output followed
+/// by the final machine state
+fn run_headless(options: &Options, program: &[u16]) {
+    let mut harness = Harness::new().isa(options.isa).input(&options.input);
+    if let Some(mode) = options.mode {
+        harness = harness.mode(mode);
+    }
+    if let Some(image) = &options.os_image {
+        harness = harness.os_image(image);
+    }
+    if let Some(limit) = options.limit {
+        harness = harness.instruction_limit(limit);
+    }
+    if let Some(log) = &options.replay {
+        harness = harness.replay(log.clone());
+    }
+    if options.frame.is_some() || options.preview {
+        harness = harness.framebuffer();
+    }
+    if options.decode_cache {
+        harness = harness.decode_cache();
+    }
+    if options.block_engine {
+        harness = harness.block_engine();
+    }
+    if options.micro {
+        harness = harness.microarchitecture();
+    }
+    let (instruction_cache, data_cache) = caches(options);
+    if let Some(cache) = instruction_cache {
+        harness = harness.instruction_cache(cache);
+    }
+    if let Some(cache) = data_cache {
+        harness = harness.data_cache(cache);
+    }
+    for address in &options.breakpoints {
+        harness = harness.breakpoint(*address);
+    }
+    let profiler = Profiler::new();
+    if options.profile {
+        harness = harness.profile(&profiler);
+    }
+
+    let result = harness.run(program);
+    println!("{}", String::from_utf8_lossy(&result.output));
+    result.registers.dump();
+    println!("Instructions: {}", result.instruction_count);
+    if let Some(cycles) = result.cycles {
+        println!("Cycles: {cycles}");
+    }
+    println!("Halt reason: {:?}", result.halt_reason);
+    print_backtrace(result.halt_reason, &result.backtrace, &options.symbols);
+    print_profile(options, &profiler);
+    print_caches(
+        result.instruction_cache.as_ref(),
+        result.data_cache.as_ref(),
+    );
+    if let Some(frame) = &result.frame {
+        export_frame(options, frame);
     }
+}
+
+/// The `--icache` and `--dcache` caches, each keeping statistics for every
+/// `--cache-region`
+fn caches(options: &Options) -> (Option<Cache>, Option<Cache>) {
+    let cache = |config: &Option<CacheConfig>| {
+        let mut cache = Cache::new((*config)?);
+        for (name, addresses) in &options.cache_regions {
+            cache = cache.region(name, addresses.clone());
+        }
+        Some(cache)
+    };
+    (
+        cache(&options.instruction_cache),
+        cache(&options.data_cache),
+    )
+}
+
+fn print_caches(instruction_cache: Option<&Cache>, data_cache: Option<&Cache>) {
+    if let Some(cache) = instruction_cache {
+        print!("Instruction cache: {cache}");
+    }
+    if let Some(cache) = data_cache {
+        print!("Data cache: {cache}");
+    }
+}
+
+/// Shows where the program was when it faulted, hit a breakpoint or ran out
+/// of instructions
+///
+/// Frames are labelled only with the symbols loaded by `--sym`, and show bare
+/// addresses without it.
+fn print_backtrace(halt_reason: HaltReason, backtrace: &Backtrace, symbols: &Symbols) {
+    if let HaltReason::Exception(_) | HaltReason::Breakpoint(_) | HaltReason::InstructionLimit =
+        halt_reason
+    {
+        print!("Backtrace:\n{}", backtrace.display(symbols));
+    }
+}
+
+fn print_profile(options: &Options, profiler: &Profiler) {
+    if options.profile {
+        print!("Profile:\n{}", profiler.display(&options.symbols));
+    }
+}
+
+fn print_cycles(microarchitecture: &Microarchitecture) {
+    println!(
+        "Cycles: {} ({} waiting for memory)",
+        microarchitecture.cycles(),
+        microarchitecture.wait_states()
+    );
+}
+
+/// Waits for a peer to connect to the console socket at `address`, either
+/// `unix:<path>` or `tcp:<port>` on localhost
+fn socket_console(address: &str) -> io::Result<Box<dyn Console>> {
+    if let Some(port) = address.strip_prefix("tcp:") {
+        let port = port
+            .parse::<u16>()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        eprintln!(
+            "Waiting for a console connection on {}",
+            listener.local_addr()?
+        );
+        return Ok(Box::new(SocketConsole::accept_tcp(&listener)?));
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix("unix:") {
+        // A socket left behind by an earlier run would make bind fail
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        eprintln!("Waiting for a console connection on {path}");
+        let console = SocketConsole::accept_unix(&listener);
+        let _ = fs::remove_file(path);
+        return Ok(Box::new(console?));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unsupported console address `{address}`"),
+    ))
+}
+
+/// Writes the frame to the `--frame` file, as PNG or PPM depending on its
+/// extension, and prints it for `--preview`
+fn export_frame(options: &Options, frame: &Frame) {
+    if let Some(path) = &options.frame {
+        let image = if path.ends_with(".png") {
+            frame.to_png()
+        } else {
+            frame.to_ppm()
+        };
+        fs::write(path, image).expect("Error writing frame");
+    }
+    if options.preview {
+        print!("{}", frame.preview());
+    }
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut options = Options::default();
+    let mut file_path = None;
+    let mut breakpoints = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => options.headless = true,
+            "--isa" => options.isa = args.next()?.parse().ok()?,
+            "--mode" => options.mode = Some(parse_mode(args.next()?)?),
+            "--os" => options.os_image = Some(read_file(args.next()?).ok()?),
+            "--decode-cache" => options.decode_cache = true,
+            "--blocks" => options.block_engine = true,
+            "--micro" => options.micro = true,
+            "--input" => options.input = args.next()?.as_bytes().to_vec(),
+            "--input-file" => options.input = fs::read(args.next()?).ok()?,
+            "--record" => options.record = Some(args.next()?.clone()),
+            "--replay" => {
+                options.replay = Some(fs::read_to_string(args.next()?).ok()?.parse().ok()?)
+            }
+            "--frame" => options.frame = Some(args.next()?.clone()),
+            "--preview" => options.preview = true,
+            "--disk" => options.disk = Some(args.next()?.clone()),
+            "--console" => options.console = Some(args.next()?.clone()),
+            "--limit" => options.limit = Some(args.next()?.parse().ok()?),
+            "--icache" => options.instruction_cache = Some(args.next()?.parse().ok()?),
+            "--dcache" => options.data_cache = Some(args.next()?.parse().ok()?),
+            "--cache-region" => options.cache_regions.push(parse_region(args.next()?)?),
+            "--break" => breakpoints.push(args.next()?),
+            "--sym" => options.symbols = fs::read_to_string(args.next()?).ok()?.parse().ok()?,
+            "--profile" => options.profile = true,
+            _ if file_path.is_none() => file_path = Some(arg.clone()),
+            _ => return None,
+        }
+    }
+
+    // Headless input is scripted already, so there is nothing to record, and
+    // headless runs only get the built-in devices, the framebuffer and the
+    // scripted console
+    if options.headless
+        && (options.record.is_some() || options.disk.is_some() || options.console.is_some())
+    {
+        return None;
+    }
+
+    // Without an operating system only supervisor mode can drive the disk
+    if options.disk.is_some() && options.mode.is_none() && options.os_image.is_none() {
+        options.mode = Some(PrivilegeMode::Privileged);
+    }
+
+    // The microsequencer only models the LC-3
+    if options.micro && options.isa != Isa::Lc3 {
+        return None;
+    }
+
+    // Labels win over addresses, since a label such as ADD is also hex
+    for breakpoint in breakpoints {
+        let address = options.symbols.address(breakpoint);
+        options
+            .breakpoints
+            .push(address.or_else(|| parse_address(breakpoint))?);
+    }
+
+    options.file_path = file_path?;
+    Some(options)
+}
+
+/// Parses the `--mode` a program starts in. Programs without an operating
+/// system that poll the device registers have to start in supervisor mode.
+fn parse_mode(mode: &str) -> Option<PrivilegeMode> {
+    match mode {
+        "user" => Some(PrivilegeMode::User),
+        "supervisor" => Some(PrivilegeMode::Privileged),
+        _ => None,
+    }
+}
+
+/// Parses `name=xSTART-xEND`, an inclusive range of hex addresses
+fn parse_region(region: &str) -> Option<(String, RangeInclusive<u16>)> {
+    let (name, range) = region.split_once('=')?;
+    let (start, end) = range.split_once('-')?;
+    Some((
+        name.to_string(),
+        parse_address(start)?..=parse_address(end)?,
+    ))
+}
 
-    vm.run();
+/// Parses a hex address, with or without the leading `x`
+fn parse_address(address: &str) -> Option<u16> {
+    let digits = address.strip_prefix(['x', 'X']).unwrap_or(address);
+    u16::from_str_radix(digits, 16).ok()
 }
 
 fn read_file(filename: &str) -> std::io::Result<Vec<u16>> {
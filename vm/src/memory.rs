@@ -1,14 +1,96 @@
-pub const MEMORY_SIZE: usize = u16::MAX as usize;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::cache::Cache;
+use crate::console::{default_console, Console};
+use crate::decode::{DecodeCache, Instruction};
+use crate::isa::Isa;
+
+pub const MEMORY_SIZE: usize = u16::MAX as usize + 1;
 pub const UNPRIVILEGED_MEMORY: u16 = 0x3000;
 
+/// Keyboard status register
+pub const KBSR: u16 = 0xFE00;
+/// Keyboard data register
+pub const KBDR: u16 = 0xFE02;
+/// Display status register
+pub const DSR: u16 = 0xFE04;
+/// Display data register
+pub const DDR: u16 = 0xFE06;
+/// Machine control register
+pub const MCR: u16 = 0xFFFE;
+
+/// Bit [15] of the device status registers and the machine control register
+pub const STATUS_READY: u16 = 1 << 15;
+
+/// A memory-mapped device attached to the memory bus. Reads and writes to the
+/// addresses it claims are routed to it instead of to memory.
+pub trait Device {
+    /// Returns true if the device owns `address`
+    fn contains(&self, address: u16) -> bool;
+
+    fn read(&mut self, address: u16) -> u16;
+
+    fn write(&mut self, address: u16, value: u16);
+
+    /// Advances the device by `instructions` executed instructions. Devices
+    /// that transfer data without the processor (DMA) do it here through
+    /// `memory`, which no longer routes to any device while this runs.
+    fn tick(&mut self, _memory: &mut Memory, _instructions: u64) {}
+
+    /// The interrupt the device is requesting, if any
+    fn interrupt(&self) -> Option<Interrupt> {
+        None
+    }
+
+    /// Called when the processor starts servicing the device's interrupt
+    fn acknowledge(&mut self) {}
+}
+
+/// A device interrupt request. The service routine's address is at
+/// `INTERRUPT_VECTOR_TABLE + vector` and it runs at `priority`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interrupt {
+    pub vector: u8,
+    /// Only taken when higher than the priority in PSR\[10:8\]
+    pub priority: u8,
+}
+
 pub struct Memory {
     memory: [u16; MEMORY_SIZE],
+    console: Box<dyn Console>,
+    devices: Vec<Box<dyn Device>>,
+    isa: Isa,
+    keyboard_ready: bool,
+    keyboard_data: u16,
+    decode_cache: Option<DecodeCache>,
+    instruction_cache: Option<Cache>,
+    data_cache: Option<Cache>,
+    translated: Option<Box<[bool]>>,
+    invalidated: Vec<u16>,
 }
 
 impl Memory {
     pub fn new() -> Self {
+        Self::with_console(default_console())
+    }
+
+    pub fn with_console(console: Box<dyn Console>) -> Self {
+        let mut memory = [0; MEMORY_SIZE];
+        memory[MCR as usize] = STATUS_READY;
         Self {
-            memory: [0; MEMORY_SIZE],
+            memory,
+            console,
+            devices: Vec::new(),
+            isa: Isa::Lc3,
+            keyboard_ready: false,
+            keyboard_data: 0,
+            decode_cache: None,
+            instruction_cache: None,
+            data_cache: None,
+            translated: None,
+            invalidated: Vec::new(),
         }
     }
 
@@ -16,20 +98,285 @@ impl Memory {
         todo!();
     }
 
-    pub fn read(&self, address: u16) -> u16 {
-        self.memory[address as usize]
+    pub fn read(&mut self, address: u16) -> u16 {
+        if self.data_cache.is_some() && !self.is_device(address) {
+            if let Some(cache) = &mut self.data_cache {
+                cache.read(address);
+            }
+        }
+        self.read_uncached(address)
+    }
+
+    fn read_uncached(&mut self, address: u16) -> u16 {
+        if let Some(device) = self.device_mut(address) {
+            return device.read(address);
+        }
+
+        match address {
+            KBSR => {
+                if !self.keyboard_ready && self.console.poll() {
+                    if let Some(byte) = self.console.read() {
+                        self.keyboard_data = byte as u16;
+                        self.keyboard_ready = true;
+                    }
+                }
+                if self.keyboard_ready {
+                    STATUS_READY
+                } else {
+                    0
+                }
+            }
+            KBDR => {
+                self.keyboard_ready = false;
+                self.keyboard_data
+            }
+            DSR => STATUS_READY,
+            DDR => 0,
+            _ => self.memory[address as usize],
+        }
     }
 
     pub fn write(&mut self, address: u16, value: u16) {
-        self.memory[address as usize] = value;
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(address);
+        }
+        if let Some(translated) = &mut self.translated {
+            if translated[address as usize] {
+                translated[address as usize] = false;
+                self.invalidated.push(address);
+            }
+        }
+        if self.data_cache.is_some() && !self.is_device(address) {
+            if let Some(cache) = &mut self.data_cache {
+                cache.write(address);
+            }
+        }
+        if let Some(device) = self.device_mut(address) {
+            device.write(address, value);
+            return;
+        }
+
+        match address {
+            KBSR | KBDR | DSR => {}
+            DDR => {
+                self.console.write(value as u8);
+                self.console.flush();
+            }
+            _ => self.memory[address as usize] = value,
+        }
     }
 
-    pub fn is_privileged(&self, address: u16) -> bool {
-        if address < UNPRIVILEGED_MEMORY {
-            true
+    /// Writes `words` starting at `origin`, one word apart
+    pub fn load(&mut self, origin: u16, words: &[u16]) {
+        let addresses =
+            (origin as usize..=u16::MAX as usize).step_by(self.isa.word_size() as usize);
+        for (address, word) in addresses.zip(words) {
+            self.write(address as u16, *word);
+        }
+    }
+
+    /// Reads the byte at `address` of LC-3b byte-addressed memory, the low
+    /// half of the word at an even address or the high half at an odd one
+    #[cfg(feature = "lc3b")]
+    pub fn read_byte(&mut self, address: u16) -> u8 {
+        let word = self.read(address & !1);
+        (word >> (8 * (address & 1))) as u8
+    }
+
+    /// Writes the byte at `address` of LC-3b byte-addressed memory, keeping
+    /// the other half of the word. Device registers only get the byte, in the
+    /// half of the word it belongs to.
+    #[cfg(feature = "lc3b")]
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        let aligned = address & !1;
+        let shift = 8 * (address & 1);
+        let word = if self.is_device(aligned) {
+            (value as u16) << shift
         } else {
-            false
+            let kept = self.memory[aligned as usize] & !(0xFF << shift);
+            kept | (value as u16) << shift
+        };
+        self.write(aligned, word);
+    }
+
+    /// Attaches a memory-mapped device. Devices attached earlier take
+    /// precedence where address ranges overlap.
+    pub fn attach(&mut self, device: Box<dyn Device>) {
+        self.devices.push(device);
+    }
+
+    pub(crate) fn has_devices(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    /// Lets every attached device run for `instructions` instructions
+    pub(crate) fn tick(&mut self, instructions: u64) {
+        let mut devices = core::mem::take(&mut self.devices);
+        let data_cache = self.data_cache.take();
+        for device in &mut devices {
+            device.tick(self, instructions);
+        }
+        self.devices = devices;
+        self.data_cache = data_cache;
+    }
+
+    /// Acknowledges and returns the highest priority interrupt request above
+    /// `priority`
+    pub(crate) fn take_interrupt(&mut self, priority: u8) -> Option<Interrupt> {
+        let (device, interrupt) = self
+            .devices
+            .iter_mut()
+            .filter_map(|device| Some((device.interrupt()?, device)))
+            .filter(|(interrupt, _)| interrupt.priority > priority)
+            .max_by_key(|(interrupt, _)| interrupt.priority)
+            .map(|(interrupt, device)| (device, interrupt))?;
+        device.acknowledge();
+        Some(interrupt)
+    }
+
+    fn device_mut(&mut self, address: u16) -> Option<&mut Box<dyn Device>> {
+        if self.devices.is_empty() {
+            return None;
         }
+        self.devices
+            .iter_mut()
+            .find(|device| device.contains(address))
+    }
+
+    /// True for the device registers, where reads can have side effects
+    pub fn is_device(&self, address: u16) -> bool {
+        address >= KBSR || self.devices.iter().any(|device| device.contains(address))
+    }
+
+    /// Reads and decodes the instruction at `address`
+    pub fn fetch(&mut self, address: u16) -> (u16, Instruction) {
+        // Only plain memory is ever cached, so a hit needs neither the device
+        // checks nor a read
+        if let Some(hit) = self.cached(address) {
+            return hit;
+        }
+        self.cache_fetch(address);
+        match self.decode_cache.take() {
+            Some(mut cache) if !self.is_device(address) => {
+                let fetched = cache.get(address, || self.read_uncached(address));
+                self.decode_cache = Some(cache);
+                fetched
+            }
+            cache => {
+                self.decode_cache = cache;
+                let instruction = self.read_uncached(address);
+                (instruction, self.isa.decode(instruction))
+            }
+        }
+    }
+
+    /// The decoded instruction at `address` if it is already in the decode
+    /// cache. Always `None` while an instruction cache has to see fetches.
+    pub(crate) fn cached(&self, address: u16) -> Option<(u16, Instruction)> {
+        match &self.decode_cache {
+            Some(cache) if self.instruction_cache.is_none() => cache.cached(address),
+            _ => None,
+        }
+    }
+
+    /// Reads the instruction at `address` without decoding it
+    pub(crate) fn fetch_word(&mut self, address: u16) -> u16 {
+        self.cache_fetch(address);
+        self.read_uncached(address)
+    }
+
+    fn cache_fetch(&mut self, address: u16) {
+        if self.instruction_cache.is_some() && !self.is_device(address) {
+            if let Some(cache) = &mut self.instruction_cache {
+                cache.read(address);
+            }
+        }
+    }
+
+    /// Keeps decoded instructions around between fetches. Any write to memory
+    /// drops the cached instruction at that address. On the loop in
+    /// `benches/interpreter.rs` this is about 3x faster than decoding every
+    /// fetch.
+    pub fn enable_decode_cache(&mut self) {
+        let isa = self.isa;
+        self.decode_cache
+            .get_or_insert_with(|| DecodeCache::with_isa(isa));
+    }
+
+    /// Runs instruction fetches through `cache`. Only the statistics of the
+    /// cache are simulated, instructions still come straight from memory.
+    pub fn set_instruction_cache(&mut self, cache: Option<Cache>) {
+        self.instruction_cache = cache;
+    }
+
+    /// Runs `read` and `write` through `cache`, except for the device
+    /// registers, which are never cached. DMA transfers by devices bypass it.
+    pub fn set_data_cache(&mut self, cache: Option<Cache>) {
+        self.data_cache = cache;
+    }
+
+    pub fn instruction_cache(&self) -> Option<&Cache> {
+        self.instruction_cache.as_ref()
+    }
+
+    pub fn data_cache(&self) -> Option<&Cache> {
+        self.data_cache.as_ref()
+    }
+
+    pub(crate) fn has_caches(&self) -> bool {
+        self.instruction_cache.is_some() || self.data_cache.is_some()
+    }
+
+    /// Instruction set that fetched instructions are decoded as
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    /// Switches the instruction set. Any decoded instructions are dropped.
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+        if self.decode_cache.is_some() {
+            self.decode_cache = Some(DecodeCache::with_isa(isa));
+        }
+    }
+
+    /// Records that `address` is part of a translated block, so that writing
+    /// to it can invalidate the block
+    pub(crate) fn mark_translated(&mut self, address: u16) {
+        self.translated
+            .get_or_insert_with(|| vec![false; MEMORY_SIZE].into_boxed_slice())[address as usize] =
+            true;
+    }
+
+    /// True if translated code has been written to since the last call to
+    /// `take_invalidated`
+    pub(crate) fn has_invalidated(&self) -> bool {
+        !self.invalidated.is_empty()
+    }
+
+    pub(crate) fn take_invalidated(&mut self) -> Vec<u16> {
+        core::mem::take(&mut self.invalidated)
+    }
+
+    /// MCR\[15\], which stops the machine when it is cleared. Unless a device
+    /// claims MCR this is a plain read, since it is checked after every
+    /// instruction.
+    pub(crate) fn clock_enabled(&mut self) -> bool {
+        let mcr = if self.devices.is_empty() {
+            self.memory[MCR as usize]
+        } else {
+            self.read_uncached(MCR)
+        };
+        mcr & STATUS_READY != 0
+    }
+
+    /// System space and the device registers can only be accessed in supervisor mode
+    pub fn is_privileged(&self, address: u16) -> bool {
+        !(UNPRIVILEGED_MEMORY..KBSR).contains(&address)
+    }
+
+    pub fn console_mut(&mut self) -> &mut dyn Console {
+        self.console.as_mut()
     }
 }
 
@@ -0,0 +1,632 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::call_stack::Entry;
+use crate::decode::Instruction;
+use crate::instruction::{self, TrapCode};
+use crate::isa::Isa;
+use crate::memory::Interrupt;
+use crate::register::{PrivilegeMode, Register};
+use crate::vm::{Exception, HaltReason, VirtualMachine};
+
+/// Cycles a memory access takes by default. Memory signals ready (R) on the
+/// last one, so every cycle before it is a wait state.
+pub const MEMORY_CYCLES: u32 = 5;
+
+/// A state of the microsequencer
+///
+/// The states of the instruction cycle follow the LC-3 state machine of Patt &
+/// Patel, Appendix C, and `number` gives their number there. Entering a
+/// service routine reads the vector table before pushing PSR and PC, so that a
+/// missing entry can fall back to the built-in trap routines like the
+/// interpreter does. Those states have no number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Fetch,
+    FetchMemory,
+    LoadIr,
+    Decode,
+    Br,
+    BrTaken,
+    Add,
+    And,
+    Not,
+    Lea,
+    Ld,
+    Ldr,
+    Ldi,
+    LdiMemory,
+    LdiIndirect,
+    LoadMemory,
+    LoadRegister,
+    St,
+    Str,
+    Sti,
+    StiMemory,
+    StiIndirect,
+    StoreData,
+    StoreMemory,
+    Jsr,
+    JsrOffset,
+    JsrRegister,
+    Jmp,
+    Trap,
+    TrapMemory,
+    Rti,
+    RtiPcMemory,
+    RtiPc,
+    RtiPsrAddress,
+    RtiPsrMemory,
+    RtiPsr,
+    RtiStack,
+    Reserved,
+    Exception,
+    Interrupt,
+    VectorMemory,
+    SavePsr,
+    SwitchStack,
+    PushAddress,
+    PushMemory,
+    SavePc,
+    EnterRoutine,
+}
+
+impl State {
+    /// Number of the state in Patt & Patel's state machine
+    pub fn number(self) -> Option<u8> {
+        let number = match self {
+            State::Fetch => 18,
+            State::FetchMemory => 33,
+            State::LoadIr => 35,
+            State::Decode => 32,
+            State::Br => 0,
+            State::BrTaken => 22,
+            State::Add => 1,
+            State::And => 5,
+            State::Not => 9,
+            State::Lea => 14,
+            State::Ld => 2,
+            State::Ldr => 6,
+            State::Ldi => 10,
+            State::LdiMemory => 24,
+            State::LdiIndirect => 26,
+            State::LoadMemory => 25,
+            State::LoadRegister => 27,
+            State::St => 3,
+            State::Str => 7,
+            State::Sti => 11,
+            State::StiMemory => 29,
+            State::StiIndirect => 31,
+            State::StoreData => 23,
+            State::StoreMemory => 16,
+            State::Jsr => 4,
+            State::JsrOffset => 21,
+            State::JsrRegister => 20,
+            State::Jmp => 12,
+            State::Trap => 15,
+            State::TrapMemory => 28,
+            State::Rti => 8,
+            State::RtiPcMemory => 36,
+            State::RtiPc => 38,
+            State::RtiPsrAddress => 39,
+            State::RtiPsrMemory => 40,
+            State::RtiPsr => 42,
+            State::RtiStack => 59,
+            State::Reserved => 13,
+            _ => return None,
+        };
+        Some(number)
+    }
+
+    /// Register transfers of the state
+    pub fn transfer(self) -> &'static str {
+        match self {
+            State::Fetch => "MAR <- PC, PC <- PC + 1",
+            State::FetchMemory => "MDR <- M[MAR]",
+            State::LoadIr => "IR <- MDR",
+            State::Decode => "BEN <- IR[11] & N + IR[10] & Z + IR[9] & P, [IR[15:12]]",
+            State::Br => "[BEN]",
+            State::BrTaken => "PC <- PC + off9",
+            State::Add => "DR <- SR1 + OP2, set CC",
+            State::And => "DR <- SR1 & OP2, set CC",
+            State::Not => "DR <- NOT(SR), set CC",
+            State::Lea => "DR <- PC + off9",
+            State::Ld | State::Ldi | State::St | State::Sti => "MAR <- PC + off9, [ACV]",
+            State::Ldr | State::Str => "MAR <- B + off6, [ACV]",
+            State::LdiMemory | State::LoadMemory | State::StiMemory => "MDR <- M[MAR]",
+            State::LdiIndirect | State::StiIndirect => "MAR <- MDR, [ACV]",
+            State::LoadRegister => "DR <- MDR, set CC",
+            State::StoreData => "MDR <- SR",
+            State::StoreMemory => "M[MAR] <- MDR",
+            State::Jsr => "TEMP <- PC, [IR[11]]",
+            State::JsrOffset => "R7 <- TEMP, PC <- PC + off11",
+            State::JsrRegister => "R7 <- TEMP, PC <- BaseR",
+            State::Jmp => "PC <- BaseR",
+            State::Trap => "MAR <- ZEXT[IR[7:0]]",
+            State::TrapMemory | State::VectorMemory => "MDR <- M[MAR]",
+            State::Rti => "MAR <- R6, [PSR[15]]",
+            State::RtiPcMemory | State::RtiPsrMemory => "MDR <- M[MAR]",
+            State::RtiPc => "PC <- MDR",
+            State::RtiPsrAddress => "MAR, R6 <- R6 + 1",
+            State::RtiPsr => "PSR <- MDR, R6 <- R6 + 1, [PSR[15]]",
+            State::RtiStack => "Saved.SSP <- R6, R6 <- Saved.USP",
+            State::Reserved => "illegal opcode exception",
+            State::Exception | State::Interrupt => "MAR <- x0100 + vector",
+            State::SavePsr => "MDR <- PSR, [PSR[15]]",
+            State::SwitchStack => "Saved.USP <- R6, R6 <- Saved.SSP, PSR[15] <- 0",
+            State::PushAddress => "MAR, R6 <- R6 - 1",
+            State::PushMemory => "M[MAR] <- MDR",
+            State::SavePc => "MDR <- PC",
+            State::EnterRoutine => "PC <- vector table entry",
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.number() {
+            Some(number) => write!(f, "{number:2}: {}", self.transfer()),
+            None => write!(f, "  : {}", self.transfer()),
+        }
+    }
+}
+
+/// The datapath latches at the end of a cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    pub state: State,
+    /// Value gated onto the bus, if any
+    pub bus: Option<u16>,
+    pub mar: u16,
+    pub mdr: u16,
+    pub ir: u16,
+    pub ben: bool,
+    /// True while the state waits for memory to become ready
+    pub wait: bool,
+}
+
+/// The cycles of one instruction, from fetch through any interrupt taken
+/// after it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstructionCycles {
+    /// Address the instruction was fetched from
+    pub pc: u16,
+    pub cycles: Vec<Cycle>,
+}
+
+impl InstructionCycles {
+    pub fn count(&self) -> u64 {
+        self.cycles.len() as u64
+    }
+
+    /// Cycles spent waiting for memory
+    pub fn wait_states(&self) -> u64 {
+        self.cycles.iter().filter(|cycle| cycle.wait).count() as u64
+    }
+}
+
+/// Runs a machine through the LC-3 microsequencer one cycle at a time instead
+/// of one instruction at a time
+///
+/// Architectural results are the same as those of `VirtualMachine::step`,
+/// including observer callbacks, devices and the built-in trap routines, which
+/// take no cycles. An interrupt requested by a device is initiated after the
+/// instruction during which it was requested, and its cycles are counted with
+/// that instruction. Only the LC-3 instruction set is modeled.
+///
+/// ```
+/// use vm::{Microarchitecture, State, VirtualMachine};
+///
+/// // ADD R0, R0, #1
+/// let mut vm = VirtualMachine::builder().program(0x3000, &[0x1021]).build();
+/// let mut microarchitecture = Microarchitecture::new().memory_cycles(1);
+/// let instruction = microarchitecture.step(&mut vm);
+///
+/// let states: Vec<_> = instruction.cycles.iter().map(|cycle| cycle.state).collect();
+/// assert_eq!(
+///     vec![State::Fetch, State::FetchMemory, State::LoadIr, State::Decode, State::Add],
+///     states
+/// );
+/// assert_eq!(Some(0x3000), instruction.cycles[0].bus);
+/// ```
+pub struct Microarchitecture {
+    mar: u16,
+    mdr: u16,
+    ben: bool,
+    /// Service routine address read from a vector table, latched while PSR
+    /// and PC are pushed
+    routine: u16,
+    memory_cycles: u32,
+    cycles: u64,
+    wait_states: u64,
+    current: Vec<Cycle>,
+}
+
+impl Microarchitecture {
+    pub fn new() -> Self {
+        Self {
+            mar: 0,
+            mdr: 0,
+            ben: false,
+            routine: 0,
+            memory_cycles: MEMORY_CYCLES,
+            cycles: 0,
+            wait_states: 0,
+            current: Vec::new(),
+        }
+    }
+
+    /// Cycles each memory access takes, at least one
+    pub fn memory_cycles(mut self, cycles: u32) -> Self {
+        self.memory_cycles = cycles.max(1);
+        self
+    }
+
+    pub fn mar(&self) -> u16 {
+        self.mar
+    }
+
+    pub fn mdr(&self) -> u16 {
+        self.mdr
+    }
+
+    pub fn ben(&self) -> bool {
+        self.ben
+    }
+
+    /// Cycles run so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Cycles run so far that were spent waiting for memory
+    pub fn wait_states(&self) -> u64 {
+        self.wait_states
+    }
+
+    /// Runs until the machine halts and returns the reason it stopped, the
+    /// same way `VirtualMachine::run` does
+    pub fn run(&mut self, vm: &mut VirtualMachine) -> HaltReason {
+        let mut resumed = true;
+        loop {
+            if let Some(reason) = vm.halt_reason() {
+                vm.memory.console_mut().flush();
+                return reason;
+            }
+            if vm
+                .instruction_limit()
+                .is_some_and(|limit| vm.instruction_count() >= limit)
+            {
+                vm.halt(HaltReason::InstructionLimit);
+                continue;
+            }
+            if !core::mem::take(&mut resumed) && vm.check_breakpoint() {
+                continue;
+            }
+            self.step(vm);
+        }
+    }
+
+    /// Runs the next instruction and returns its cycles
+    ///
+    /// The microsequencer only models the LC-3, so other ISAs halt with
+    /// `HaltReason::UnsupportedIsa` instead.
+    pub fn step(&mut self, vm: &mut VirtualMachine) -> InstructionCycles {
+        if vm.isa() != Isa::Lc3 {
+            vm.halt(HaltReason::UnsupportedIsa(vm.isa()));
+        }
+        let pc = vm.registers.get(Register::PC);
+        if vm.halt_reason().is_some() {
+            return InstructionCycles {
+                pc,
+                cycles: Vec::new(),
+            };
+        }
+
+        self.mar = pc;
+        vm.registers.set(Register::PC, pc.wrapping_add(1));
+        self.cycle(vm, State::Fetch, Some(pc));
+
+        // Instruction fetches are not data reads, so observers don't see them
+        self.wait(vm, State::FetchMemory);
+        self.mdr = vm.memory.fetch_word(self.mar);
+        self.cycle(vm, State::FetchMemory, None);
+
+        vm.registers.set(Register::IR, self.mdr);
+        vm.notify_fetch(pc, self.mdr);
+        self.cycle(vm, State::LoadIr, Some(self.mdr));
+
+        let ir = self.mdr;
+        let instruction = Instruction::decode(ir);
+        self.ben = (ir >> 9) & vm.registers.get(Register::PSR) & 0x7 != 0;
+        self.cycle(vm, State::Decode, None);
+
+        self.execute(vm, instruction);
+        vm.retire(pc, &instruction);
+        self.service_devices(vm);
+
+        let cycles = core::mem::take(&mut self.current);
+        let instruction = InstructionCycles { pc, cycles };
+        self.cycles += instruction.count();
+        self.wait_states += instruction.wait_states();
+        instruction
+    }
+
+    fn execute(&mut self, vm: &mut VirtualMachine, instruction: Instruction) {
+        let pc = vm.registers.get(Register::PC);
+        match instruction {
+            Instruction::Br { offset, .. } => {
+                self.cycle(vm, State::Br, None);
+                if self.ben {
+                    vm.registers.set(Register::PC, pc.wrapping_add(offset));
+                    self.cycle(vm, State::BrTaken, None);
+                }
+            }
+            Instruction::Add { dr, sr1, operand } => {
+                instruction::add(vm, dr, sr1, operand);
+                self.cycle(vm, State::Add, Some(vm.registers.get(dr)));
+            }
+            Instruction::And { dr, sr1, operand } => {
+                instruction::and(vm, dr, sr1, operand);
+                self.cycle(vm, State::And, Some(vm.registers.get(dr)));
+            }
+            Instruction::Not { dr, sr } => {
+                instruction::not(vm, dr, sr);
+                self.cycle(vm, State::Not, Some(vm.registers.get(dr)));
+            }
+            Instruction::Lea { dr, offset } => {
+                let address = pc.wrapping_add(offset);
+                vm.registers.set(dr, address);
+                self.cycle(vm, State::Lea, Some(address));
+            }
+            Instruction::Ld { dr, offset } => {
+                if self.address(vm, State::Ld, pc.wrapping_add(offset)) {
+                    self.load(vm, dr);
+                }
+            }
+            Instruction::Ldr { dr, base, offset } => {
+                let address = vm.registers.get(base).wrapping_add(offset);
+                if self.address(vm, State::Ldr, address) {
+                    self.load(vm, dr);
+                }
+            }
+            Instruction::Ldi { dr, offset } => {
+                if self.address(vm, State::Ldi, pc.wrapping_add(offset)) {
+                    self.read(vm, State::LdiMemory);
+                    if self.address(vm, State::LdiIndirect, self.mdr) {
+                        self.load(vm, dr);
+                    }
+                }
+            }
+            Instruction::St { sr, offset } => {
+                if self.address(vm, State::St, pc.wrapping_add(offset)) {
+                    self.store(vm, sr);
+                }
+            }
+            Instruction::Str { sr, base, offset } => {
+                let address = vm.registers.get(base).wrapping_add(offset);
+                if self.address(vm, State::Str, address) {
+                    self.store(vm, sr);
+                }
+            }
+            Instruction::Sti { sr, offset } => {
+                if self.address(vm, State::Sti, pc.wrapping_add(offset)) {
+                    self.read(vm, State::StiMemory);
+                    if self.address(vm, State::StiIndirect, self.mdr) {
+                        self.store(vm, sr);
+                    }
+                }
+            }
+            Instruction::Jsr { offset } => {
+                self.cycle(vm, State::Jsr, None);
+                vm.registers.set(Register::R7, pc);
+                vm.registers.set(Register::PC, pc.wrapping_add(offset));
+                vm.enter_call(pc.wrapping_add(offset));
+                self.cycle(vm, State::JsrOffset, None);
+            }
+            Instruction::Jsrr { base } => {
+                self.cycle(vm, State::Jsr, None);
+                let address = vm.registers.get(base);
+                vm.registers.set(Register::R7, pc);
+                vm.registers.set(Register::PC, address);
+                vm.enter_call(address);
+                self.cycle(vm, State::JsrRegister, None);
+            }
+            Instruction::Jmp { base } => {
+                let address = vm.registers.get(base);
+                vm.registers.set(Register::PC, address);
+                if base == Register::R7 {
+                    vm.leave_call(address);
+                }
+                self.cycle(vm, State::Jmp, None);
+            }
+            Instruction::Rti => self.rti(vm),
+            Instruction::Res => {
+                self.cycle(vm, State::Reserved, None);
+                self.exception(vm, Exception::IllegalOpcode);
+            }
+            Instruction::Trap { vector } => {
+                vm.notify_trap(vector);
+                self.mar = vector as u16;
+                self.cycle(vm, State::Trap, Some(self.mar));
+                self.read(vm, State::TrapMemory);
+                if self.mdr != 0 {
+                    self.routine = self.mdr;
+                    self.enter_service_routine(vm, Entry::Trap(vector));
+                    return;
+                }
+                match TrapCode::try_from(vector) {
+                    Ok(code) => instruction::trap_routine(vm, code),
+                    Err(vector) => vm.halt(HaltReason::UnknownTrap(vector)),
+                }
+            }
+            #[cfg(feature = "lc3b")]
+            _ => unreachable!("LC-3b instructions are never decoded"),
+        }
+    }
+
+    fn rti(&mut self, vm: &mut VirtualMachine) {
+        self.mar = vm.registers.get(Register::R6);
+        self.cycle(vm, State::Rti, Some(self.mar));
+        if vm.get_mode() == PrivilegeMode::User {
+            self.exception(vm, Exception::PrivilegeViolation);
+            return;
+        }
+
+        self.read(vm, State::RtiPcMemory);
+        vm.registers.set(Register::PC, self.mdr);
+        self.cycle(vm, State::RtiPc, Some(self.mdr));
+
+        self.mar = self.mar.wrapping_add(1);
+        vm.registers.set(Register::R6, self.mar);
+        self.cycle(vm, State::RtiPsrAddress, Some(self.mar));
+        self.read(vm, State::RtiPsrMemory);
+
+        let psr = self.mdr;
+        vm.registers.set(Register::R6, self.mar.wrapping_add(1));
+        self.cycle(vm, State::RtiPsr, Some(psr));
+        let mode = PrivilegeMode::from_psr(psr);
+        if mode != vm.get_mode() {
+            vm.set_mode(mode);
+            self.cycle(vm, State::RtiStack, None);
+        }
+        vm.registers.set(Register::PSR, psr);
+        vm.leave_service_routine();
+    }
+
+    /// Loads MAR with a data address and returns true if the access is
+    /// allowed. Otherwise the access violation exception is initiated.
+    fn address(&mut self, vm: &mut VirtualMachine, state: State, address: u16) -> bool {
+        self.mar = address;
+        self.cycle(vm, state, Some(address));
+        if vm.memory.is_privileged(address) && vm.get_mode() == PrivilegeMode::User {
+            self.exception(vm, Exception::AccessViolation);
+            return false;
+        }
+        true
+    }
+
+    fn load(&mut self, vm: &mut VirtualMachine, dr: Register) {
+        self.read(vm, State::LoadMemory);
+        vm.registers.set(dr, self.mdr);
+        vm.registers.set_condition_codes(dr);
+        self.cycle(vm, State::LoadRegister, Some(self.mdr));
+    }
+
+    fn store(&mut self, vm: &mut VirtualMachine, sr: Register) {
+        self.mdr = vm.registers.get(sr);
+        self.cycle(vm, State::StoreData, Some(self.mdr));
+        self.write(vm, State::StoreMemory);
+    }
+
+    fn exception(&mut self, vm: &mut VirtualMachine, exception: Exception) {
+        let vector = exception.vector();
+        vm.notify_interrupt(vector);
+        self.mar = vector;
+        self.cycle(vm, State::Exception, Some(vector));
+        self.read(vm, State::VectorMemory);
+        if self.mdr == 0 {
+            vm.halt(HaltReason::Exception(exception));
+        } else {
+            self.routine = self.mdr;
+            self.enter_service_routine(vm, Entry::Exception(exception));
+        }
+    }
+
+    fn interrupt(&mut self, vm: &mut VirtualMachine, interrupt: Interrupt) {
+        let vector = vm.isa().interrupt_vector(interrupt.vector);
+        vm.notify_interrupt(vector);
+        self.mar = vector;
+        self.cycle(vm, State::Interrupt, Some(vector));
+        self.read(vm, State::VectorMemory);
+        if self.mdr != 0 {
+            self.routine = self.mdr;
+            self.enter_service_routine(vm, Entry::Interrupt(interrupt.vector));
+            vm.set_priority(interrupt.priority);
+        }
+    }
+
+    /// Pushes PSR and PC on the supervisor stack and jumps to the latched
+    /// service routine
+    fn enter_service_routine(&mut self, vm: &mut VirtualMachine, entry: Entry) {
+        let return_address = vm.registers.get(Register::PC);
+        self.mdr = vm.registers.get(Register::PSR);
+        self.cycle(vm, State::SavePsr, Some(self.mdr));
+        if vm.get_mode() == PrivilegeMode::User {
+            vm.set_mode(PrivilegeMode::Privileged);
+            self.cycle(vm, State::SwitchStack, None);
+        }
+        self.push(vm);
+
+        self.mdr = vm.registers.get(Register::PC);
+        self.cycle(vm, State::SavePc, Some(self.mdr));
+        self.push(vm);
+
+        vm.registers.set(Register::PC, self.routine);
+        vm.enter_routine(entry, self.routine, return_address);
+        self.cycle(vm, State::EnterRoutine, Some(self.routine));
+    }
+
+    fn push(&mut self, vm: &mut VirtualMachine) {
+        self.mar = vm.registers.get(Register::R6).wrapping_sub(1);
+        vm.registers.set(Register::R6, self.mar);
+        self.cycle(vm, State::PushAddress, Some(self.mar));
+        self.write(vm, State::PushMemory);
+    }
+
+    /// Lets the devices run for the instruction and initiates the interrupt
+    /// they request, the same way the interpreter does
+    fn service_devices(&mut self, vm: &mut VirtualMachine) {
+        if !vm.memory.has_devices() {
+            return;
+        }
+
+        vm.memory.tick(1);
+        if vm.halt_reason().is_some() {
+            return;
+        }
+        if let Some(interrupt) = vm.memory.take_interrupt(vm.get_priority()) {
+            self.interrupt(vm, interrupt);
+        }
+    }
+
+    /// MDR <- M[MAR], waiting until memory is ready
+    fn read(&mut self, vm: &mut VirtualMachine, state: State) {
+        self.wait(vm, state);
+        self.mdr = vm.read(self.mar);
+        self.cycle(vm, state, None);
+    }
+
+    /// M[MAR] <- MDR, waiting until memory is ready
+    fn write(&mut self, vm: &mut VirtualMachine, state: State) {
+        self.wait(vm, state);
+        vm.write(self.mar, self.mdr);
+        self.cycle(vm, state, None);
+    }
+
+    /// Every cycle of a memory access before the one where memory is ready
+    fn wait(&mut self, vm: &VirtualMachine, state: State) {
+        for _ in 1..self.memory_cycles {
+            self.cycle(vm, state, None);
+            self.current.last_mut().unwrap().wait = true;
+        }
+    }
+
+    fn cycle(&mut self, vm: &VirtualMachine, state: State, bus: Option<u16>) {
+        self.current.push(Cycle {
+            state,
+            bus,
+            mar: self.mar,
+            mdr: self.mdr,
+            ir: vm.registers.get(Register::IR),
+            ben: self.ben,
+            wait: false,
+        });
+    }
+}
+
+impl Default for Microarchitecture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,57 @@
+use crate::decode::Instruction;
+use crate::register::Registers;
+use crate::vm::HaltReason;
+
+/// Callbacks invoked by the `VirtualMachine` as it executes. Every method has
+/// an empty default, so an observer only implements the events it cares about.
+///
+/// Observers see the machine but cannot change it, which keeps tracing,
+/// profiling, coverage and grading tools out of the core.
+///
+/// ```
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// use vm::{Observer, VirtualMachine};
+///
+/// struct Counter(Rc<Cell<u32>>);
+///
+/// impl Observer for Counter {
+///     fn on_mem_write(&mut self, _address: u16, _value: u16) {
+///         self.0.set(self.0.get() + 1);
+///     }
+/// }
+///
+/// // ST R0, #0; HALT
+/// let writes = Rc::new(Cell::new(0));
+/// let mut vm = VirtualMachine::builder()
+///     .program(0x3000, &[0x3000, 0xF025])
+///     .observer(Box::new(Counter(writes.clone())))
+///     .build();
+///
+/// vm.run();
+/// assert_eq!(1, writes.get());
+/// ```
+pub trait Observer {
+    /// An instruction word was fetched from `pc`
+    fn on_fetch(&mut self, _pc: u16, _word: u16) {}
+
+    /// The instruction fetched from `pc` finished executing
+    fn on_execute(&mut self, _pc: u16, _instruction: &Instruction, _registers: &Registers) {}
+
+    /// A load, indirect address, stack pop or trap vector read
+    fn on_mem_read(&mut self, _address: u16, _value: u16) {}
+
+    /// A store or stack push
+    fn on_mem_write(&mut self, _address: u16, _value: u16) {}
+
+    /// A TRAP instruction, before its service routine runs
+    fn on_trap(&mut self, _vector: u8, _registers: &Registers) {}
+
+    /// An exception or interrupt is about to be serviced. `vector` is the
+    /// address of its entry in the interrupt vector table.
+    fn on_interrupt(&mut self, _vector: u16, _registers: &Registers) {}
+
+    /// The machine stopped
+    fn on_halt(&mut self, _reason: HaltReason, _registers: &Registers) {}
+}
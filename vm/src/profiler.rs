@@ -0,0 +1,113 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+
+use crate::decode::Instruction;
+use crate::observer::Observer;
+use crate::register::Registers;
+use crate::symbols::Symbols;
+
+/// Counts the instructions executed at each address. Attach it with
+/// `VirtualMachineBuilder::profile` or `Harness::profile`; the counts can be
+/// read while and after the machine runs.
+///
+/// ```
+/// use vm::{Profiler, Symbols, VirtualMachine};
+///
+/// // x3000: AND R0, R0, #0; ADD R0, R0, #3
+/// // x3002: LOOP ADD R0, R0, #-1; BRp LOOP; HALT
+/// let profiler = Profiler::new();
+/// let mut vm = VirtualMachine::builder()
+///     .program(0x3000, &[0x5020, 0x1023, 0x103F, 0x03FE, 0xF025])
+///     .profile(&profiler)
+///     .build();
+/// vm.run();
+///
+/// let mut symbols = Symbols::new();
+/// symbols.insert("MAIN", 0x3000);
+/// symbols.insert("LOOP", 0x3002);
+/// assert_eq!(
+///     "Instructions  Percent  Location
+///            7    77.8%  LOOP
+///            2    22.2%  MAIN
+/// ",
+///     profiler.display(&symbols).to_string()
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    counts: Rc<RefCell<BTreeMap<u16, u64>>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Instructions executed so far by the address they were fetched from
+    pub fn counts(&self) -> BTreeMap<u16, u64> {
+        self.counts.borrow().clone()
+    }
+
+    /// Instructions executed so far in each routine, most first. Addresses
+    /// are grouped under the closest label at or before them, and shown in
+    /// hex where there is none.
+    pub fn by_label(&self, symbols: &Symbols) -> Vec<(String, u64)> {
+        let mut totals: Vec<(String, u64)> = Vec::new();
+        for (&address, &count) in self.counts.borrow().iter() {
+            let location = match symbols.lookup(address) {
+                Some((name, _)) => String::from(name),
+                None => format!("x{address:04X}"),
+            };
+            match totals.iter_mut().find(|(name, _)| *name == location) {
+                Some((_, total)) => *total += count,
+                None => totals.push((location, count)),
+            }
+        }
+        totals.sort_by_key(|&(_, count)| core::cmp::Reverse(count));
+        totals
+    }
+
+    /// Formats `by_label` as a table with the share of each location
+    pub fn display<'a>(&'a self, symbols: &'a Symbols) -> impl fmt::Display + 'a {
+        DisplayProfile {
+            profiler: self,
+            symbols,
+        }
+    }
+
+    pub(crate) fn observer(&self) -> Box<dyn Observer> {
+        Box::new(ProfileObserver(Rc::clone(&self.counts)))
+    }
+}
+
+struct ProfileObserver(Rc<RefCell<BTreeMap<u16, u64>>>);
+
+impl Observer for ProfileObserver {
+    fn on_execute(&mut self, pc: u16, _instruction: &Instruction, _registers: &Registers) {
+        *self.0.borrow_mut().entry(pc).or_default() += 1;
+    }
+}
+
+struct DisplayProfile<'a> {
+    profiler: &'a Profiler,
+    symbols: &'a Symbols,
+}
+
+impl fmt::Display for DisplayProfile<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let totals = self.profiler.by_label(self.symbols);
+        let all: u64 = totals.iter().map(|(_, count)| count).sum();
+        writeln!(f, "Instructions  Percent  Location")?;
+        for (location, count) in totals {
+            let percent = count as f64 * 100.0 / all as f64;
+            writeln!(f, "{count:>12}  {percent:>6.1}%  {location}")?;
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
-use std::str::FromStr;
+use core::fmt;
+use core::str::FromStr;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u16)]
 pub enum Register {
     R0,
@@ -14,6 +15,33 @@ pub enum Register {
     PC,
     IR,
     PSR,
+    /// User stack pointer, saved while running in supervisor mode
+    SavedUSP,
+    /// Supervisor stack pointer, saved while running in user mode
+    SavedSSP,
+}
+
+/// Number of registers in the register file
+pub const REGISTER_COUNT: usize = 13;
+
+impl Register {
+    /// General purpose registers in index order
+    pub const GPRS: [Register; 8] = [
+        Register::R0,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+    ];
+
+    /// General purpose register from a 3-bit instruction field. Only the low
+    /// three bits of `index` are used.
+    pub fn gpr(index: u16) -> Self {
+        Self::GPRS[(index & 0x7) as usize]
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -49,7 +77,7 @@ impl From<Register> for u16 {
 /// Bit 2: Negative
 /// Bit 1: Zero
 /// Bit 0: Positive
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u16)]
 pub enum ConditionalFlag {
     Positive = 1 << 0,
@@ -63,115 +91,171 @@ impl From<ConditionalFlag> for u16 {
     }
 }
 
-#[derive(Debug, Default)]
+/// Bit \[15\] of the PSR register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum PrivilegeMode {
+    Privileged = 0,
+    User = 1,
+}
+
+impl PrivilegeMode {
+    pub fn from_psr(psr: u16) -> Self {
+        if psr & PSR_PRIVILEGE == 0 {
+            PrivilegeMode::Privileged
+        } else {
+            PrivilegeMode::User
+        }
+    }
+}
+
+impl From<PrivilegeMode> for u16 {
+    fn from(mode: PrivilegeMode) -> Self {
+        mode as u16
+    }
+}
+
+const PSR_PRIVILEGE: u16 = 1 << 15;
+const PSR_PRIORITY: u16 = 0x7 << 8;
+const PSR_CONDITION: u16 = 0x7;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Registers {
-    r0: u16,
-    r1: u16,
-    r2: u16,
-    r3: u16,
-    r4: u16,
-    r5: u16,
-    r6: u16,
-    r7: u16,
-    pc: u16,
-    ir: u16,
-    psr: u16,
+    registers: [u16; REGISTER_COUNT],
 }
 
 impl Registers {
+    /// Programs start at x3000 in user mode with priority 0 and the Z flag set.
+    /// The supervisor stack starts just below the user program.
     pub fn new() -> Self {
-        Self {
-            r0: 0,
-            r1: 0,
-            r2: 0,
-            r3: 0,
-            r4: 0,
-            r5: 0,
-            r6: 0,
-            r7: 0,
-            pc: 0x3000,
-            ir: 0,
-            psr: 0x8002,
-        }
+        let mut registers = Self {
+            registers: [0; REGISTER_COUNT],
+        };
+        registers.set(Register::PC, 0x3000);
+        registers.set(Register::PSR, 0x8002);
+        registers.set(Register::SavedSSP, 0x3000);
+        registers
     }
 
-    pub fn get(&self, register: u16) -> u16 {
-        match register {
-            0 => self.r0,
-            1 => self.r1,
-            2 => self.r2,
-            3 => self.r3,
-            4 => self.r4,
-            5 => self.r5,
-            6 => self.r6,
-            7 => self.r7,
-            8 => self.pc,
-            9 => self.ir,
-            10 => self.psr,
-            _ => panic!("Can't get unknown register {register}"),
-        }
+    pub fn get(&self, register: Register) -> u16 {
+        self.registers[register as usize]
     }
 
-    pub fn set(&mut self, register: u16, value: u16) {
-        match register {
-            0 => self.r0 = value,
-            1 => self.r1 = value,
-            2 => self.r2 = value,
-            3 => self.r3 = value,
-            4 => self.r4 = value,
-            5 => self.r5 = value,
-            6 => self.r6 = value,
-            7 => self.r7 = value,
-            8 => self.pc = value,
-            9 => self.ir = value,
-            10 => self.psr = value,
-            _ => panic!("Can't set unknown register {register}"),
-        }
+    pub fn set(&mut self, register: Register, value: u16) {
+        self.registers[register as usize] = value;
     }
 
+    #[cfg(feature = "std")]
     pub fn dump(&self) {
-        println!(
-            "R0: 0x{:04X} | R1: 0x{:04X} | R2: 0x{:04X} | R3: 0x{:04X} | R4: 0x{:04X} | R5: 0x{:04X}",
-            self.get(Register::R0.into()),
-            self.get(Register::R1.into()),
-            self.get(Register::R2.into()),
-            self.get(Register::R3.into()),
-            self.get(Register::R4.into()),
-            self.get(Register::R5.into()),
-        );
-        println!(
-            "R6: 0x{:04X} | R7: 0x{:04X} | PC: 0x{:04X} | IR: 0x{:04X} | PSR: 0x{:04X}",
-            self.get(Register::R6.into()),
-            self.get(Register::R7.into()),
-            self.get(Register::PC.into()),
-            self.get(Register::IR.into()),
-            self.get(Register::PSR.into())
-        );
-    }
-
-    pub fn set_condition_codes(&mut self, register: u16) {
-        let psr = self.get(Register::PSR.into());
-        let mask = 0xFFF8;
-        if self.get(register) == 0 {
-            self.set(
-                Register::PSR.into(),
-                (psr & mask) | ConditionalFlag::Zero as u16,
-            );
-        } else if (self.get(register) >> 15) != 0 {
+        println!("{self}");
+    }
+
+    /// PSR\[15\]
+    pub fn privilege(&self) -> PrivilegeMode {
+        PrivilegeMode::from_psr(self.get(Register::PSR))
+    }
+
+    pub fn set_privilege(&mut self, mode: PrivilegeMode) {
+        let psr = self.get(Register::PSR) & !PSR_PRIVILEGE;
+        self.set(Register::PSR, psr | ((mode as u16) << 15));
+    }
+
+    /// PSR\[10:8\]
+    pub fn priority(&self) -> u8 {
+        ((self.get(Register::PSR) & PSR_PRIORITY) >> 8) as u8
+    }
+
+    /// Sets PSR\[10:8\]. Only the low three bits of `priority` are used.
+    pub fn set_priority(&mut self, priority: u8) {
+        let psr = self.get(Register::PSR) & !PSR_PRIORITY;
+        self.set(Register::PSR, psr | (((priority & 0x7) as u16) << 8));
+    }
+
+    /// PSR\[2:0\]
+    pub fn condition(&self) -> u16 {
+        self.get(Register::PSR) & PSR_CONDITION
+    }
+
+    pub fn set_condition(&mut self, flag: ConditionalFlag) {
+        let psr = self.get(Register::PSR) & !PSR_CONDITION;
+        self.set(Register::PSR, psr | flag as u16);
+    }
+
+    pub fn set_condition_codes(&mut self, register: Register) {
+        let value = self.get(register);
+        if value == 0 {
+            self.set_condition(ConditionalFlag::Zero);
+        } else if (value >> 15) != 0 {
             // NOTE: A 1 in the left-most bit indicates a negative
-            self.set(
-                Register::PSR.into(),
-                (psr & mask) | ConditionalFlag::Negative as u16,
-            );
+            self.set_condition(ConditionalFlag::Negative);
         } else {
-            self.set(
-                Register::PSR.into(),
-                (psr & mask) | ConditionalFlag::Positive as u16,
-            );
+            self.set_condition(ConditionalFlag::Positive);
         }
     }
 
     pub fn increment_pc_register(&mut self) {
-        self.pc += 1;
+        let pc = self.get(Register::PC);
+        self.set(Register::PC, pc.wrapping_add(1));
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "R0: 0x{:04X} | R1: 0x{:04X} | R2: 0x{:04X} | R3: 0x{:04X} | R4: 0x{:04X} | R5: 0x{:04X}",
+            self.get(Register::R0),
+            self.get(Register::R1),
+            self.get(Register::R2),
+            self.get(Register::R3),
+            self.get(Register::R4),
+            self.get(Register::R5),
+        )?;
+        write!(
+            f,
+            "R6: 0x{:04X} | R7: 0x{:04X} | PC: 0x{:04X} | IR: 0x{:04X} | PSR: 0x{:04X}",
+            self.get(Register::R6),
+            self.get(Register::R7),
+            self.get(Register::PC),
+            self.get(Register::IR),
+            self.get(Register::PSR)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn psr_fields() {
+        let mut registers = Registers::new();
+        assert_eq!(PrivilegeMode::User, registers.privilege());
+        assert_eq!(0, registers.priority());
+        assert_eq!(ConditionalFlag::Zero as u16, registers.condition());
+
+        registers.set_privilege(PrivilegeMode::Privileged);
+        registers.set_priority(4);
+        registers.set_condition(ConditionalFlag::Negative);
+        assert_eq!(0x0404, registers.get(Register::PSR));
+        assert_eq!(PrivilegeMode::Privileged, registers.privilege());
+        assert_eq!(4, registers.priority());
+    }
+
+    #[test]
+    fn default_matches_new() {
+        assert_eq!(Registers::new(), Registers::default());
+    }
+
+    #[test]
+    fn gpr_from_instruction_field() {
+        assert_eq!(Register::R5, Register::gpr(5));
+        assert_eq!(Register::R1, Register::gpr(9));
     }
 }
@@ -0,0 +1,218 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use core::str::FromStr;
+
+use crate::console::Console;
+use crate::decode::Instruction;
+use crate::observer::Observer;
+use crate::register::Registers;
+
+const HEADER: &str = "# lc3 input replay";
+
+/// A byte of keyboard input and the number of instructions that had executed
+/// when the program first saw it, through GETC, IN or a KBSR poll
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub instruction: u64,
+    pub byte: u8,
+}
+
+/// Every byte of input a run consumed. Feeding the log back to a later run
+/// with `VirtualMachineBuilder::replay_input` reproduces the run exactly.
+///
+/// The text form has one event per line, the instruction count followed by
+/// the byte in hex:
+///
+/// ```text
+/// # lc3 input replay
+/// 1042 0x61
+/// 1877 0x0a
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputLog {
+    pub events: Vec<InputEvent>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The line is not an instruction count followed by a byte
+    Malformed { line: usize },
+    /// The instruction count is lower than the one on the line before
+    OutOfOrder { line: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Malformed { line } => {
+                write!(f, "line {line}: expected `<instruction> <byte>`")
+            }
+            ReplayError::OutOfOrder { line } => {
+                write!(f, "line {line}: instruction count goes backwards")
+            }
+        }
+    }
+}
+
+impl FromStr for InputLog {
+    type Err = ReplayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events: Vec<InputEvent> = Vec::new();
+        for (index, text) in s.lines().enumerate() {
+            let line = index + 1;
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = text.split_whitespace();
+            let event = match (fields.next(), fields.next(), fields.next()) {
+                (Some(instruction), Some(byte), None) => {
+                    let byte = byte.strip_prefix("0x").unwrap_or(byte);
+                    match (instruction.parse(), u8::from_str_radix(byte, 16)) {
+                        (Ok(instruction), Ok(byte)) => InputEvent { instruction, byte },
+                        _ => return Err(ReplayError::Malformed { line }),
+                    }
+                }
+                _ => return Err(ReplayError::Malformed { line }),
+            };
+            if events
+                .last()
+                .is_some_and(|last| last.instruction > event.instruction)
+            {
+                return Err(ReplayError::OutOfOrder { line });
+            }
+            events.push(event);
+        }
+        Ok(Self { events })
+    }
+}
+
+impl fmt::Display for InputLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{HEADER}")?;
+        for event in &self.events {
+            writeln!(f, "{} {:#04x}", event.instruction, event.byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Handle to the input recorded by a machine built with
+/// `VirtualMachineBuilder::record_input`
+#[derive(Clone, Default)]
+pub struct InputRecorder {
+    log: Rc<RefCell<InputLog>>,
+}
+
+impl InputRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The input consumed so far
+    pub fn log(&self) -> InputLog {
+        self.log.borrow().clone()
+    }
+}
+
+/// Number of instructions executed, kept up to date as an observer so that
+/// consoles can timestamp input
+#[derive(Clone, Default)]
+pub(crate) struct Clock(Rc<Cell<u64>>);
+
+impl Clock {
+    fn now(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+impl Observer for Clock {
+    fn on_execute(&mut self, _pc: u16, _instruction: &Instruction, _registers: &Registers) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+/// Passes everything through to `inner`, logging each byte read from it
+pub(crate) struct RecordingConsole {
+    inner: Box<dyn Console>,
+    clock: Clock,
+    log: Rc<RefCell<InputLog>>,
+}
+
+impl RecordingConsole {
+    pub(crate) fn new(inner: Box<dyn Console>, clock: Clock, recorder: &InputRecorder) -> Self {
+        Self {
+            inner,
+            clock,
+            log: Rc::clone(&recorder.log),
+        }
+    }
+}
+
+impl Console for RecordingConsole {
+    fn poll(&mut self) -> bool {
+        self.inner.poll()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let byte = self.inner.read()?;
+        self.log.borrow_mut().events.push(InputEvent {
+            instruction: self.clock.now(),
+            byte,
+        });
+        Some(byte)
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.inner.write(byte);
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush();
+    }
+}
+
+/// Feeds input from a log, making each byte visible to polling only once the
+/// instruction count it was recorded at has been reached. Output goes to
+/// `output`, whose input is never read.
+pub(crate) struct ReplayConsole {
+    events: VecDeque<InputEvent>,
+    clock: Clock,
+    output: Box<dyn Console>,
+}
+
+impl ReplayConsole {
+    pub(crate) fn new(log: InputLog, clock: Clock, output: Box<dyn Console>) -> Self {
+        Self {
+            events: log.events.into(),
+            clock,
+            output,
+        }
+    }
+}
+
+impl Console for ReplayConsole {
+    fn poll(&mut self) -> bool {
+        self.events
+            .front()
+            .is_some_and(|event| event.instruction <= self.clock.now())
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.events.pop_front().map(|event| event.byte)
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.output.write(byte);
+    }
+
+    fn flush(&mut self) {
+        self.output.flush();
+    }
+}
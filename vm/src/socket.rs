@@ -0,0 +1,127 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::console::Console;
+
+/// A connected stream socket
+trait Stream: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Console on the other end of a socket, so that a grader or a second terminal
+/// can drive the keyboard and read the display while the VM's own terminal is
+/// free for the debugger
+///
+/// Bytes received become keyboard input and display output is sent back.
+/// Input is exhausted once the peer shuts down its side of the connection.
+pub struct SocketConsole {
+    stream: Box<dyn Stream>,
+    pending: Option<u8>,
+    closed: bool,
+    output: Vec<u8>,
+}
+
+impl SocketConsole {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Self {
+            stream,
+            pending: None,
+            closed: false,
+            output: Vec::new(),
+        }
+    }
+
+    /// Waits for a connection on a TCP listener
+    pub fn accept_tcp(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Ok(Self::from(stream))
+    }
+
+    /// Waits for a connection on a Unix domain socket listener
+    #[cfg(unix)]
+    pub fn accept_unix(listener: &UnixListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Ok(Self::from(stream))
+    }
+
+    /// Reads one byte, returning `None` at end of input
+    fn receive(&mut self) -> Option<u8> {
+        let mut buffer = [0; 1];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(1) => return Some(buffer[0]),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return None,
+                _ => {
+                    self.closed = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl From<TcpStream> for SocketConsole {
+    fn from(stream: TcpStream) -> Self {
+        Self::new(Box::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for SocketConsole {
+    fn from(stream: UnixStream) -> Self {
+        Self::new(Box::new(stream))
+    }
+}
+
+impl Console for SocketConsole {
+    fn poll(&mut self) -> bool {
+        if self.pending.is_none() && !self.closed && self.stream.set_nonblocking(true).is_ok() {
+            self.pending = self.receive();
+            let _ = self.stream.set_nonblocking(false);
+        }
+        self.pending.is_some()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.flush();
+        match self.pending.take() {
+            Some(byte) => Some(byte),
+            None if self.closed => None,
+            None => self.receive(),
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+
+    fn flush(&mut self) {
+        if !self.output.is_empty() {
+            let _ = self.stream.write_all(&self.output);
+            let _ = self.stream.flush();
+            self.output.clear();
+        }
+    }
+}
+
+impl Drop for SocketConsole {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,130 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// Labels and their addresses, for showing addresses the way they appear in
+/// the source
+///
+/// ```
+/// use vm::Symbols;
+///
+/// let mut symbols = Symbols::new();
+/// symbols.insert("MAIN", 0x3000);
+/// symbols.insert("LOOP", 0x3004);
+///
+/// assert_eq!("LOOP", symbols.symbolize(0x3004));
+/// assert_eq!("MAIN+3", symbols.symbolize(0x3003));
+/// assert_eq!("x2FFF", symbols.symbolize(0x2FFF));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: BTreeMap<String, u16>,
+    addresses: BTreeMap<u16, Vec<String>>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a label, or moves it if it was already added. Where labels share
+    /// an address, the first one added is shown.
+    pub fn insert(&mut self, name: &str, address: u16) {
+        if let Some(old) = self.names.insert(name.to_string(), address) {
+            if let Some(names) = self.addresses.get_mut(&old) {
+                names.retain(|label| label != name);
+                if names.is_empty() {
+                    self.addresses.remove(&old);
+                }
+            }
+        }
+        self.addresses
+            .entry(address)
+            .or_default()
+            .push(name.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Labels by address, lowest first, and in the order they were added at
+    /// the same address
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.addresses
+            .iter()
+            .flat_map(|(address, names)| names.iter().map(|name| (*address, name.as_str())))
+    }
+
+    /// Address of the label `name`
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.names.get(name).copied()
+    }
+
+    /// The closest label at or before `address` and the distance from it
+    pub fn lookup(&self, address: u16) -> Option<(&str, u16)> {
+        let (start, names) = self.addresses.range(..=address).next_back()?;
+        Some((&names[0], address - start))
+    }
+
+    /// `LABEL` or `LABEL+offset`, or the address in hex before the first label
+    pub fn symbolize(&self, address: u16) -> String {
+        match self.lookup(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{name}+{offset}"),
+            None => format!("x{address:04X}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SymbolFileError(pub String);
+
+impl fmt::Display for SymbolFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid symbol file: {}", self.0)
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = SymbolFileError;
+
+    /// Reads a `.sym` file as written by lc3as and the `assembler` crate:
+    /// comment lines with a header, then one label and its hex address per
+    /// line. The lines after the first two start with `//` and a tab.
+    ///
+    /// ```text
+    /// // Symbol table
+    /// // Scope level 0:
+    /// //    Symbol Name       Page Address
+    /// //    ----------------  ------------
+    /// //    MAIN              3000
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut symbols = Symbols::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || SymbolFileError(format!("line {}: {line}", number + 1));
+            let entry = line.strip_prefix("//").ok_or_else(error)?.trim();
+            let header = ["Symbol table", "Scope level", "Symbol Name", "-"];
+            if entry.is_empty() || header.iter().any(|start| entry.starts_with(start)) {
+                continue;
+            }
+
+            let mut fields = entry.split_whitespace();
+            let (Some(name), Some(address), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(error());
+            };
+            let address = u16::from_str_radix(address, 16).map_err(|_| error())?;
+            symbols.insert(name, address);
+        }
+        Ok(symbols)
+    }
+}
@@ -0,0 +1,139 @@
+//! Host terminal handling for interactive programs
+//!
+//! While a `RawMode` guard is alive the terminal delivers every key press
+//! immediately and does not echo it, which is what GETC and KBSR polling
+//! expect. The original settings are restored when the guard is dropped, when
+//! the program panics and when it is interrupted with Ctrl-C.
+
+use vm::{Console, StdConsole};
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{self, Write};
+    use std::panic;
+    use std::sync::OnceLock;
+
+    use vm::Console;
+
+    static ORIGINAL: OnceLock<libc::termios> = OnceLock::new();
+
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            // SAFETY: isatty only inspects the file descriptor
+            if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+                return None;
+            }
+
+            // SAFETY: termios is plain old data and is filled in by tcgetattr
+            let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+                return None;
+            }
+            let original = *ORIGINAL.get_or_init(|| original);
+
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            // SAFETY: raw is a valid termios derived from the current settings
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+                return None;
+            }
+
+            let previous_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                restore();
+                previous_hook(info);
+            }));
+
+            let handler: extern "C" fn(libc::c_int) = interrupt;
+            // SAFETY: the handler only calls async-signal-safe functions
+            unsafe {
+                libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+                libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+            }
+
+            Some(Self)
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            restore();
+        }
+    }
+
+    fn restore() {
+        if let Some(original) = ORIGINAL.get() {
+            // SAFETY: original holds the settings read by tcgetattr
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+        }
+    }
+
+    extern "C" fn interrupt(signal: libc::c_int) {
+        restore();
+        // SAFETY: _exit is async-signal-safe
+        unsafe { libc::_exit(128 + signal) };
+    }
+
+    /// Console for a terminal in raw mode. Polling never blocks.
+    pub struct TerminalConsole;
+
+    impl Console for TerminalConsole {
+        fn poll(&mut self) -> bool {
+            let mut fd = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: fd points to a single valid pollfd
+            unsafe { libc::poll(&mut fd, 1, 0) > 0 && fd.revents & libc::POLLIN != 0 }
+        }
+
+        fn read(&mut self) -> Option<u8> {
+            let mut byte = 0u8;
+            // SAFETY: byte is a valid one byte buffer
+            match unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } {
+                1 => Some(byte),
+                _ => None,
+            }
+        }
+
+        fn write(&mut self, byte: u8) {
+            let _ = io::stdout().write_all(&[byte]);
+        }
+
+        fn flush(&mut self) {
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+/// Raw mode needs termios, so elsewhere the console stays line-buffered
+#[cfg(not(unix))]
+mod fallback {
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub use fallback::RawMode;
+#[cfg(unix)]
+pub use unix::RawMode;
+
+/// Switches the terminal into raw mode and returns a matching console.
+/// Falls back to line-buffered input when stdin is not a terminal.
+pub fn console() -> (Option<RawMode>, Box<dyn Console>) {
+    match RawMode::enable() {
+        #[cfg(unix)]
+        Some(raw_mode) => (Some(raw_mode), Box::new(unix::TerminalConsole)),
+        _ => (None, Box::new(StdConsole::new())),
+    }
+}
@@ -1,59 +1,586 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use crate::block::BlockEngine;
+use crate::builder::VirtualMachineBuilder;
+use crate::call_stack::{Backtrace, CallStack, Entry, StackFrame};
+use crate::decode::Instruction;
 use crate::instruction;
-use crate::memory::{Memory, MEMORY_SIZE};
-use crate::register::{Register, Registers};
+use crate::isa::Isa;
+use crate::memory::{Interrupt, Memory};
+use crate::observer::Observer;
+use crate::register::{PrivilegeMode, Register, Registers};
 
-#[derive(Default)]
 pub struct VirtualMachine {
-    pub registers: Registers,
-    pub memory: Memory,
+    pub(crate) registers: Registers,
+    pub(crate) memory: Memory,
+    halt_reason: Option<HaltReason>,
+    instruction_count: u64,
+    instruction_limit: Option<u64>,
+    blocks: Option<BlockEngine>,
+    observers: Vec<Box<dyn Observer>>,
+    call_stack: CallStack,
+    breakpoints: BTreeSet<u16>,
 }
 
 impl VirtualMachine {
+    /// A machine in user mode at x3000 with zeroed memory and the console on
+    /// stdin and stdout. Use `builder` for anything else.
     pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> VirtualMachineBuilder {
+        VirtualMachineBuilder::new()
+    }
+
+    pub(crate) fn with_memory(memory: Memory) -> Self {
         Self {
             registers: Registers::new(),
-            memory: Memory::new(),
+            memory,
+            halt_reason: None,
+            instruction_count: 0,
+            instruction_limit: None,
+            blocks: None,
+            observers: Vec::new(),
+            call_stack: CallStack::new(),
+            breakpoints: BTreeSet::new(),
         }
     }
 
-    fn fetch(&mut self) -> u16 {
-        let pc = self.registers.get(Register::PC.into());
-        self.registers.increment_pc_register();
-        let instruction = self.memory.read(pc);
-        self.registers.set(Register::IR.into(), instruction);
-        instruction
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// Memory, including the device registers. Reading a device register
+    /// through this has the same side effects as a load instruction.
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Instruction set the machine executes
+    pub fn isa(&self) -> Isa {
+        self.memory.isa()
+    }
+
+    /// Writes `words` to memory starting at `origin`
+    pub fn load(&mut self, origin: u16, words: &[u16]) {
+        self.memory.load(origin, words);
+    }
+
+    /// Registers an observer. Observers are called in the order they were added.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    fn fetch(&mut self) -> Instruction {
+        let pc = self.registers.get(Register::PC);
+        let word_size = self.isa().word_size();
+        self.registers.set(Register::PC, pc.wrapping_add(word_size));
+        // LC-3b ignores the low bit of PC
+        let (instruction, decoded) = self.memory.fetch(pc & !(word_size - 1));
+        self.registers.set(Register::IR, instruction);
+        self.notify_fetch(pc, instruction);
+        decoded
     }
 
     pub fn step(&mut self) {
+        if self.halt_reason.is_some() {
+            return;
+        }
+
+        let pc = self.registers.get(Register::PC);
         let instruction = self.fetch();
-        instruction::execute(self, instruction);
+        instruction::execute_decoded(self, instruction);
+        self.retire(pc, &instruction);
+        self.service_devices(1);
+    }
+
+    /// Finishes an instruction that was fetched from `pc` and has executed
+    pub(crate) fn retire(&mut self, pc: u16, instruction: &Instruction) {
+        for observer in &mut self.observers {
+            observer.on_execute(pc, instruction, &self.registers);
+        }
+        self.instruction_count += 1;
+        self.check_machine_control();
+    }
+
+    /// Runs the translated block at PC, or a single instruction if there is no
+    /// block for PC or the block would overrun the instruction limit
+    pub fn step_block(&mut self) {
+        if self.halt_reason.is_some() {
+            return;
+        }
+        let Some(mut blocks) = self.take_blocks() else {
+            self.step();
+            return;
+        };
+        if !self.run_block(&mut blocks) {
+            self.step();
+        }
+        self.blocks = Some(blocks);
     }
 
-    pub fn run(&mut self) {
-        // TODO: Add 'or HALT' to stop early or maybe change this to just a loop
-        while self.registers.get(Register::PC.into()) < MEMORY_SIZE as u16 {
+    /// Runs translated blocks one after another, until one can't be used or
+    /// the machine halts. Between blocks there is nothing for `run` to check,
+    /// since breakpoints keep blocks from being used.
+    fn run_blocks(&mut self) {
+        let Some(mut blocks) = self.take_blocks() else {
             self.step();
+            return;
+        };
+        while self.halt_reason.is_none() && !self.at_instruction_limit() {
+            if !self.run_block(&mut blocks) {
+                self.step();
+                break;
+            }
+        }
+        self.blocks = Some(blocks);
+    }
+
+    /// Interprets instructions straight out of the decode cache until one
+    /// isn't cached yet, which `step` then decodes. Like `run_blocks`, this is
+    /// only used when there are no per-instruction hooks to run.
+    fn run_decoded(&mut self) {
+        while self.halt_reason.is_none() && !self.at_instruction_limit() {
+            let pc = self.registers.get(Register::PC);
+            let Some((word, instruction)) = self.memory.cached(pc) else {
+                self.step();
+                return;
+            };
+            self.registers.set(Register::PC, pc.wrapping_add(1));
+            self.registers.set(Register::IR, word);
+            instruction::execute_decoded(self, instruction);
+            self.instruction_count += 1;
+            self.check_machine_control();
+        }
+    }
+
+    /// Whether `run` can skip the hooks that `step` runs for every
+    /// instruction
+    fn hooks_idle(&self) -> bool {
+        // Observers, caches and breakpoints have to see every fetch and
+        // instruction. So do devices, which have to be serviced after every
+        // instruction to finish commands and raise interrupts when they would
+        // in the interpreter. Blocks are only translated for the LC-3, and the
+        // fast paths only step PC by one word.
+        self.observers.is_empty()
+            && !self.memory.has_caches()
+            && !self.memory.has_devices()
+            && self.breakpoints.is_empty()
+            && self.memory.isa() == Isa::Lc3
+    }
+
+    /// Sets the block engine aside while blocks run, which only marks code as
+    /// overwritten in memory and never touches the engine. There is none to
+    /// take if blocks can't be used at all.
+    fn take_blocks(&mut self) -> Option<BlockEngine> {
+        if !self.hooks_idle() {
+            return None;
+        }
+        self.blocks.take()
+    }
+
+    /// Runs the translated block at PC, if there is one that fits within the
+    /// instruction limit
+    fn run_block(&mut self, blocks: &mut BlockEngine) -> bool {
+        let pc = self.registers.get(Register::PC);
+        let budget = self.instruction_limit.map_or(u64::MAX, |limit| {
+            limit.saturating_sub(self.instruction_count)
+        });
+        match blocks.get(&mut self.memory, pc) {
+            Some(block) if block.len() <= budget => {
+                self.instruction_count += block.run(self, budget);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn at_instruction_limit(&self) -> bool {
+        self.instruction_limit
+            .is_some_and(|limit| self.instruction_count >= limit)
+    }
+
+    /// Translates straight-line code into blocks of pre-decoded operations and
+    /// runs those instead of interpreting one instruction at a time. On the
+    /// loop in `benches/interpreter.rs` this is about 11x faster than
+    /// interpreting.
+    pub fn enable_block_engine(&mut self) {
+        self.blocks.get_or_insert_with(BlockEngine::new);
+    }
+
+    pub(crate) fn check_machine_control(&mut self) {
+        if self.halt_reason.is_none() && !self.memory.clock_enabled() {
+            self.halt(HaltReason::MachineControl);
+        }
+    }
+
+    /// Runs until the machine halts and returns the reason it stopped. The
+    /// instruction at PC runs even if it has a breakpoint, so that `run`
+    /// continues from a breakpoint after `resume`.
+    pub fn run(&mut self) -> HaltReason {
+        let mut resumed = true;
+        loop {
+            if let Some(reason) = self.halt_reason {
+                self.memory.console_mut().flush();
+                return reason;
+            }
+            if self.at_instruction_limit() {
+                self.halt(HaltReason::InstructionLimit);
+                continue;
+            }
+            if !core::mem::take(&mut resumed) && self.check_breakpoint() {
+                continue;
+            }
+            if self.blocks.is_some() {
+                self.run_blocks();
+            } else if self.hooks_idle() {
+                self.run_decoded();
+            } else {
+                self.step();
+            }
+        }
+    }
+
+    /// Stops the machine. Only the first reason given is kept.
+    pub fn halt(&mut self, reason: HaltReason) {
+        if self.halt_reason.is_some() {
+            return;
         }
+        self.halt_reason = Some(reason);
+        for observer in &mut self.observers {
+            observer.on_halt(reason, &self.registers);
+        }
+    }
+
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason
+    }
+
+    /// Clears a `HaltReason::Breakpoint` so that the machine can run on
+    pub fn resume(&mut self) {
+        if let Some(HaltReason::Breakpoint(_)) = self.halt_reason {
+            self.halt_reason = None;
+        }
+    }
+
+    /// Stops `run` with `HaltReason::Breakpoint` before the instruction at
+    /// `address` executes
+    pub fn set_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Halts if there is a breakpoint at PC
+    pub(crate) fn check_breakpoint(&mut self) -> bool {
+        let pc = self.registers.get(Register::PC);
+        if self.breakpoints.contains(&pc) {
+            self.halt(HaltReason::Breakpoint(pc));
+        }
+        self.halt_reason.is_some()
+    }
+
+    /// Routines the program is in
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// The call stack and the instruction the machine is at, which is the one
+    /// that stopped it when it halted on a HALT, a trap or an exception
+    pub fn backtrace(&self) -> Backtrace {
+        let pc = self.registers.get(Register::PC);
+        let pc = match self.halt_reason {
+            None | Some(HaltReason::Breakpoint(_) | HaltReason::InstructionLimit) => pc,
+            Some(_) => pc.wrapping_sub(self.isa().word_size()),
+        };
+        Backtrace {
+            pc,
+            frames: self.call_stack.frames().rev().copied().collect(),
+            truncated: self.call_stack.is_truncated(),
+        }
+    }
+
+    /// JSR or JSRR to `routine` has set R7 and PC
+    pub(crate) fn enter_call(&mut self, routine: u16) {
+        let return_address = self.registers.get(Register::R7);
+        self.call_stack.push(StackFrame {
+            entry: Entry::Call,
+            site: return_address.wrapping_sub(self.isa().word_size()),
+            routine,
+            return_address,
+        });
+    }
+
+    /// JMP R7 to `address`
+    pub(crate) fn leave_call(&mut self, address: u16) {
+        self.call_stack.ret(address);
+    }
+
+    /// RTI has restored PC and PSR
+    pub(crate) fn leave_service_routine(&mut self) {
+        self.call_stack.rti();
+    }
+
+    /// A service routine at `routine` was entered, and will return to
+    /// `return_address`
+    pub(crate) fn enter_routine(&mut self, entry: Entry, routine: u16, return_address: u16) {
+        let site = match entry {
+            Entry::Interrupt(_) => return_address,
+            _ => return_address.wrapping_sub(self.isa().word_size()),
+        };
+        self.call_stack.push(StackFrame {
+            entry,
+            site,
+            routine,
+            return_address,
+        });
+    }
+
+    /// Number of instructions executed so far
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    pub fn instruction_limit(&self) -> Option<u64> {
+        self.instruction_limit
+    }
+
+    /// Stops `run` with `HaltReason::InstructionLimit` after `limit` instructions
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.instruction_limit = limit;
     }
 
     pub fn get_mode(&self) -> PrivilegeMode {
-        if ((PrivilegeMode::User as u16) << 15) & self.registers.get(Register::PSR.into()) == 1 {
-            PrivilegeMode::User
+        self.registers.privilege()
+    }
+
+    /// Switches between user and supervisor mode. R6 is swapped with the saved
+    /// stack pointer of the mode being entered.
+    pub fn set_mode(&mut self, mode: PrivilegeMode) {
+        if self.get_mode() == mode {
+            return;
+        }
+
+        let stack_pointer = self.registers.get(Register::R6);
+        match mode {
+            PrivilegeMode::Privileged => {
+                self.registers.set(Register::SavedUSP, stack_pointer);
+                let ssp = self.registers.get(Register::SavedSSP);
+                self.registers.set(Register::R6, ssp);
+            }
+            PrivilegeMode::User => {
+                self.registers.set(Register::SavedSSP, stack_pointer);
+                let usp = self.registers.get(Register::SavedUSP);
+                self.registers.set(Register::R6, usp);
+            }
+        }
+        self.registers.set_privilege(mode);
+    }
+
+    pub fn get_priority(&self) -> u8 {
+        self.registers.priority()
+    }
+
+    pub fn set_priority(&mut self, priority: u8) {
+        self.registers.set_priority(priority);
+    }
+
+    /// Advances the attached devices and takes their interrupts
+    fn service_devices(&mut self, instructions: u64) {
+        if !self.memory.has_devices() {
+            return;
+        }
+
+        self.memory.tick(instructions);
+        if self.halt_reason.is_some() {
+            return;
+        }
+        if let Some(interrupt) = self.memory.take_interrupt(self.get_priority()) {
+            self.interrupt(interrupt);
+        }
+    }
+
+    /// Starts the service routine for a device interrupt at the device's
+    /// priority. Interrupts without an entry in the interrupt vector table are
+    /// dropped.
+    pub fn interrupt(&mut self, interrupt: Interrupt) {
+        let vector = self.isa().interrupt_vector(interrupt.vector);
+        self.notify_interrupt(vector);
+        let routine = self.read(vector);
+        if routine != 0 {
+            self.enter_service_routine(routine, Entry::Interrupt(interrupt.vector));
+            self.set_priority(interrupt.priority);
+        }
+    }
+
+    /// Starts the service routine for an exception, or halts with
+    /// `HaltReason::Exception` if the interrupt vector table has no entry for it.
+    /// PC still points past the instruction that caused the exception.
+    pub fn raise_exception(&mut self, exception: Exception) {
+        let vector = self.isa().interrupt_vector(exception as u8);
+        self.notify_interrupt(vector);
+        let routine = self.read(vector);
+        if routine == 0 {
+            self.halt(HaltReason::Exception(exception));
         } else {
-            PrivilegeMode::Privileged
+            self.enter_service_routine(routine, Entry::Exception(exception));
         }
     }
+
+    /// Switches to supervisor mode, pushes PSR and PC on the supervisor stack and
+    /// jumps to `routine`
+    pub(crate) fn enter_service_routine(&mut self, routine: u16, entry: Entry) {
+        let psr = self.registers.get(Register::PSR);
+        let pc = self.registers.get(Register::PC);
+        self.set_mode(PrivilegeMode::Privileged);
+        self.push(psr);
+        self.push(pc);
+        self.registers.set(Register::PC, routine);
+        self.enter_routine(entry, routine, pc);
+    }
+
+    /// Data read on behalf of an instruction, reported to observers
+    pub(crate) fn read(&mut self, address: u16) -> u16 {
+        let value = self.memory.read(address);
+        for observer in &mut self.observers {
+            observer.on_mem_read(address, value);
+        }
+        value
+    }
+
+    /// Data write on behalf of an instruction, reported to observers
+    pub(crate) fn write(&mut self, address: u16, value: u16) {
+        self.memory.write(address, value);
+        for observer in &mut self.observers {
+            observer.on_mem_write(address, value);
+        }
+    }
+
+    /// LC-3b byte read on behalf of an instruction, reported to observers
+    #[cfg(feature = "lc3b")]
+    pub(crate) fn read_byte(&mut self, address: u16) -> u8 {
+        let value = self.memory.read_byte(address);
+        for observer in &mut self.observers {
+            observer.on_mem_read(address, value as u16);
+        }
+        value
+    }
+
+    /// LC-3b byte write on behalf of an instruction, reported to observers
+    #[cfg(feature = "lc3b")]
+    pub(crate) fn write_byte(&mut self, address: u16, value: u8) {
+        self.memory.write_byte(address, value);
+        for observer in &mut self.observers {
+            observer.on_mem_write(address, value as u16);
+        }
+    }
+
+    /// True if a word access to `address` is misaligned
+    #[cfg(feature = "lc3b")]
+    pub(crate) fn is_unaligned(&self, address: u16) -> bool {
+        address & (self.isa().word_size() - 1) != 0
+    }
+
+    pub(crate) fn notify_fetch(&mut self, pc: u16, instruction: u16) {
+        for observer in &mut self.observers {
+            observer.on_fetch(pc, instruction);
+        }
+    }
+
+    pub(crate) fn notify_interrupt(&mut self, vector: u16) {
+        for observer in &mut self.observers {
+            observer.on_interrupt(vector, &self.registers);
+        }
+    }
+
+    pub(crate) fn notify_trap(&mut self, vector: u8) {
+        for observer in &mut self.observers {
+            observer.on_trap(vector, &self.registers);
+        }
+    }
+
+    pub(crate) fn push(&mut self, value: u16) {
+        let stack_pointer = self
+            .registers
+            .get(Register::R6)
+            .wrapping_sub(self.isa().word_size());
+        self.registers.set(Register::R6, stack_pointer);
+        self.write(stack_pointer, value);
+    }
+
+    pub(crate) fn pop(&mut self) -> u16 {
+        let stack_pointer = self.registers.get(Register::R6);
+        let word_size = self.isa().word_size();
+        self.registers
+            .set(Register::R6, stack_pointer.wrapping_add(word_size));
+        self.read(stack_pointer)
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The program executed the HALT trap
+    Halt,
+    /// The program cleared the clock enable bit of the machine control register
+    MachineControl,
+    /// GETC or IN was executed after all input was consumed
+    InputExhausted,
+    /// The instruction limit was reached
+    InstructionLimit,
+    /// TRAP was executed with a vector that has no service routine
+    UnknownTrap(u8),
+    /// An exception was raised that has no service routine
+    Exception(Exception),
+    /// PC reached a breakpoint, at the given address
+    Breakpoint(u16),
+    /// The machine was run through a model that doesn't support its ISA
+    UnsupportedIsa(Isa),
 }
 
-#[derive(PartialEq, Eq)]
+/// Base address of the interrupt vector table
+pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u16)]
-pub enum PrivilegeMode {
-    Privileged = 0,
-    User = 1,
+pub enum Exception {
+    /// RTI executed in user mode
+    PrivilegeViolation = 0x00,
+    /// The reserved opcode was executed
+    IllegalOpcode = 0x01,
+    /// Privileged memory accessed in user mode
+    AccessViolation = 0x02,
+    /// LC-3b word access to an odd address
+    #[cfg(feature = "lc3b")]
+    UnalignedAccess = 0x03,
 }
 
-impl From<PrivilegeMode> for u16 {
-    fn from(mode: PrivilegeMode) -> Self {
-        mode as u16
+impl Exception {
+    /// Address of the exception's entry in the LC-3 interrupt vector table.
+    /// See `Isa::interrupt_vector` for other instruction sets.
+    pub fn vector(self) -> u16 {
+        INTERRUPT_VECTOR_TABLE + self as u16
     }
 }
@@ -5,17 +5,15 @@ fn immediate_mode() {
     // 0001 000 000 1 00111 = 0x1027 = ADD R0 R0 7
     // 0101 000 000 1 00010 = 0x5022 = AND R0 R0 2
     let binary = vec![0x1027, 0x5022];
-    let mut address = 0x3000;
 
     let mut vm = VirtualMachine::new();
-    for line in binary {
-        vm.memory.write(address, line);
-        address += 1;
+    for (address, line) in (0x3000..).zip(binary) {
+        vm.memory_mut().write(address, line);
     }
     vm.step();
     vm.step();
 
-    assert_eq!(2, vm.registers.get(Register::R0.into()));
+    assert_eq!(2, vm.registers().get(Register::R0));
 }
 
 #[test]
@@ -24,16 +22,14 @@ fn register_mode() {
     // 0001 001 001 1 00011 = 0x1263 = ADD R1 R1 3
     // 0101 010 001 0 00000 = 0x5440 = AND R2 R1 R0
     let binary = vec![0x1021, 0x1262, 0x1440];
-    let mut address = 0x3000;
 
     let mut vm = VirtualMachine::new();
-    for line in binary {
-        vm.memory.write(address, line);
-        address += 1;
+    for (address, line) in (0x3000..).zip(binary) {
+        vm.memory_mut().write(address, line);
     }
     vm.step();
     vm.step();
     vm.step();
 
-    assert_eq!(3, vm.registers.get(Register::R2.into()));
+    assert_eq!(3, vm.registers().get(Register::R2));
 }
@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use vm::{Harness, PrivilegeMode, Register, ScriptedConsole, VirtualMachine, KBSR};
+
+/// xorshift32, so that failures can be reproduced from the seed
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u16 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as u16
+    }
+
+    fn instruction(&mut self) -> u16 {
+        let word = self.next();
+        match word >> 12 {
+            // Mostly use the standard trap vectors
+            15 if word & 0x100 == 0 => 0xF020 | ((word & 0x7) % 6),
+            _ => word,
+        }
+    }
+}
+
+fn machine(
+    program: &[u16],
+    registers: &[u16],
+    mode: PrivilegeMode,
+) -> (VirtualMachine, Rc<RefCell<Vec<u8>>>) {
+    let console = ScriptedConsole::new(b"lc3");
+    let output = console.output();
+
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, program)
+        .mode(mode)
+        .console(Box::new(console))
+        .instruction_limit(2_000)
+        .build();
+    for (register, value) in Register::GPRS.into_iter().zip(registers) {
+        vm.registers_mut().set(register, *value);
+    }
+
+    (vm, output)
+}
+
+#[test]
+fn random_programs_match_interpreter() {
+    for seed in 1..=300 {
+        let mut random = Random(seed);
+        let program: Vec<u16> = (0..64).map(|_| random.instruction()).collect();
+        let registers: Vec<u16> = (0..8).map(|_| random.next()).collect();
+        let mode = if seed % 2 == 0 {
+            PrivilegeMode::Privileged
+        } else {
+            PrivilegeMode::User
+        };
+
+        let (mut interpreted, interpreted_output) = machine(&program, &registers, mode);
+        let (mut translated, translated_output) = machine(&program, &registers, mode);
+        translated.enable_block_engine();
+
+        let interpreted_halt = interpreted.run();
+        let translated_halt = translated.run();
+
+        assert_eq!(interpreted_halt, translated_halt, "seed {seed}");
+        assert_eq!(
+            interpreted.instruction_count(),
+            translated.instruction_count(),
+            "seed {seed}"
+        );
+        assert_eq!(
+            interpreted.registers(),
+            translated.registers(),
+            "seed {seed}"
+        );
+        assert_eq!(interpreted_output, translated_output, "seed {seed}");
+        for address in 0..KBSR {
+            assert_eq!(
+                interpreted.memory_mut().read(address),
+                translated.memory_mut().read(address),
+                "seed {seed} address {address:#06X}"
+            );
+        }
+    }
+}
+
+#[test]
+fn loop_matches_interpreter() {
+    // Counts to 10 * 10 with two nested loops
+    let program = [
+        0x5020, 0x2207, 0x2407, 0x1021, 0x14BF, 0x03FD, 0x127F, 0x03FA, 0xF025, 0x000A, 0x000A,
+    ];
+    let interpreted = Harness::new().run(&program);
+    let translated = Harness::new().block_engine().run(&program);
+
+    assert_eq!(100, translated.registers.get(Register::R0));
+    assert_eq!(interpreted, translated);
+}
+
+#[test]
+fn write_into_running_block() {
+    // 0x3000: 0010 000 000000100 = 0x2004 = LD R0 4
+    // 0x3001: 0011 000 000000000 = 0x3000 = ST R0 0
+    // 0x3002: 0001 001 001 1 00001 = 0x1261 = ADD R1 R1 1 (overwritten)
+    // 0x3003: 1111 0000 00100101 = 0xF025 = HALT
+    // 0x3004: 0000 0000 00000000 = 0x0000
+    // 0x3005: 0001 001 001 1 00101 = 0x1265 = ADD R1 R1 5
+    let program = [0x2004, 0x3000, 0x1261, 0xF025, 0x0000, 0x1265];
+    let result = Harness::new().block_engine().run(&program);
+
+    assert_eq!(5, result.registers.get(Register::R1));
+}
+
+#[test]
+fn instruction_limit_inside_block() {
+    // 0001 000 000 1 00001 = 0x1021 = ADD R0 R0 1
+    let program = [0x1021, 0x1021, 0x1021, 0x1021, 0x0FFB];
+    let interpreted = Harness::new().instruction_limit(12).run(&program);
+    let translated = Harness::new()
+        .block_engine()
+        .instruction_limit(12)
+        .run(&program);
+
+    assert_eq!(12, translated.instruction_count);
+    assert_eq!(interpreted, translated);
+}
+
+#[test]
+fn looping_block_stops_at_every_limit() {
+    // 0x3000: 0010 001 000000100 = 0x2204 = LD R1 4
+    // 0x3001: 0001 000 000 1 00010 = 0x1022 = ADD R0 R0 2
+    // 0x3002: 0001 001 001 1 11111 = 0x127F = ADD R1 R1 -1
+    // 0x3003: 0000 001 111111101 = 0x03FD = BRp -3
+    // 0x3004: 1111 0000 00100101 = 0xF025 = HALT
+    // 0x3005: 0000 0000 00000111 = 0x0007
+    let program = [0x2204, 0x1022, 0x127F, 0x03FD, 0xF025, 0x0007];
+    for limit in 1..=30 {
+        let interpreted = Harness::new().instruction_limit(limit).run(&program);
+        let translated = Harness::new()
+            .block_engine()
+            .instruction_limit(limit)
+            .run(&program);
+
+        assert_eq!(interpreted, translated, "limit {limit}");
+    }
+}
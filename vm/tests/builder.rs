@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use vm::{
+    Device, HaltReason, MemoryInit, PrivilegeMode, Register, ScriptedConsole, VirtualMachine,
+    INTERRUPT_VECTOR_TABLE,
+};
+
+#[test]
+fn defaults_match_new() {
+    let built = VirtualMachine::builder().build();
+    let new = VirtualMachine::new();
+
+    assert_eq!(new.registers(), built.registers());
+    assert_eq!(PrivilegeMode::User, built.get_mode());
+}
+
+#[test]
+fn entry_point_and_psr() {
+    let vm = VirtualMachine::builder()
+        .entry_point(0x4000)
+        .mode(PrivilegeMode::Privileged)
+        .priority(5)
+        .build();
+
+    assert_eq!(0x4000, vm.registers().get(Register::PC));
+    assert_eq!(0x0502, vm.registers().get(Register::PSR));
+    assert_eq!(PrivilegeMode::Privileged, vm.get_mode());
+    assert_eq!(5, vm.get_priority());
+    // Starting in supervisor mode keeps the user stack pointer aside
+    assert_eq!(0x3000, vm.registers().get(Register::R6));
+}
+
+#[test]
+fn memory_init_leaves_vector_tables_zero() {
+    let mut filled = VirtualMachine::builder()
+        .memory_init(MemoryInit::Fill(0xDEAD))
+        .build();
+    let mut random = VirtualMachine::builder()
+        .memory_init(MemoryInit::Random(7))
+        .build();
+
+    for address in 0..INTERRUPT_VECTOR_TABLE + 0x100 {
+        assert_eq!(0, filled.memory_mut().read(address));
+        assert_eq!(0, random.memory_mut().read(address));
+    }
+    assert_eq!(0xDEAD, filled.memory_mut().read(0x3000));
+    assert_ne!(
+        random.memory_mut().read(0x3000),
+        random.memory_mut().read(0x3001)
+    );
+}
+
+#[test]
+fn os_image_service_routine() {
+    // x0040 in the trap vector table points at x1000
+    // Service routine:
+    // 0001 001 001 1 00001 = 0x1261 = ADD R1 R1 1
+    // 1000 000000000000 = 0x8000 = RTI
+    let mut os = vec![0; 0x1002];
+    os[0x40] = 0x1000;
+    os[0x1000] = 0x1261;
+    os[0x1001] = 0x8000;
+
+    // 1111 0000 01000000 = 0xF040 = TRAP x40
+    let mut vm = VirtualMachine::builder()
+        .os_image(&os)
+        .program(0x3000, &[0xF040, 0xF025])
+        .console(Box::new(ScriptedConsole::default()))
+        .build();
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(1, vm.registers().get(Register::R1));
+}
+
+/// A single register that remembers the last value written to it
+struct Latch {
+    address: u16,
+    value: Rc<RefCell<u16>>,
+}
+
+impl Device for Latch {
+    fn contains(&self, address: u16) -> bool {
+        address == self.address
+    }
+
+    fn read(&mut self, _address: u16) -> u16 {
+        *self.value.borrow() + 1
+    }
+
+    fn write(&mut self, _address: u16, value: u16) {
+        *self.value.borrow_mut() = value;
+    }
+}
+
+#[test]
+fn attached_device() {
+    // 0010 000 000000100 = 0x2004 = LD R0 4
+    // 1011 000 000000100 = 0xB004 = STI R0 4
+    // 0010 010 000000011 = 0x2403 = LD R2 3
+    // 0110 001 010 000000 = 0x6280 = LDR R1 R2 0
+    let value = Rc::new(RefCell::new(0));
+    let program = [0x2004, 0xB004, 0x2403, 0x6280, 0xF025, 0x0041, 0xFE10];
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &program)
+        .mode(PrivilegeMode::Privileged)
+        .device(Box::new(Latch {
+            address: 0xFE10,
+            value: value.clone(),
+        }))
+        .console(Box::new(ScriptedConsole::default()))
+        .build();
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(0x41, *value.borrow());
+    assert_eq!(0x42, vm.registers().get(Register::R1));
+}
+
+#[test]
+fn instruction_limit() {
+    // 0000 111 111111111 = 0x0FFF = BRnzp -1
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &[0x0FFF])
+        .instruction_limit(10)
+        .block_engine()
+        .build();
+
+    assert_eq!(HaltReason::InstructionLimit, vm.run());
+    assert_eq!(10, vm.instruction_count());
+}
@@ -0,0 +1,208 @@
+use vm::{
+    Cache, CacheConfig, CacheStats, HaltReason, Harness, Microarchitecture, Register, Replacement,
+    VirtualMachine, WritePolicy,
+};
+
+fn stats(hits: u64, misses: u64, evictions: u64, write_backs: u64) -> CacheStats {
+    CacheStats {
+        hits,
+        misses,
+        evictions,
+        write_backs,
+    }
+}
+
+#[test]
+fn conflict_misses_need_associativity() {
+    // x4000 and x4008 map to the same set of an eight word cache
+    let accesses = [0x4000, 0x4008, 0x4000, 0x4008];
+
+    let mut direct = Cache::new(CacheConfig::new(8, 1, 2));
+    let mut two_way = Cache::new(CacheConfig::new(8, 2, 2));
+    for address in accesses {
+        direct.read(address);
+        two_way.read(address);
+    }
+
+    assert_eq!(&stats(0, 4, 3, 0), direct.total());
+    assert_eq!(&stats(2, 2, 0, 0), two_way.total());
+}
+
+#[test]
+fn replacement_policies() {
+    // One set of two lines: A B A C A
+    let accesses = [0x0000, 0x0001, 0x0000, 0x0002, 0x0000];
+    let run = |replacement| {
+        let mut config = CacheConfig::new(2, 2, 1);
+        config.replacement = replacement;
+        let mut cache = Cache::new(config);
+        for address in accesses {
+            cache.read(address);
+        }
+        *cache.total()
+    };
+
+    // LRU evicts B for C, FIFO evicts A
+    assert_eq!(stats(2, 3, 1, 0), run(Replacement::Lru));
+    assert_eq!(stats(1, 4, 2, 0), run(Replacement::Fifo));
+    assert_eq!(run(Replacement::Random(7)), run(Replacement::Random(7)));
+}
+
+#[test]
+fn write_policies() {
+    let run = |write_policy| {
+        let mut config = CacheConfig::new(1, 1, 1);
+        config.write_policy = write_policy;
+        let mut cache = Cache::new(config);
+        cache.write(0x4000);
+        cache.write(0x4000);
+        cache.read(0x4001);
+        *cache.total()
+    };
+
+    // Write-back allocates on the first write and writes the line back when
+    // x4001 evicts it. Write-through never allocates for a write.
+    assert_eq!(stats(1, 2, 1, 1), run(WritePolicy::WriteBack));
+    assert_eq!(stats(0, 3, 0, 0), run(WritePolicy::WriteThrough));
+}
+
+#[test]
+fn statistics_per_region() {
+    let mut cache = Cache::new(CacheConfig::new(16, 1, 4))
+        .region("array", 0x4000..=0x400F)
+        .region("stack", 0x2FF0..=0x2FFF);
+    for address in 0x4000..0x4010 {
+        cache.read(address);
+    }
+    cache.write(0x2FFF);
+
+    let regions = cache.regions();
+    assert_eq!("array", regions[0].name);
+    assert_eq!(stats(12, 4, 0, 0), regions[0].stats);
+    assert_eq!(stats(0, 1, 1, 0), regions[1].stats);
+    assert_eq!(stats(12, 5, 1, 0), *cache.total());
+    assert_eq!(0.75, regions[0].stats.hit_rate());
+}
+
+#[test]
+fn parse_config() {
+    let config: CacheConfig = "size=64,ways=2,line=4,write=through,replace=fifo"
+        .parse()
+        .unwrap();
+    assert_eq!(64, config.size);
+    assert_eq!(2, config.ways);
+    assert_eq!(4, config.line_size);
+    assert_eq!(WritePolicy::WriteThrough, config.write_policy);
+    assert_eq!(Replacement::Fifo, config.replacement);
+
+    assert_eq!(Ok(CacheConfig::new(32, 1, 1)), "size=32".parse());
+    assert!("ways=2".parse::<CacheConfig>().is_err());
+    assert!("size=48".parse::<CacheConfig>().is_err());
+    assert!("size=4,ways=2,line=4".parse::<CacheConfig>().is_err());
+    assert!("size=4,colour=red".parse::<CacheConfig>().is_err());
+}
+
+#[test]
+#[should_panic(expected = "powers of two")]
+fn invalid_geometry_panics() {
+    Cache::new(CacheConfig::new(12, 1, 1));
+}
+
+// Sums the eight words at x4000 into R2
+// x3000: LD R1 #9       0x2209
+// x3001: AND R2 R2 0    0x54A0
+// x3002: AND R3 R3 0    0x56E0
+// x3003: ADD R3 R3 8    0x16E8
+// loop:
+// x3004: LDR R4 R1 0    0x6840
+// x3005: ADD R2 R2 R4   0x1484
+// x3006: ADD R1 R1 1    0x1261
+// x3007: ADD R3 R3 -1   0x16FF
+// x3008: BRp loop       0x03FB
+// x3009: HALT           0xF025
+// x300A: x4000
+const SUM: [u16; 11] = [
+    0x2209, 0x54A0, 0x56E0, 0x16E8, 0x6840, 0x1484, 0x1261, 0x16FF, 0x03FB, 0xF025, 0x4000,
+];
+
+fn array() -> Vec<u16> {
+    (1..=8).collect()
+}
+
+fn caches() -> (Cache, Cache) {
+    let instruction = Cache::new(CacheConfig::new(16, 1, 4));
+    let data = Cache::new(CacheConfig::new(16, 1, 4)).region("array", 0x4000..=0x4007);
+    (instruction, data)
+}
+
+#[test]
+fn program_accesses() {
+    for block_engine in [false, true] {
+        let (instruction, data) = caches();
+        let mut builder = VirtualMachine::builder()
+            .program(0x3000, &SUM)
+            .load(0x4000, &array())
+            .instruction_cache(instruction)
+            .data_cache(data);
+        if block_engine {
+            builder = builder.block_engine();
+        }
+        let mut vm = builder.build();
+        assert_eq!(HaltReason::Halt, vm.run());
+        assert_eq!(36, vm.registers().get(Register::R2));
+
+        // Every fetch goes through the instruction cache, even with the block
+        // engine enabled: 4 instructions before the loop, 8 times 5 in it and
+        // the HALT
+        let instructions = vm.memory().instruction_cache().unwrap().total();
+        assert_eq!(45, instructions.accesses());
+        assert_eq!(3, instructions.misses);
+
+        // The LD of the array address, one read per element and the trap
+        // vector of HALT, which evicts the second half of the array. The
+        // machine control register that HALT writes is never cached.
+        let data = vm.memory().data_cache().unwrap();
+        assert_eq!(&stats(6, 4, 1, 0), data.total());
+        assert_eq!(stats(6, 2, 0, 0), data.regions()[0].stats);
+    }
+}
+
+#[test]
+fn microsequencer_fetches_use_the_instruction_cache() {
+    let (instruction, data) = caches();
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &SUM)
+        .load(0x4000, &array())
+        .instruction_cache(instruction)
+        .data_cache(data)
+        .build();
+    Microarchitecture::new().run(&mut vm);
+
+    assert_eq!(
+        45,
+        vm.memory().instruction_cache().unwrap().total().accesses()
+    );
+    assert_eq!(10, vm.memory().data_cache().unwrap().total().accesses());
+}
+
+#[test]
+fn harness_reports_caches() {
+    let (_, data) = caches();
+    let mut program = SUM.to_vec();
+    program.resize(0x1000, 0);
+    program.extend(array());
+
+    let result = Harness::new().data_cache(data).run(&program);
+    assert_eq!(36, result.registers.get(Register::R2));
+    assert!(result.instruction_cache.is_none());
+    let data = result.data_cache.unwrap();
+    assert_eq!(stats(6, 2, 0, 0), data.regions()[0].stats);
+
+    let report = data.to_string();
+    assert!(
+        report.contains("16 words, 1-way, 4 words per line"),
+        "{report}"
+    );
+    assert!(report.contains("array"), "{report}");
+    assert!(report.contains("75.0%"), "{report}");
+}
@@ -0,0 +1,182 @@
+use vm::{
+    Backtrace, Entry, Exception, HaltReason, Harness, Microarchitecture, PrivilegeMode, Register,
+    StackFrame, Symbols, VirtualMachine, MAX_CALL_DEPTH,
+};
+
+fn call(site: u16, routine: u16) -> StackFrame {
+    StackFrame {
+        entry: Entry::Call,
+        site,
+        routine,
+        return_address: site + 1,
+    }
+}
+
+// x3000: JSR FIRST       0x4802
+// x3001: HALT            0xF025
+// x3002: .FILL 0
+// x3003: FIRST JSR SECOND 0x4802
+// x3004: RET             0xC1C0
+// x3005: .FILL 0
+// x3006: SECOND RES      0xD000
+const NESTED: [u16; 7] = [0x4802, 0xF025, 0x0000, 0x4802, 0xC1C0, 0x0000, 0xD000];
+
+#[test]
+fn backtrace_on_fault() {
+    let mut vm = VirtualMachine::builder().program(0x3000, &NESTED).build();
+    assert_eq!(HaltReason::Exception(Exception::IllegalOpcode), vm.run());
+
+    let backtrace = vm.backtrace();
+    assert_eq!(0x3006, backtrace.pc);
+    assert_eq!(
+        vec![call(0x3003, 0x3006), call(0x3000, 0x3003)],
+        backtrace.frames
+    );
+    assert!(!backtrace.truncated);
+
+    let mut symbols = Symbols::new();
+    symbols.insert("MAIN", 0x3000);
+    symbols.insert("FIRST", 0x3003);
+    symbols.insert("SECOND", 0x3006);
+    assert_eq!(
+        "#0 x3006 SECOND\n#1 x3003 FIRST, JSR to SECOND\n#2 x3000 MAIN, JSR to FIRST\n",
+        backtrace.display(&symbols).to_string()
+    );
+    assert_eq!(
+        "#0 x3006\n#1 x3003, JSR to x3006\n#2 x3000, JSR to x3003\n",
+        backtrace.display(&Symbols::new()).to_string()
+    );
+}
+
+// x3000: JSR SUB         0x4802
+// x3001: HALT            0xF025
+// x3002: .FILL 0
+// x3003: SUB ADD R0 R0 1 0x1021
+// x3004: RET             0xC1C0
+const SUBROUTINE: [u16; 5] = [0x4802, 0xF025, 0x0000, 0x1021, 0xC1C0];
+
+#[test]
+fn breakpoints_stop_and_resume() {
+    for block_engine in [false, true] {
+        let mut builder = VirtualMachine::builder()
+            .program(0x3000, &SUBROUTINE)
+            .breakpoint(0x3004);
+        if block_engine {
+            builder = builder.block_engine();
+        }
+        let mut vm = builder.build();
+
+        assert_eq!(HaltReason::Breakpoint(0x3004), vm.run());
+        assert_eq!(1, vm.registers().get(Register::R0));
+        let backtrace = vm.backtrace();
+        assert_eq!(0x3004, backtrace.pc);
+        assert_eq!(vec![call(0x3000, 0x3003)], backtrace.frames);
+
+        // Running again without resuming stays stopped
+        assert_eq!(HaltReason::Breakpoint(0x3004), vm.run());
+        vm.resume();
+        assert_eq!(HaltReason::Halt, vm.run());
+        assert_eq!(0, vm.call_stack().depth());
+        assert_eq!(0x3001, vm.backtrace().pc);
+    }
+}
+
+#[test]
+fn microsequencer_stops_at_breakpoints() {
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &SUBROUTINE)
+        .breakpoint(0x3003)
+        .build();
+    let mut microarchitecture = Microarchitecture::new();
+
+    assert_eq!(
+        HaltReason::Breakpoint(0x3003),
+        microarchitecture.run(&mut vm)
+    );
+    assert_eq!(vec![call(0x3000, 0x3003)], vm.backtrace().frames);
+    vm.clear_breakpoint(0x3003);
+    assert_eq!(0, vm.breakpoints().count());
+    vm.resume();
+    assert_eq!(HaltReason::Halt, microarchitecture.run(&mut vm));
+    assert_eq!(0, vm.call_stack().depth());
+}
+
+#[test]
+fn service_routines() {
+    // x3000: TRAP x30; RTI in user mode; HALT
+    // x4000: RTI
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &[0xF030, 0x8000, 0xF025])
+        .load(0x0030, &[0x4000])
+        .load(0x0100, &[0x4000])
+        .load(0x4000, &[0x8000])
+        .breakpoint(0x4000)
+        .build();
+    vm.registers_mut().set(Register::SavedSSP, 0x5000);
+
+    assert_eq!(HaltReason::Breakpoint(0x4000), vm.run());
+    let trap = StackFrame {
+        entry: Entry::Trap(0x30),
+        site: 0x3000,
+        routine: 0x4000,
+        return_address: 0x3001,
+    };
+    assert_eq!(vec![trap], vm.backtrace().frames);
+    assert_eq!(
+        "#0 x4000\n#1 x3000, TRAP x30 to x4000\n",
+        vm.backtrace().display(&Symbols::new()).to_string()
+    );
+
+    // RTI in user mode raises a privilege violation, which enters the same
+    // routine again
+    vm.resume();
+    assert_eq!(HaltReason::Breakpoint(0x4000), vm.run());
+    let exception = StackFrame {
+        entry: Entry::Exception(Exception::PrivilegeViolation),
+        site: 0x3001,
+        routine: 0x4000,
+        return_address: 0x3002,
+    };
+    assert_eq!(vec![exception], vm.backtrace().frames);
+    assert_eq!(PrivilegeMode::Privileged, vm.get_mode());
+
+    vm.resume();
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(0, vm.call_stack().depth());
+}
+
+#[test]
+fn runaway_calls_are_truncated() {
+    // x3000: JSR x3000
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &[0x4FFF])
+        .instruction_limit(5000)
+        .build();
+    assert_eq!(HaltReason::InstructionLimit, vm.run());
+
+    let backtrace = vm.backtrace();
+    assert_eq!(0x3000, backtrace.pc);
+    assert_eq!(MAX_CALL_DEPTH, backtrace.frames.len());
+    assert!(backtrace.truncated);
+    assert!(backtrace
+        .display(&Symbols::new())
+        .to_string()
+        .ends_with("#4096 x3000, JSR to x3000\n...\n"));
+}
+
+#[test]
+fn harness_reports_backtrace() {
+    let result = Harness::new().run(&NESTED);
+    assert_eq!(
+        Backtrace {
+            pc: 0x3006,
+            frames: vec![call(0x3003, 0x3006), call(0x3000, 0x3003)],
+            truncated: false,
+        },
+        result.backtrace
+    );
+
+    let result = Harness::new().breakpoint(0x3003).run(&SUBROUTINE);
+    assert_eq!(HaltReason::Breakpoint(0x3003), result.halt_reason);
+    assert_eq!(vec![call(0x3000, 0x3003)], result.backtrace.frames);
+}
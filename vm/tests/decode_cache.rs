@@ -0,0 +1,44 @@
+use vm::{HaltReason, Harness, Register};
+
+#[test]
+fn same_result_as_interpreter() {
+    // Counts to 10 * 10 with two nested loops
+    let program = [
+        0x5020, 0x2207, 0x2407, 0x1021, 0x14BF, 0x03FD, 0x127F, 0x03FA, 0xF025, 0x000A, 0x000A,
+    ];
+    let interpreted = Harness::new().run(&program);
+    let cached = Harness::new().decode_cache().run(&program);
+
+    assert_eq!(100, cached.registers.get(Register::R0));
+    assert_eq!(interpreted, cached);
+}
+
+#[test]
+fn self_modifying_code() {
+    // 0x3000: 0001 001 001 1 00001 = 0x1261 = ADD R1 R1 1
+    // 0x3001: 0001 000 000 1 00001 = 0x1021 = ADD R0 R0 1 (patched)
+    // 0x3002: 0010 010 000000100 = 0x2404 = LD R2 4
+    // 0x3003: 0011 010 111111101 = 0x35FD = ST R2 -3
+    // 0x3004: 0001 011 001 1 11110 = 0x167E = ADD R3 R1 -2
+    // 0x3005: 0000 100 111111010 = 0x09FA = BRn -6
+    // 0x3006: 1111 0000 00100101 = 0xF025 = HALT
+    // 0x3007: 0001 000 000 1 01010 = 0x102A = ADD R0 R0 10
+    let program = [
+        0x1261, 0x1021, 0x2404, 0x35FD, 0x167E, 0x09FA, 0xF025, 0x102A,
+    ];
+    let result = Harness::new().decode_cache().run(&program);
+
+    assert_eq!(11, result.registers.get(Register::R0));
+}
+
+#[test]
+fn stops_at_instruction_limit() {
+    // 0000 111 111111111 = 0x0FFF = BRnzp -1
+    let result = Harness::new()
+        .decode_cache()
+        .instruction_limit(10)
+        .run(&[0x0FFF]);
+
+    assert_eq!(HaltReason::InstructionLimit, result.halt_reason);
+    assert_eq!(10, result.instruction_count);
+}
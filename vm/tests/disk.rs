@@ -0,0 +1,239 @@
+use std::fs;
+
+use vm::{
+    Device, Disk, FileStorage, HaltReason, MemoryStorage, PrivilegeMode, Register, VirtualMachine,
+    DBUF, DCMD, DISK_ERROR, DISK_INTERRUPT, DISK_READ, DISK_READY, DISK_WRITE, DSEC, DSTAT,
+    INTERRUPT_VECTOR_TABLE, SECTOR_WORDS,
+};
+
+/// Runs `command` on `sector` with the buffer at `buffer` and polls the
+/// status register until it is done
+///
+/// x3000: 0010 100 000001001 = 0x2809 = LD R4 9
+/// x3001: 0010 000 000001001 = 0x2009 = LD R0 9
+/// x3002: 0111 000 100 000010 = 0x7102 = STR R0 R4 2 (DSEC)
+/// x3003: 0010 000 000001000 = 0x2008 = LD R0 8
+/// x3004: 0111 000 100 000100 = 0x7104 = STR R0 R4 4 (DBUF)
+/// x3005: 0010 000 000000111 = 0x2007 = LD R0 7
+/// x3006: 0111 000 100 000000 = 0x7100 = STR R0 R4 0 (DCMD)
+/// x3007: 0110 001 100 000110 = 0x6306 = LDR R1 R4 6 (DSTAT)
+/// x3008: 0000 011 111111110 = 0x07FE = BRzp -2
+/// x3009: 1111 0000 00100101 = 0xF025 = HALT
+fn polling(command: u16, sector: u16, buffer: u16) -> [u16; 14] {
+    [
+        0x2809, 0x2009, 0x7102, 0x2008, 0x7104, 0x2007, 0x7100, 0x6306, 0x07FE, 0xF025, 0xFE10,
+        sector, buffer, command,
+    ]
+}
+
+fn machine(program: &[u16], disk: Disk) -> VirtualMachine {
+    VirtualMachine::builder()
+        .program(0x3000, program)
+        .mode(PrivilegeMode::Privileged)
+        .device(Box::new(disk))
+        .instruction_limit(10_000)
+        .build()
+}
+
+fn sector_pattern(sector: u16) -> Vec<u16> {
+    (0..SECTOR_WORDS as u16)
+        .map(|word| (sector << 8) | word)
+        .collect()
+}
+
+#[test]
+fn read_sector_by_polling() {
+    let contents: Vec<u16> = (0..4).flat_map(sector_pattern).collect();
+    let disk = Disk::new(Box::new(MemoryStorage::with_contents(contents))).latency(50);
+    let mut vm = machine(&polling(DISK_READ, 2, 0x4000), disk);
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    let status = vm.registers().get(Register::R1);
+    assert_eq!(DISK_READY, status);
+    for (address, word) in (0x4000..).zip(sector_pattern(2)) {
+        assert_eq!(word, vm.memory_mut().read(address));
+    }
+    // The command completes 50 instructions after it is issued
+    assert!(vm.instruction_count() >= 7 + 50);
+}
+
+#[test]
+fn write_sector() {
+    let storage = MemoryStorage::new(4);
+    let contents = storage.contents();
+    let disk = Disk::new(Box::new(storage)).latency(10);
+    let mut vm = machine(&polling(DISK_WRITE, 3, 0x3000), disk);
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    let contents = contents.borrow();
+    let sector = &contents[3 * SECTOR_WORDS..][..SECTOR_WORDS];
+    assert_eq!(&polling(DISK_WRITE, 3, 0x3000), &sector[..14]);
+    assert!(contents[..3 * SECTOR_WORDS].iter().all(|word| *word == 0));
+}
+
+#[test]
+fn failed_commands() {
+    for program in [
+        polling(DISK_READ, 4, 0x4000),
+        polling(DISK_READ, 0, 0xFD01),
+        polling(7, 0, 0x4000),
+    ] {
+        let mut vm = machine(&program, Disk::new(Box::new(MemoryStorage::new(4))));
+
+        assert_eq!(HaltReason::Halt, vm.run());
+        assert_eq!(
+            DISK_READY | DISK_ERROR,
+            vm.registers().get(Register::R1),
+            "{program:04X?}"
+        );
+    }
+}
+
+#[test]
+fn completion_interrupt() {
+    // Enables interrupts, starts a read and waits for the handler to run
+    //
+    // x3000: 0010 100 000001011 = 0x280B = LD R4 11
+    // x3001: 0010 000 000001011 = 0x200B = LD R0 11
+    // x3002: 0111 000 100 000110 = 0x7106 = STR R0 R4 6 (DSTAT)
+    // x3003: 0010 000 000001010 = 0x200A = LD R0 10
+    // x3004: 0111 000 100 000010 = 0x7102 = STR R0 R4 2 (DSEC)
+    // x3005: 0010 000 000001001 = 0x2009 = LD R0 9
+    // x3006: 0111 000 100 000100 = 0x7104 = STR R0 R4 4 (DBUF)
+    // x3007: 0010 000 000001000 = 0x2008 = LD R0 8
+    // x3008: 0111 000 100 000000 = 0x7100 = STR R0 R4 0 (DCMD)
+    // x3009: 0001 101 101 1 00000 = 0x1B60 = ADD R5 R5 0
+    // x300A: 0000 010 111111110 = 0x05FE = BRz -2
+    // x300B: 1111 0000 00100101 = 0xF025 = HALT
+    let program = [
+        0x280B, 0x200B, 0x7106, 0x200A, 0x7102, 0x2009, 0x7104, 0x2008, 0x7100, 0x1B60, 0x05FE,
+        0xF025, 0xFE10, 0x4000, 0x0001, 0x4000, DISK_READ,
+    ];
+    // Handler:
+    // 0001 101 101 1 00001 = 0x1B61 = ADD R5 R5 1
+    // 1000 000000000000 = 0x8000 = RTI
+    let vector = INTERRUPT_VECTOR_TABLE + DISK_INTERRUPT.vector as u16;
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &program)
+        .load(0x1000, &[0x1B61, 0x8000])
+        .load(vector, &[0x1000])
+        .mode(PrivilegeMode::Privileged)
+        .device(Box::new(
+            Disk::new(Box::new(MemoryStorage::with_contents(
+                (0..2).flat_map(sector_pattern).collect(),
+            )))
+            .latency(20),
+        ))
+        .instruction_limit(10_000)
+        .build();
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(1, vm.registers().get(Register::R5));
+    assert_eq!(0, vm.get_priority());
+    assert_eq!(0x3000, vm.registers().get(Register::R6));
+    assert_eq!(sector_pattern(1)[5], vm.memory_mut().read(0x4005));
+}
+
+#[test]
+fn interrupt_waits_for_lower_priority() {
+    // Same program as `completion_interrupt`, but running at priority 7
+    let program = [
+        0x280B, 0x200B, 0x7106, 0x200A, 0x7102, 0x2009, 0x7104, 0x2008, 0x7100, 0x1B60, 0x05FE,
+        0xF025, 0xFE10, 0x4000, 0x0000, 0x4000, DISK_READ,
+    ];
+    let vector = INTERRUPT_VECTOR_TABLE + DISK_INTERRUPT.vector as u16;
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &program)
+        .load(0x1000, &[0x1B61, 0x8000])
+        .load(vector, &[0x1000])
+        .mode(PrivilegeMode::Privileged)
+        .priority(7)
+        .device(Box::new(
+            Disk::new(Box::new(MemoryStorage::new(1))).latency(20),
+        ))
+        .instruction_limit(1_000)
+        .build();
+
+    assert_eq!(HaltReason::InstructionLimit, vm.run());
+    assert_eq!(0, vm.registers().get(Register::R5));
+}
+
+#[test]
+fn file_storage() {
+    let path = std::env::temp_dir().join(format!("lc3-disk-{}.img", std::process::id()));
+    let image: Vec<u8> = (0..2)
+        .flat_map(sector_pattern)
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    fs::write(&path, image).unwrap();
+
+    let storage = FileStorage::open(&path).unwrap();
+    let mut vm = machine(&polling(DISK_READ, 1, 0x4000), Disk::new(Box::new(storage)));
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(sector_pattern(1)[0xFF], vm.memory_mut().read(0x40FF));
+
+    let storage = FileStorage::open(&path).unwrap();
+    let mut vm = machine(
+        &polling(DISK_WRITE, 0, 0x3000),
+        Disk::new(Box::new(storage)),
+    );
+    assert_eq!(HaltReason::Halt, vm.run());
+    let image = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(2 * 2 * SECTOR_WORDS, image.len());
+    assert_eq!([0x09, 0x28], image[..2]);
+    assert_eq!(sector_pattern(1)[0].to_le_bytes(), image[512..514]);
+}
+
+/// Issues a read and reads the status register five times, all in one basic
+/// block, so the block engine has to see the command finish part way through
+///
+/// x3000: 0010 100 000001100 = 0x280C = LD R4 12
+/// x3001: 0010 000 000001100 = 0x200C = LD R0 12
+/// x3002: 0111 000 100 000010 = 0x7102 = STR R0 R4 2 (DSEC)
+/// x3003: 0010 000 000001011 = 0x200B = LD R0 11
+/// x3004: 0111 000 100 000100 = 0x7104 = STR R0 R4 4 (DBUF)
+/// x3005: 0010 000 000001010 = 0x200A = LD R0 10
+/// x3006: 0111 000 100 000000 = 0x7100 = STR R0 R4 0 (DCMD)
+/// x3007: 0110 001 100 000110 = 0x6306 = LDR R1 R4 6 (DSTAT)
+/// x3008: 0110 010 100 000110 = 0x6506 = LDR R2 R4 6
+/// x3009: 0110 011 100 000110 = 0x6706 = LDR R3 R4 6
+/// x300A: 0110 101 100 000110 = 0x6B06 = LDR R5 R4 6
+/// x300B: 0110 110 100 000110 = 0x6D06 = LDR R6 R4 6
+/// x300C: 1111 0000 00100101 = 0xF025 = HALT
+#[test]
+fn block_engine_matches_interpreter() {
+    let program = [
+        0x280C, 0x200C, 0x7102, 0x200B, 0x7104, 0x200A, 0x7100, 0x6306, 0x6506, 0x6706, 0x6B06,
+        0x6D06, 0xF025, 0xFE10, 1, 0x4000, DISK_READ,
+    ];
+    let run = |block_engine: bool| {
+        let disk = Disk::new(Box::new(MemoryStorage::new(4))).latency(2);
+        let mut builder = VirtualMachine::builder()
+            .program(0x3000, &program)
+            .mode(PrivilegeMode::Privileged)
+            .device(Box::new(disk))
+            .instruction_limit(100);
+        if block_engine {
+            builder = builder.block_engine();
+        }
+        let mut vm = builder.build();
+        assert_eq!(HaltReason::Halt, vm.run());
+        (vm.registers().clone(), vm.instruction_count())
+    };
+
+    let interpreted = run(false);
+    assert_eq!(0, interpreted.0.get(Register::R1) & DISK_READY);
+    assert_eq!(DISK_READY, interpreted.0.get(Register::R6));
+    assert_eq!(interpreted, run(true));
+}
+
+#[test]
+fn registers_are_word_aligned() {
+    let disk = Disk::new(Box::new(MemoryStorage::new(1)));
+    for register in [DCMD, DSEC, DBUF, DSTAT] {
+        assert_eq!(0, register % 2);
+        assert!(disk.contains(register));
+        assert!(!disk.contains(register + 1));
+    }
+}
@@ -0,0 +1,91 @@
+use vm::{
+    Frame, Framebuffer, HaltReason, Harness, MemoryInit, VirtualMachine, FRAMEBUFFER_HEIGHT,
+    FRAMEBUFFER_WIDTH,
+};
+
+// 0010 001 000000101 = 0x2205 = LD R1 5
+// 0010 000 000000101 = 0x2005 = LD R0 5
+// 0111 000 001 000000 = 0x7040 = STR R0 R1 0
+// 0111 000 001 000001 = 0x7041 = STR R0 R1 1
+// 1011 000 000000011 = 0xB003 = STI R0 3
+// 1111 0000 00100101 = 0xF025 = HALT
+const PROGRAM: [u16; 9] = [
+    0x2205, 0x2005, 0x7040, 0x7041, 0xB003, 0xF025, 0xC000, 0x7C00, 0xC081,
+];
+
+fn frame() -> Frame {
+    let result = Harness::new().framebuffer().run(&PROGRAM);
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+    result.frame.unwrap()
+}
+
+#[test]
+fn user_program_draws_pixels() {
+    let frame = frame();
+
+    assert_eq!(0x7C00, frame.pixel(0, 0));
+    assert_eq!(0x7C00, frame.pixel(1, 0));
+    assert_eq!(0x7C00, frame.pixel(1, 1));
+    assert_eq!(0, frame.pixel(0, 1));
+    assert_eq!([255, 0, 0], frame.rgb(0, 0));
+    assert_eq!(
+        3,
+        frame.pixels().iter().filter(|pixel| **pixel != 0).count()
+    );
+}
+
+#[test]
+fn frames_compare_equal() {
+    assert_eq!(frame(), frame());
+    assert_ne!(Frame::new(), frame());
+    assert_eq!(None, Harness::new().run(&PROGRAM).frame);
+}
+
+#[test]
+fn ppm() {
+    let ppm = frame().to_ppm();
+    let header = b"P6\n128 124\n255\n";
+
+    assert_eq!(header, &ppm[..header.len()]);
+    assert_eq!(
+        header.len() + 3 * FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT,
+        ppm.len()
+    );
+    assert_eq!([255, 0, 0, 255, 0, 0, 0, 0, 0], ppm[header.len()..][..9]);
+}
+
+#[test]
+fn png() {
+    let png = frame().to_png();
+
+    assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+    assert_eq!(b"IHDR", &png[12..16]);
+    assert_eq!(128, u32::from_be_bytes(png[16..20].try_into().unwrap()));
+    assert_eq!(124, u32::from_be_bytes(png[20..24].try_into().unwrap()));
+    // CRC of the IHDR chunk
+    assert_eq!([0x2E, 0x0C, 0x2F, 0xFE], png[29..33]);
+    assert_eq!(b"IEND\xae\x42\x60\x82", &png[png.len() - 8..]);
+}
+
+#[test]
+fn preview() {
+    let preview = frame().preview();
+    let lines: Vec<&str> = preview.lines().collect();
+
+    assert_eq!(FRAMEBUFFER_HEIGHT / 2, lines.len());
+    assert!(lines.iter().all(|line| line.chars().count() == 128));
+    assert!(lines[0].starts_with("▀█ "));
+    assert!(lines[1..].iter().all(|line| line.trim().is_empty()));
+}
+
+#[test]
+fn memory_init_leaves_the_frame_black() {
+    let framebuffer = Framebuffer::new();
+    let frame = framebuffer.frame();
+    let _vm = VirtualMachine::builder()
+        .memory_init(MemoryInit::Random(7))
+        .device(Box::new(framebuffer))
+        .build();
+
+    assert_eq!(Frame::new(), *frame.borrow());
+}
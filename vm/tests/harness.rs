@@ -0,0 +1,149 @@
+use vm::{Exception, HaltReason, Harness, PrivilegeMode, Register};
+
+#[test]
+fn puts() {
+    // 1110 000 000000010 = 0xE002 = LEA R0 2
+    // 1111 0000 00100010 = 0xF022 = PUTS
+    // 1111 0000 00100101 = 0xF025 = HALT
+    let program = [0xE002, 0xF022, 0xF025, 0x0048, 0x0069, 0x0000];
+    let result = Harness::new().run(&program);
+
+    assert_eq!(b"Hi", result.output.as_slice());
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+    assert_eq!(3, result.instruction_count);
+}
+
+#[test]
+fn putsp() {
+    // 1110 000 000000010 = 0xE002 = LEA R0 2
+    // 1111 0000 00100100 = 0xF024 = PUTSP
+    // 1111 0000 00100101 = 0xF025 = HALT
+    let program = [0xE002, 0xF024, 0xF025, 0x6948, 0x0021, 0x0000];
+    let result = Harness::new().run(&program);
+
+    assert_eq!(b"Hi!", result.output.as_slice());
+}
+
+#[test]
+fn getc_and_out() {
+    // 1111 0000 00100000 = 0xF020 = GETC
+    // 1111 0000 00100001 = 0xF021 = OUT
+    let program = [0xF020, 0xF021, 0xF020, 0xF021, 0xF025];
+    let result = Harness::new().input(b"ab").run(&program);
+
+    assert_eq!(b"ab", result.output.as_slice());
+    assert_eq!(b'b' as u16, result.registers.get(Register::R0));
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+}
+
+#[test]
+fn in_echoes_with_prompt() {
+    // 1111 0000 00100011 = 0xF023 = IN
+    let program = [0xF023, 0xF025];
+    let result = Harness::new().input(b"q").run(&program);
+
+    assert_eq!(b"\nInput a character> q\n", result.output.as_slice());
+    assert_eq!(b'q' as u16, result.registers.get(Register::R0));
+}
+
+#[test]
+fn input_exhausted() {
+    let program = [0xF020, 0xF025];
+    let result = Harness::new().run(&program);
+
+    assert_eq!(HaltReason::InputExhausted, result.halt_reason);
+}
+
+#[test]
+fn instruction_limit() {
+    // 0000 111 111111111 = 0x0FFF = BRnzp -1
+    let program = [0x0FFF];
+    let result = Harness::new().instruction_limit(100).run(&program);
+
+    assert_eq!(HaltReason::InstructionLimit, result.halt_reason);
+    assert_eq!(100, result.instruction_count);
+}
+
+#[test]
+fn unknown_trap() {
+    // 1111 0000 00010000 = 0xF010 = TRAP x10
+    let program = [0xF010];
+    let result = Harness::new().run(&program);
+
+    assert_eq!(HaltReason::UnknownTrap(0x10), result.halt_reason);
+}
+
+#[test]
+fn display_device() {
+    // 0010 000 000000011 = 0x2003 = LD R0 3
+    // 1011 000 000000011 = 0xB003 = STI R0 3
+    let program = [0x2003, 0xB003, 0xF025, 0x0000, 0x0041, 0xFE06];
+    let result = Harness::new().mode(PrivilegeMode::Privileged).run(&program);
+
+    assert_eq!(b"A", result.output.as_slice());
+}
+
+#[test]
+fn keyboard_device() {
+    // 0010 010 000000100 = 0x2404 = LD R2 4
+    // 0110 001 010 000000 = 0x6280 = LDR R1 R2 0
+    // 0110 000 010 000010 = 0x6082 = LDR R0 R2 2
+    let program = [0x2404, 0x6280, 0x6082, 0xF025, 0x0000, 0xFE00];
+    let result = Harness::new()
+        .mode(PrivilegeMode::Privileged)
+        .input(b"z")
+        .run(&program);
+
+    assert_eq!(0x8000, result.registers.get(Register::R1));
+    assert_eq!(b'z' as u16, result.registers.get(Register::R0));
+}
+
+#[test]
+fn machine_control_register() {
+    // 0101 000 000 1 00000 = 0x5020 = AND R0 R0 0
+    // 1011 000 000000000 = 0xB000 = STI R0 0
+    let program = [0x5020, 0xB000, 0xFFFE];
+    let result = Harness::new().mode(PrivilegeMode::Privileged).run(&program);
+
+    assert_eq!(HaltReason::MachineControl, result.halt_reason);
+    assert_eq!(2, result.instruction_count);
+}
+
+#[test]
+fn origin() {
+    // 1110 000 000000010 = 0xE002 = LEA R0 2
+    let program = [0xE002, 0xF025];
+    let result = Harness::new().origin(0x4000).run(&program);
+
+    assert_eq!(0x4003, result.registers.get(Register::R0));
+}
+
+#[test]
+fn devices_are_privileged() {
+    // 0010 000 000000011 = 0x2003 = LD R0 3
+    // 1011 000 000000011 = 0xB003 = STI R0 3
+    let program = [0x2003, 0xB003, 0xF025, 0x0000, 0x0041, 0xFE06];
+    let result = Harness::new().run(&program);
+
+    assert!(result.output.is_empty());
+    assert_eq!(
+        HaltReason::Exception(Exception::AccessViolation),
+        result.halt_reason
+    );
+}
+
+#[test]
+fn os_image_handles_exceptions() {
+    // The access violation vector x0102 points at a handler at x0200
+    // 0x0200: 0101 101 101 1 00000 = 0x5B60 = AND R5 R5 0
+    // 0x0201: 0001 101 101 1 00111 = 0x1B67 = ADD R5 R5 7
+    // 0x0202: 1111 0000 00100101 = 0xF025 = HALT
+    let mut image = vec![0; 0x0203];
+    image[0x0102] = 0x0200;
+    image[0x0200..].copy_from_slice(&[0x5B60, 0x1B67, 0xF025]);
+    let program = [0x2003, 0xB003, 0xF025, 0x0000, 0x0041, 0xFE06];
+    let result = Harness::new().os_image(&image).run(&program);
+
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+    assert_eq!(7, result.registers.get(Register::R5));
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "lc3b")]
+
+use vm::{Exception, HaltReason, Harness, Isa, PrivilegeMode, Register, VirtualMachine};
+
+fn builder(program: &[u16]) -> vm::VirtualMachineBuilder {
+    VirtualMachine::builder()
+        .isa(Isa::Lc3b)
+        .program(0x3000, program)
+        .instruction_limit(100)
+}
+
+#[test]
+fn parse_isa() {
+    assert_eq!(Ok(Isa::Lc3b), "LC-3b".parse());
+    assert_eq!(Ok(Isa::Lc3b), "lc3b".parse());
+    assert_eq!(Ok(Isa::Lc3), "LC3".parse());
+    assert!("LC-4".parse::<Isa>().is_err());
+}
+
+#[test]
+fn vector_tables_hold_words() {
+    assert_eq!(0x004A, Isa::Lc3b.trap_vector(0x25));
+    assert_eq!(0x0206, Isa::Lc3b.interrupt_vector(0x03));
+    assert_eq!(0x0400, Isa::Lc3b.vector_tables_end());
+    assert_eq!(0x0025, Isa::Lc3.trap_vector(0x25));
+    assert_eq!(0x0200, Isa::Lc3.vector_tables_end());
+}
+
+// x3000: 1110 001 000000111 = 0xE207 = LEA R1 x3010
+// x3002: 0010 010 001 000001 = 0x2441 = LDB R2 R1 1
+// x3004: 0010 011 001 000000 = 0x2640 = LDB R3 R1 0
+// x3006: 0011 011 001 000011 = 0x3643 = STB R3 R1 3
+// x3008: 0110 100 001 000001 = 0x6841 = LDW R4 R1 1
+// x300A: 1111 0000 00100101 = 0xF025 = HALT
+// x3010: 0x8034
+// x3012: 0x0056
+const BYTES: [u16; 10] = [
+    0xE207, 0x2441, 0x2640, 0x3643, 0x6841, 0xF025, 0x0000, 0x0000, 0x8034, 0x0056,
+];
+
+#[test]
+fn byte_loads_and_stores() {
+    for decode_cache in [false, true] {
+        let mut builder = builder(&BYTES);
+        if decode_cache {
+            builder = builder.decode_cache();
+        }
+        let mut vm = builder.build();
+        assert_eq!(HaltReason::Halt, vm.run());
+
+        assert_eq!(0x3010, vm.registers().get(Register::R1));
+        // Words are little-endian, the high byte is at the odd address
+        assert_eq!(0xFF80, vm.registers().get(Register::R2));
+        assert_eq!(0x0034, vm.registers().get(Register::R3));
+        assert_eq!(0x3456, vm.memory_mut().read(0x3012));
+        assert_eq!(0x3456, vm.registers().get(Register::R4));
+        assert_eq!(0x300C, vm.registers().get(Register::PC));
+        assert_eq!(6, vm.instruction_count());
+    }
+}
+
+#[test]
+fn unaligned_word_access() {
+    // LEA R1 x3010; ADD R1 R1 1; LDW R2 R1 0
+    let mut vm = builder(&[0xE207, 0x1261, 0x6440]).build();
+    assert_eq!(HaltReason::Exception(Exception::UnalignedAccess), vm.run());
+    assert_eq!(0, vm.registers().get(Register::R2));
+}
+
+// x3000: 0101 000 000 1 00000 = 0x5020 = AND R0 R0 0
+// x3002: 0001 000 000 1 11000 = 0x1038 = ADD R0 R0 -8
+// x3004: 1101 001 000 00 0010 = 0xD202 = LSHF R1 R0 2
+// x3006: 1101 010 000 01 0001 = 0xD411 = RSHFL R2 R0 1
+// x3008: 1101 011 000 11 0010 = 0xD632 = RSHFA R3 R0 2
+// x300A: 1001 100 000 1 11111 = 0x983F = NOT R4 R0
+// x300C: 1001 101 100 0 00 000 = 0x9B00 = XOR R5 R4 R0
+// x300E: 1111 0000 00100101 = 0xF025 = HALT
+#[test]
+fn shifts_and_xor() {
+    let program = [
+        0x5020, 0x1038, 0xD202, 0xD411, 0xD632, 0x983F, 0x9B00, 0xF025,
+    ];
+    let mut vm = builder(&program).build();
+    assert_eq!(HaltReason::Halt, vm.run());
+
+    assert_eq!(0xFFE0, vm.registers().get(Register::R1));
+    assert_eq!(0x7FFC, vm.registers().get(Register::R2));
+    assert_eq!(0xFFFE, vm.registers().get(Register::R3));
+    assert_eq!(0x0007, vm.registers().get(Register::R4));
+    assert_eq!(0xFFFF, vm.registers().get(Register::R5));
+}
+
+// x3000: 0100 1 00000000100 = 0x4804 = JSR x300A
+// x3002: 0000 111 000000001 = 0x0E01 = BRnzp x3006
+// x3004: 0001 001 001 1 00001 = 0x1261 = ADD R1 R1 1
+// x3006: 1111 0000 00100101 = 0xF025 = HALT
+// x300A: 0001 000 000 1 00101 = 0x1025 = ADD R0 R0 5
+// x300C: 1100 000 111 000000 = 0xC1C0 = RET
+#[test]
+fn pc_relative_offsets_are_scaled() {
+    let program = [0x4804, 0x0E01, 0x1261, 0xF025, 0x0000, 0x1025, 0xC1C0];
+    let mut vm = builder(&program).build();
+    assert_eq!(HaltReason::Halt, vm.run());
+
+    assert_eq!(5, vm.registers().get(Register::R0));
+    assert_eq!(0, vm.registers().get(Register::R1));
+    assert_eq!(0x3002, vm.registers().get(Register::R7));
+    assert_eq!(0x3008, vm.registers().get(Register::PC));
+}
+
+#[test]
+fn indirect_loads_are_illegal() {
+    // LDI R0 0
+    let mut vm = builder(&[0xA000]).build();
+    assert_eq!(HaltReason::Exception(Exception::IllegalOpcode), vm.run());
+}
+
+#[test]
+fn trap_through_vector_table() {
+    // x4000: AND R5 R5 0; ADD R5 R5 7; RTI
+    let mut vm = builder(&[0xF030, 0xF025])
+        .mode(PrivilegeMode::Privileged)
+        .load(Isa::Lc3b.trap_vector(0x30), &[0x4000])
+        .load(0x4000, &[0x5B60, 0x1B67, 0x8000])
+        .build();
+    vm.registers_mut().set(Register::R6, 0x5000);
+    assert_eq!(HaltReason::Halt, vm.run());
+
+    assert_eq!(7, vm.registers().get(Register::R5));
+    assert_eq!(0x5000, vm.registers().get(Register::R6));
+    assert_eq!(0x3002, vm.memory_mut().read(0x4FFC));
+    assert_eq!(0x3004, vm.registers().get(Register::PC));
+}
+
+#[test]
+fn built_in_puts() {
+    // LEA R0 x3006; PUTS; HALT; "Hi"
+    let program = [0xE002, 0xF022, 0xF025, 0x0048, 0x0069, 0x0000];
+    let result = Harness::new().isa(Isa::Lc3b).run(&program);
+    assert_eq!(b"Hi", result.output.as_slice());
+    assert_eq!(HaltReason::Halt, result.halt_reason);
+}
+
+#[test]
+fn microarchitecture_only_models_the_lc3() {
+    let result = Harness::new()
+        .isa(Isa::Lc3b)
+        .microarchitecture()
+        .run(&[0xF025]);
+    assert_eq!(HaltReason::UnsupportedIsa(Isa::Lc3b), result.halt_reason);
+    assert_eq!(0, result.instruction_count);
+}
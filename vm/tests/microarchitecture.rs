@@ -0,0 +1,307 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use vm::{
+    Device, HaltReason, Instruction, Interrupt, Memory, MemoryInit, Microarchitecture, Observer,
+    PrivilegeMode, Register, Registers, ScriptedConsole, State, VirtualMachine, KBSR,
+};
+
+/// Every observer callback, formatted
+#[derive(Clone, Default)]
+struct Events(Rc<RefCell<Vec<String>>>);
+
+impl Observer for Events {
+    fn on_fetch(&mut self, pc: u16, instruction: u16) {
+        self.0
+            .borrow_mut()
+            .push(format!("fetch {pc:04X} {instruction:04X}"));
+    }
+
+    fn on_execute(&mut self, pc: u16, instruction: &Instruction, registers: &Registers) {
+        self.0
+            .borrow_mut()
+            .push(format!("execute {pc:04X} {instruction:?} {registers:?}"));
+    }
+
+    fn on_mem_read(&mut self, address: u16, value: u16) {
+        self.0
+            .borrow_mut()
+            .push(format!("read {address:04X} {value:04X}"));
+    }
+
+    fn on_mem_write(&mut self, address: u16, value: u16) {
+        self.0
+            .borrow_mut()
+            .push(format!("write {address:04X} {value:04X}"));
+    }
+
+    fn on_trap(&mut self, vector: u8, _registers: &Registers) {
+        self.0.borrow_mut().push(format!("trap {vector:02X}"));
+    }
+
+    fn on_interrupt(&mut self, vector: u16, _registers: &Registers) {
+        self.0.borrow_mut().push(format!("interrupt {vector:04X}"));
+    }
+
+    fn on_halt(&mut self, reason: HaltReason, _registers: &Registers) {
+        self.0.borrow_mut().push(format!("halt {reason:?}"));
+    }
+}
+
+/// xorshift32
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u16 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as u16
+    }
+}
+
+struct Run {
+    vm: VirtualMachine,
+    output: Rc<RefCell<Vec<u8>>>,
+    events: Events,
+}
+
+fn machine(seed: u32) -> Run {
+    let mut random = Random(seed);
+    let console = ScriptedConsole::new(b"lc3");
+    let output = console.output();
+    let events = Events::default();
+    let mode = if seed.is_multiple_of(2) {
+        PrivilegeMode::Privileged
+    } else {
+        PrivilegeMode::User
+    };
+    let vector_tables: Vec<u16> = (0..0x200)
+        .map(|_| {
+            if seed.is_multiple_of(3) {
+                random.next()
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    let mut vm = VirtualMachine::builder()
+        .entry_point(0x3000 + random.next() % 0x100)
+        .mode(mode)
+        .memory_init(MemoryInit::Random(seed))
+        .load(0x0000, &vector_tables)
+        .console(Box::new(console))
+        .observer(Box::new(events.clone()))
+        .instruction_limit(100)
+        .build();
+    for register in [
+        Register::R0,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+        Register::SavedSSP,
+    ] {
+        vm.registers_mut().set(register, random.next());
+    }
+    Run { vm, output, events }
+}
+
+fn assert_same(case: &str, interpreted: &mut Run, micro: &mut Run) {
+    assert_eq!(
+        interpreted.vm.halt_reason(),
+        micro.vm.halt_reason(),
+        "{case}"
+    );
+    assert_eq!(interpreted.vm.registers(), micro.vm.registers(), "{case}");
+    assert_eq!(
+        interpreted.vm.instruction_count(),
+        micro.vm.instruction_count(),
+        "{case}"
+    );
+    assert_eq!(
+        *interpreted.output.borrow(),
+        *micro.output.borrow(),
+        "{case}"
+    );
+    assert_eq!(
+        *interpreted.events.0.borrow(),
+        *micro.events.0.borrow(),
+        "{case}"
+    );
+    assert_eq!(interpreted.vm.backtrace(), micro.vm.backtrace(), "{case}");
+    for address in 0..KBSR {
+        assert_eq!(
+            interpreted.vm.memory_mut().read(address),
+            micro.vm.memory_mut().read(address),
+            "{case}: memory at {address:#06X}"
+        );
+    }
+}
+
+#[test]
+fn random_programs_match_interpreter() {
+    for seed in 1..=200 {
+        let mut interpreted = machine(seed);
+        let mut micro = machine(seed);
+
+        interpreted.vm.run();
+        Microarchitecture::new().run(&mut micro.vm);
+
+        assert_same(&format!("seed {seed}"), &mut interpreted, &mut micro);
+    }
+}
+
+/// Requests one interrupt after a number of instructions
+struct Timer {
+    remaining: u64,
+    fired: bool,
+}
+
+impl Device for Timer {
+    fn contains(&self, _address: u16) -> bool {
+        false
+    }
+
+    fn read(&mut self, _address: u16) -> u16 {
+        0
+    }
+
+    fn write(&mut self, _address: u16, _value: u16) {}
+
+    fn tick(&mut self, _memory: &mut Memory, instructions: u64) {
+        self.remaining = self.remaining.saturating_sub(instructions);
+    }
+
+    fn interrupt(&self) -> Option<Interrupt> {
+        (self.remaining == 0 && !self.fired).then_some(Interrupt {
+            vector: 0x80,
+            priority: 4,
+        })
+    }
+
+    fn acknowledge(&mut self) {
+        self.fired = true;
+    }
+}
+
+#[test]
+fn device_interrupts_match_interpreter() {
+    let timer = || {
+        Box::new(Timer {
+            remaining: 3,
+            fired: false,
+        })
+    };
+    // x3000: ADD R1 R1 1 (five times); HALT
+    // x1000: ADD R5 R5 1; RTI
+    let build = |device: Box<dyn Device>| {
+        let events = Events::default();
+        let mut vm = VirtualMachine::builder()
+            .program(0x3000, &[0x1261, 0x1261, 0x1261, 0x1261, 0x1261, 0xF025])
+            .load(0x0180, &[0x1000])
+            .load(0x1000, &[0x1B61, 0x8000])
+            .observer(Box::new(events.clone()))
+            .device(device)
+            .build();
+        vm.registers_mut().set(Register::SavedSSP, 0x3000);
+        Run {
+            vm,
+            output: Rc::default(),
+            events,
+        }
+    };
+
+    let mut interpreted = build(timer());
+    assert_eq!(HaltReason::Halt, interpreted.vm.run());
+
+    let mut micro = build(timer());
+    let mut microarchitecture = Microarchitecture::new();
+    let mut states = Vec::new();
+    while micro.vm.halt_reason().is_none() {
+        let instruction = microarchitecture.step(&mut micro.vm);
+        states.extend(instruction.cycles.iter().map(|cycle| cycle.state));
+    }
+
+    assert_same("timer", &mut interpreted, &mut micro);
+    assert_eq!(1, micro.vm.registers().get(Register::R5));
+    assert!(states.contains(&State::Interrupt));
+    assert!(states.contains(&State::SwitchStack));
+    assert!(states.contains(&State::RtiStack));
+}
+
+#[test]
+fn cycle_counts_include_wait_states() {
+    // ADD R0 R0 1; LD R1 #1; HALT; x1234
+    let program = [0x1021, 0x2201, 0xF025, 0x1234];
+    let mut vm = VirtualMachine::builder().program(0x3000, &program).build();
+    let mut microarchitecture = Microarchitecture::new();
+
+    let add = microarchitecture.step(&mut vm);
+    assert_eq!(9, add.count());
+    assert_eq!(4, add.wait_states());
+
+    let ld = microarchitecture.step(&mut vm);
+    assert_eq!(15, ld.count());
+    assert_eq!(8, ld.wait_states());
+
+    assert_eq!(24, microarchitecture.cycles());
+    assert_eq!(12, microarchitecture.wait_states());
+    assert_eq!(0x1234, vm.registers().get(Register::R1));
+
+    let mut vm = VirtualMachine::builder().program(0x3000, &program).build();
+    let add = Microarchitecture::new().memory_cycles(1).step(&mut vm);
+    assert_eq!(5, add.count());
+    assert_eq!(0, add.wait_states());
+}
+
+#[test]
+fn load_trace() {
+    // LD R1 #1; HALT; x1234
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &[0x2201, 0xF025, 0x1234])
+        .build();
+    let instruction = Microarchitecture::new().memory_cycles(2).step(&mut vm);
+
+    let trace: Vec<_> = instruction
+        .cycles
+        .iter()
+        .map(|cycle| (cycle.state, cycle.bus, cycle.mar, cycle.mdr, cycle.wait))
+        .collect();
+    assert_eq!(
+        vec![
+            (State::Fetch, Some(0x3000), 0x3000, 0x0000, false),
+            (State::FetchMemory, None, 0x3000, 0x0000, true),
+            (State::FetchMemory, None, 0x3000, 0x2201, false),
+            (State::LoadIr, Some(0x2201), 0x3000, 0x2201, false),
+            (State::Decode, None, 0x3000, 0x2201, false),
+            (State::Ld, Some(0x3002), 0x3002, 0x2201, false),
+            (State::LoadMemory, None, 0x3002, 0x2201, true),
+            (State::LoadMemory, None, 0x3002, 0x1234, false),
+            (State::LoadRegister, Some(0x1234), 0x3002, 0x1234, false),
+        ],
+        trace
+    );
+    assert!(instruction.cycles[4..]
+        .iter()
+        .all(|cycle| cycle.ir == 0x2201));
+    assert_eq!(Some(27), State::LoadRegister.number());
+    assert_eq!("27: DR <- MDR, set CC", State::LoadRegister.to_string());
+}
+
+#[test]
+fn branch_enable() {
+    // BRz #1 with Z set; the branch is taken in state 22
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &[0x0401])
+        .psr(0x8002)
+        .build();
+    let instruction = Microarchitecture::new().step(&mut vm);
+    assert!(instruction.cycles.last().unwrap().ben);
+    assert_eq!(State::BrTaken, instruction.cycles.last().unwrap().state);
+    assert_eq!(0x3002, vm.registers().get(Register::PC));
+}
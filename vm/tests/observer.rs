@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use vm::{
+    Exception, HaltReason, Instruction, Observer, Register, Registers, ScriptedConsole,
+    VirtualMachine,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    Fetch(u16, u16),
+    Execute(u16),
+    Read(u16, u16),
+    Write(u16, u16),
+    Trap(u8),
+    Interrupt(u16),
+    Halt(HaltReason),
+}
+
+struct Recorder(Rc<RefCell<Vec<Event>>>);
+
+impl Observer for Recorder {
+    fn on_fetch(&mut self, pc: u16, word: u16) {
+        self.0.borrow_mut().push(Event::Fetch(pc, word));
+    }
+
+    fn on_execute(&mut self, pc: u16, _instruction: &Instruction, _registers: &Registers) {
+        self.0.borrow_mut().push(Event::Execute(pc));
+    }
+
+    fn on_mem_read(&mut self, address: u16, value: u16) {
+        self.0.borrow_mut().push(Event::Read(address, value));
+    }
+
+    fn on_mem_write(&mut self, address: u16, value: u16) {
+        self.0.borrow_mut().push(Event::Write(address, value));
+    }
+
+    fn on_trap(&mut self, vector: u8, _registers: &Registers) {
+        self.0.borrow_mut().push(Event::Trap(vector));
+    }
+
+    fn on_interrupt(&mut self, vector: u16, _registers: &Registers) {
+        self.0.borrow_mut().push(Event::Interrupt(vector));
+    }
+
+    fn on_halt(&mut self, reason: HaltReason, _registers: &Registers) {
+        self.0.borrow_mut().push(Event::Halt(reason));
+    }
+}
+
+fn record(program: &[u16], block_engine: bool) -> Vec<Event> {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut builder = VirtualMachine::builder()
+        .program(0x3000, program)
+        .console(Box::new(ScriptedConsole::default()))
+        .observer(Box::new(Recorder(events.clone())))
+        .instruction_limit(100);
+    if block_engine {
+        builder = builder.block_engine();
+    }
+    builder.build().run();
+    events.take()
+}
+
+#[test]
+fn load_store_and_halt() {
+    // 0010 000 000000010 = 0x2002 = LD R0 2
+    // 0011 000 000000010 = 0x3002 = ST R0 2
+    // 1111 0000 00100101 = 0xF025 = HALT
+    let program = [0x2002, 0x3002, 0xF025, 0x0007];
+
+    assert_eq!(
+        vec![
+            Event::Fetch(0x3000, 0x2002),
+            Event::Read(0x3003, 0x0007),
+            Event::Execute(0x3000),
+            Event::Fetch(0x3001, 0x3002),
+            Event::Write(0x3004, 0x0007),
+            Event::Execute(0x3001),
+            Event::Fetch(0x3002, 0xF025),
+            Event::Trap(0x25),
+            Event::Read(0x0025, 0x0000),
+            Event::Halt(HaltReason::Halt),
+            Event::Execute(0x3002),
+        ],
+        record(&program, false)
+    );
+}
+
+#[test]
+fn exception() {
+    // 1101 000000000000 = 0xD000 = RES
+    let program = [0xD000];
+
+    assert_eq!(
+        vec![
+            Event::Fetch(0x3000, 0xD000),
+            Event::Interrupt(Exception::IllegalOpcode.vector()),
+            Event::Read(Exception::IllegalOpcode.vector(), 0x0000),
+            Event::Halt(HaltReason::Exception(Exception::IllegalOpcode)),
+            Event::Execute(0x3000),
+        ],
+        record(&program, false)
+    );
+}
+
+#[test]
+fn block_engine_reports_every_instruction() {
+    // 0001 000 000 1 00001 = 0x1021 = ADD R0 R0 1
+    let program = [0x1021, 0x1021, 0xF025];
+
+    assert_eq!(record(&program, false), record(&program, true));
+}
+
+#[test]
+fn observers_do_not_change_execution() {
+    // Counts to 10 * 10 with two nested loops
+    let program = [
+        0x5020, 0x2207, 0x2407, 0x1021, 0x14BF, 0x03FD, 0x127F, 0x03FA, 0xF025, 0x000A, 0x000A,
+    ];
+    let mut observed = VirtualMachine::builder()
+        .program(0x3000, &program)
+        .observer(Box::new(Recorder(Rc::default())))
+        .build();
+
+    assert_eq!(HaltReason::Halt, observed.run());
+    assert_eq!(100, observed.registers().get(Register::R0));
+}
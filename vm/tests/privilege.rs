@@ -0,0 +1,136 @@
+use vm::{Exception, HaltReason, PrivilegeMode, Register, VirtualMachine};
+
+fn load(vm: &mut VirtualMachine, address: u16, binary: &[u16]) {
+    for (address, line) in (address..).zip(binary) {
+        vm.memory_mut().write(address, *line);
+    }
+}
+
+#[test]
+fn starts_in_user_mode() {
+    let vm = VirtualMachine::new();
+
+    assert_eq!(PrivilegeMode::User, vm.get_mode());
+    assert_eq!(0, vm.get_priority());
+}
+
+#[test]
+fn set_mode_swaps_stack_pointers() {
+    let mut vm = VirtualMachine::new();
+    vm.registers_mut().set(Register::R6, 0x4000);
+
+    vm.set_mode(PrivilegeMode::Privileged);
+    assert_eq!(0x3000, vm.registers().get(Register::R6));
+    assert_eq!(0x4000, vm.registers().get(Register::SavedUSP));
+    assert_eq!(0x0002, vm.registers().get(Register::PSR));
+
+    vm.set_mode(PrivilegeMode::User);
+    assert_eq!(0x4000, vm.registers().get(Register::R6));
+    assert_eq!(0x3000, vm.registers().get(Register::SavedSSP));
+    assert_eq!(0x8002, vm.registers().get(Register::PSR));
+}
+
+#[test]
+fn user_access_violation_without_handler() {
+    // 0110 000 001 000000 = 0x6040 = LDR R0 R1 0
+    let mut vm = VirtualMachine::new();
+    load(&mut vm, 0x3000, &[0x6040]);
+    vm.registers_mut().set(Register::R1, 0x0200);
+
+    assert_eq!(HaltReason::Exception(Exception::AccessViolation), vm.run());
+    assert_eq!(0, vm.registers().get(Register::R0));
+}
+
+#[test]
+fn user_store_to_device_register() {
+    // 0111 000 001 000000 = 0x7040 = STR R0 R1 0
+    let mut vm = VirtualMachine::new();
+    load(&mut vm, 0x3000, &[0x7040]);
+    vm.registers_mut().set(Register::R1, 0xFFFE);
+
+    assert_eq!(HaltReason::Exception(Exception::AccessViolation), vm.run());
+}
+
+#[test]
+fn supervisor_access_to_system_space() {
+    // 0110 000 001 000000 = 0x6040 = LDR R0 R1 0
+    let mut vm = VirtualMachine::new();
+    load(&mut vm, 0x3000, &[0x6040]);
+    load(&mut vm, 0x0200, &[0x1234]);
+    vm.set_mode(PrivilegeMode::Privileged);
+    vm.registers_mut().set(Register::R1, 0x0200);
+    vm.step();
+
+    assert_eq!(0x1234, vm.registers().get(Register::R0));
+    assert_eq!(None, vm.halt_reason());
+}
+
+#[test]
+fn access_violation_handler() {
+    // 0110 000 001 000000 = 0x6040 = LDR R0 R1 0
+    // Handler:
+    // 0001 101 101 1 00001 = 0x1B61 = ADD R5 R5 1
+    // 1111 0000 00100101 = 0xF025 = HALT
+    let mut vm = VirtualMachine::new();
+    load(&mut vm, 0x3000, &[0x6040]);
+    load(&mut vm, 0x1000, &[0x1B61, 0xF025]);
+    load(&mut vm, Exception::AccessViolation.vector(), &[0x1000]);
+    vm.registers_mut().set(Register::R1, 0x0200);
+    vm.registers_mut().set(Register::R6, 0x4000);
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(1, vm.registers().get(Register::R5));
+    assert_eq!(PrivilegeMode::Privileged, vm.get_mode());
+    assert_eq!(0x4000, vm.registers().get(Register::SavedUSP));
+    assert_eq!(0x2FFE, vm.registers().get(Register::R6));
+    assert_eq!(0x3001, vm.memory_mut().read(0x2FFE));
+    assert_eq!(0x8002, vm.memory_mut().read(0x2FFF));
+}
+
+#[test]
+fn trap_through_vector_table_and_rti() {
+    // 1111 0000 00110000 = 0xF030 = TRAP x30
+    // 0001 011 011 1 00001 = 0x16E1 = ADD R3 R3 1
+    // 1111 0000 00100101 = 0xF025 = HALT
+    // Service routine:
+    // 0001 100 100 1 00001 = 0x1921 = ADD R4 R4 1
+    // 1000 000000000000 = 0x8000 = RTI
+    let mut vm = VirtualMachine::new();
+    load(&mut vm, 0x3000, &[0xF030, 0x16E1, 0xF025]);
+    load(&mut vm, 0x1000, &[0x1921, 0x8000]);
+    load(&mut vm, 0x0030, &[0x1000]);
+    vm.registers_mut().set(Register::R6, 0x4000);
+
+    vm.step();
+    assert_eq!(PrivilegeMode::Privileged, vm.get_mode());
+    assert_eq!(0x1000, vm.registers().get(Register::PC));
+    assert_eq!(0x2FFE, vm.registers().get(Register::R6));
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(1, vm.registers().get(Register::R4));
+    assert_eq!(1, vm.registers().get(Register::R3));
+    assert_eq!(PrivilegeMode::User, vm.get_mode());
+    assert_eq!(0x4000, vm.registers().get(Register::R6));
+    assert_eq!(0x3000, vm.registers().get(Register::SavedSSP));
+}
+
+#[test]
+fn rti_in_user_mode() {
+    // 1000 000000000000 = 0x8000 = RTI
+    let mut vm = VirtualMachine::new();
+    load(&mut vm, 0x3000, &[0x8000]);
+
+    assert_eq!(
+        HaltReason::Exception(Exception::PrivilegeViolation),
+        vm.run()
+    );
+}
+
+#[test]
+fn reserved_opcode() {
+    // 1101 000000000000 = 0xD000 = RES
+    let mut vm = VirtualMachine::new();
+    load(&mut vm, 0x3000, &[0xD000]);
+
+    assert_eq!(HaltReason::Exception(Exception::IllegalOpcode), vm.run());
+}
@@ -0,0 +1,428 @@
+//! A deliberately simple second implementation of the LC-3 instruction set,
+//! written from the ISA description rather than from `vm::instruction`, and a
+//! differential test that runs random instructions through both.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use vm::{
+    execute, Exception, HaltReason, Observer, Register, ScriptedConsole, VirtualMachine, KBSR,
+};
+
+const PRIVILEGE: u16 = 1 << 15;
+
+/// Machine state the reference model works on
+#[derive(Clone)]
+struct State {
+    gprs: [u16; 8],
+    /// Already incremented, as it is while an instruction executes
+    pc: u16,
+    psr: u16,
+    saved_usp: u16,
+    saved_ssp: u16,
+    memory: Vec<u16>,
+    writes: Vec<(u16, u16)>,
+    halt: Option<HaltReason>,
+}
+
+/// The model leaves out the device registers and the built-in trap routines,
+/// so instructions that reach them cannot be compared
+struct Unmodeled;
+
+type Semantics = fn(&mut State, u16) -> Result<(), Unmodeled>;
+
+/// Indexed by opcode
+const TABLE: [(&str, Semantics); 16] = [
+    ("BR", br),
+    ("ADD", add),
+    ("LD", ld),
+    ("ST", st),
+    ("JSR", jsr),
+    ("AND", and),
+    ("LDR", ldr),
+    ("STR", str),
+    ("RTI", rti),
+    ("NOT", not),
+    ("LDI", ldi),
+    ("STI", sti),
+    ("JMP", jmp),
+    ("RES", res),
+    ("LEA", lea),
+    ("TRAP", trap),
+];
+
+/// Bits [high:low] of `word`
+fn field(word: u16, high: u16, low: u16) -> u16 {
+    (word >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+/// Bits [bits-1:0] of `word`, sign-extended
+fn sext(word: u16, bits: u16) -> u16 {
+    let shift = 16 - bits;
+    (((word << shift) as i16) >> shift) as u16
+}
+
+fn dr(word: u16) -> usize {
+    field(word, 11, 9) as usize
+}
+
+fn sr1(word: u16) -> usize {
+    field(word, 8, 6) as usize
+}
+
+impl State {
+    fn user(&self) -> bool {
+        self.psr & PRIVILEGE != 0
+    }
+
+    fn violates(&self, address: u16) -> bool {
+        self.user() && !(0x3000..KBSR).contains(&address)
+    }
+
+    fn read(&self, address: u16) -> Result<u16, Unmodeled> {
+        if address >= KBSR {
+            return Err(Unmodeled);
+        }
+        Ok(self.memory[address as usize])
+    }
+
+    fn write(&mut self, address: u16, value: u16) -> Result<(), Unmodeled> {
+        if address >= KBSR {
+            return Err(Unmodeled);
+        }
+        self.memory[address as usize] = value;
+        self.writes.push((address, value));
+        Ok(())
+    }
+
+    fn setcc(&mut self, value: u16) {
+        let flag = if value == 0 {
+            0b010
+        } else if value & 0x8000 != 0 {
+            0b100
+        } else {
+            0b001
+        };
+        self.psr = (self.psr & !0x7) | flag;
+    }
+
+    fn push(&mut self, value: u16) -> Result<(), Unmodeled> {
+        self.gprs[6] = self.gprs[6].wrapping_sub(1);
+        self.write(self.gprs[6], value)
+    }
+
+    fn pop(&mut self) -> Result<u16, Unmodeled> {
+        let value = self.read(self.gprs[6])?;
+        self.gprs[6] = self.gprs[6].wrapping_add(1);
+        Ok(value)
+    }
+
+    /// Enters supervisor mode and starts `routine` with PSR and PC saved on
+    /// the supervisor stack
+    fn interrupt(&mut self, routine: u16) -> Result<(), Unmodeled> {
+        let (psr, pc) = (self.psr, self.pc);
+        if self.user() {
+            self.saved_usp = self.gprs[6];
+            self.gprs[6] = self.saved_ssp;
+        }
+        self.psr &= !PRIVILEGE;
+        self.push(psr)?;
+        self.push(pc)?;
+        self.pc = routine;
+        Ok(())
+    }
+
+    fn exception(&mut self, exception: Exception) -> Result<(), Unmodeled> {
+        match self.read(exception.vector())? {
+            0 => {
+                self.halt = Some(HaltReason::Exception(exception));
+                Ok(())
+            }
+            routine => self.interrupt(routine),
+        }
+    }
+
+    fn load(&mut self, word: u16, address: u16) -> Result<(), Unmodeled> {
+        if self.violates(address) {
+            return self.exception(Exception::AccessViolation);
+        }
+        let value = self.read(address)?;
+        self.gprs[dr(word)] = value;
+        self.setcc(value);
+        Ok(())
+    }
+
+    fn store(&mut self, word: u16, address: u16) -> Result<(), Unmodeled> {
+        if self.violates(address) {
+            return self.exception(Exception::AccessViolation);
+        }
+        self.write(address, self.gprs[dr(word)])
+    }
+
+    fn indirect(&mut self, word: u16) -> Result<Option<u16>, Unmodeled> {
+        let pointer = self.pc.wrapping_add(sext(word, 9));
+        if self.violates(pointer) {
+            self.exception(Exception::AccessViolation)?;
+            return Ok(None);
+        }
+        Ok(Some(self.read(pointer)?))
+    }
+}
+
+fn br(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    if field(word, 11, 9) & state.psr != 0 {
+        state.pc = state.pc.wrapping_add(sext(word, 9));
+    }
+    Ok(())
+}
+
+fn add(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    let operand = if field(word, 5, 5) == 1 {
+        sext(word, 5)
+    } else {
+        state.gprs[field(word, 2, 0) as usize]
+    };
+    let value = state.gprs[sr1(word)].wrapping_add(operand);
+    state.gprs[dr(word)] = value;
+    state.setcc(value);
+    Ok(())
+}
+
+fn and(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    let operand = if field(word, 5, 5) == 1 {
+        sext(word, 5)
+    } else {
+        state.gprs[field(word, 2, 0) as usize]
+    };
+    let value = state.gprs[sr1(word)] & operand;
+    state.gprs[dr(word)] = value;
+    state.setcc(value);
+    Ok(())
+}
+
+fn not(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    let value = !state.gprs[sr1(word)];
+    state.gprs[dr(word)] = value;
+    state.setcc(value);
+    Ok(())
+}
+
+fn ld(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    state.load(word, state.pc.wrapping_add(sext(word, 9)))
+}
+
+fn ldr(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    state.load(word, state.gprs[sr1(word)].wrapping_add(sext(word, 6)))
+}
+
+fn ldi(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    match state.indirect(word)? {
+        Some(address) => state.load(word, address),
+        None => Ok(()),
+    }
+}
+
+fn st(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    state.store(word, state.pc.wrapping_add(sext(word, 9)))
+}
+
+fn str(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    state.store(word, state.gprs[sr1(word)].wrapping_add(sext(word, 6)))
+}
+
+fn sti(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    match state.indirect(word)? {
+        Some(address) => state.store(word, address),
+        None => Ok(()),
+    }
+}
+
+fn lea(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    state.gprs[dr(word)] = state.pc.wrapping_add(sext(word, 9));
+    Ok(())
+}
+
+fn jsr(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    let link = state.pc;
+    state.pc = if field(word, 11, 11) == 1 {
+        state.pc.wrapping_add(sext(word, 11))
+    } else {
+        state.gprs[sr1(word)]
+    };
+    state.gprs[7] = link;
+    Ok(())
+}
+
+fn jmp(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    state.pc = state.gprs[sr1(word)];
+    Ok(())
+}
+
+fn rti(state: &mut State, _word: u16) -> Result<(), Unmodeled> {
+    if state.user() {
+        return state.exception(Exception::PrivilegeViolation);
+    }
+    state.pc = state.pop()?;
+    let psr = state.pop()?;
+    if psr & PRIVILEGE != 0 {
+        state.saved_ssp = state.gprs[6];
+        state.gprs[6] = state.saved_usp;
+    }
+    state.psr = psr;
+    Ok(())
+}
+
+fn res(state: &mut State, _word: u16) -> Result<(), Unmodeled> {
+    state.exception(Exception::IllegalOpcode)
+}
+
+fn trap(state: &mut State, word: u16) -> Result<(), Unmodeled> {
+    match state.read(field(word, 7, 0))? {
+        0 => Err(Unmodeled),
+        routine => state.interrupt(routine),
+    }
+}
+
+/// xorshift32, so that failures can be reproduced from the seed
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u16 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as u16
+    }
+}
+
+fn random_state(random: &mut Random, seed: u32) -> State {
+    let mut memory: Vec<u16> = (0..=u16::MAX).map(|_| random.next()).collect();
+    // Leave some exceptions without a handler
+    if seed.is_multiple_of(4) {
+        memory[0x0100..0x0200].fill(0);
+    }
+
+    State {
+        gprs: [(); 8].map(|_| random.next()),
+        pc: random.next(),
+        psr: (random.next() & 0x8700) | (1 << (random.next() % 3)),
+        saved_usp: random.next(),
+        saved_ssp: random.next(),
+        memory,
+        writes: Vec::new(),
+        halt: None,
+    }
+}
+
+/// Every memory write, in order
+type WriteLog = Rc<RefCell<Vec<(u16, u16)>>>;
+
+struct Writes(WriteLog);
+
+impl Observer for Writes {
+    fn on_mem_write(&mut self, address: u16, value: u16) {
+        self.0.borrow_mut().push((address, value));
+    }
+}
+
+fn machine(state: &State) -> (VirtualMachine, WriteLog) {
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VirtualMachine::builder()
+        .load(0x0000, &state.memory[..KBSR as usize])
+        .console(Box::new(ScriptedConsole::default()))
+        .observer(Box::new(Writes(writes.clone())))
+        .build();
+
+    let registers = vm.registers_mut();
+    for (register, value) in Register::GPRS.into_iter().zip(state.gprs) {
+        registers.set(register, value);
+    }
+    registers.set(Register::PC, state.pc);
+    registers.set(Register::PSR, state.psr);
+    registers.set(Register::SavedUSP, state.saved_usp);
+    registers.set(Register::SavedSSP, state.saved_ssp);
+
+    (vm, writes)
+}
+
+#[test]
+fn random_instructions_match_reference() {
+    const CASES: u32 = 1_000;
+    let mut unmodeled = 0;
+
+    for seed in 1..=CASES {
+        let mut random = Random(seed);
+        let state = random_state(&mut random, seed);
+        let word = random.next();
+        let (name, semantics) = TABLE[(word >> 12) as usize];
+        let case = format!("seed {seed}: {name} {word:#06X}");
+
+        let mut expected = state.clone();
+        if semantics(&mut expected, word).is_err() {
+            unmodeled += 1;
+            continue;
+        }
+
+        let (mut vm, writes) = machine(&state);
+        execute(&mut vm, word);
+
+        let registers = vm.registers();
+        for (index, register) in Register::GPRS.into_iter().enumerate() {
+            assert_eq!(
+                expected.gprs[index],
+                registers.get(register),
+                "{case} {register:?}"
+            );
+        }
+        assert_eq!(expected.pc, registers.get(Register::PC), "{case} PC");
+        assert_eq!(expected.psr, registers.get(Register::PSR), "{case} PSR");
+        assert_eq!(
+            expected.saved_usp,
+            registers.get(Register::SavedUSP),
+            "{case} SavedUSP"
+        );
+        assert_eq!(
+            expected.saved_ssp,
+            registers.get(Register::SavedSSP),
+            "{case} SavedSSP"
+        );
+        assert_eq!(expected.halt, vm.halt_reason(), "{case}");
+        assert_eq!(expected.writes, *writes.borrow(), "{case}");
+
+        // Writes that bypass the observer would still show up here
+        if seed.is_multiple_of(16) {
+            for address in 0..KBSR {
+                assert_eq!(
+                    expected.memory[address as usize],
+                    vm.memory_mut().read(address),
+                    "{case} address {address:#06X}"
+                );
+            }
+        }
+    }
+
+    assert!(
+        unmodeled < CASES / 10,
+        "only {} of {CASES} cases could be compared",
+        CASES - unmodeled
+    );
+}
+
+#[test]
+fn ldi_dereferences_twice() {
+    // 1010 001 000000001 = 0xA201 = LDI R1 1
+    let mut state = random_state(&mut Random(1), 1);
+    state.pc = 0x3001;
+    state.psr = 0x8002;
+    state.memory[0x3002] = 0x4000;
+    state.memory[0x4000] = 0xBEEF;
+
+    let mut expected = state.clone();
+    assert!(ldi(&mut expected, 0xA201).is_ok());
+    assert_eq!(0xBEEF, expected.gprs[1]);
+
+    let (mut vm, _) = machine(&state);
+    execute(&mut vm, 0xA201);
+    assert_eq!(0xBEEF, vm.registers().get(Register::R1));
+    assert_eq!(0x8004, vm.registers().get(Register::PSR));
+}
@@ -0,0 +1,155 @@
+use vm::{
+    Console, HaltReason, InputEvent, InputLog, InputRecorder, PrivilegeMode, Register, ReplayError,
+    ScriptedConsole, VirtualMachine,
+};
+
+// x3000: 1010 001 000000110 = 0xA206 = LDI R1 6 (KBSR)
+// x3001: 0000 011 111111110 = 0x07FE = BRzp -2
+// x3002: 1010 000 000000101 = 0xA005 = LDI R0 5 (KBDR)
+// x3003: 0001 010 000 1 00000 = 0x1420 = ADD R2 R0 0
+// x3004: 1111 0000 00100000 = 0xF020 = GETC
+// x3005: 1111 0000 00100001 = 0xF021 = OUT
+// x3006: 1111 0000 00100101 = 0xF025 = HALT
+const PROGRAM: [u16; 9] = [
+    0xA206, 0x07FE, 0xA005, 0x1420, 0xF020, 0xF021, 0xF025, 0xFE00, 0xFE02,
+];
+
+/// Keyboard that only has input after a number of polls, like a person typing
+struct SlowKeyboard {
+    polls: u32,
+    input: ScriptedConsole,
+}
+
+impl Console for SlowKeyboard {
+    fn poll(&mut self) -> bool {
+        self.polls = self.polls.saturating_sub(1);
+        self.polls == 0 && self.input.poll()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.read()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.input.write(byte);
+    }
+}
+
+fn builder() -> vm::VirtualMachineBuilder {
+    VirtualMachine::builder()
+        .program(0x3000, &PROGRAM)
+        .mode(PrivilegeMode::Privileged)
+        .instruction_limit(1_000)
+}
+
+#[test]
+fn record_then_replay() {
+    let recorder = InputRecorder::new();
+    let mut recorded = builder()
+        .console(Box::new(SlowKeyboard {
+            polls: 20,
+            input: ScriptedConsole::new(b"ab"),
+        }))
+        .record_input(&recorder)
+        .build();
+    assert_eq!(HaltReason::Halt, recorded.run());
+
+    let log = recorder.log();
+    assert_eq!(
+        vec![
+            InputEvent {
+                instruction: 38,
+                byte: b'a'
+            },
+            InputEvent {
+                instruction: 42,
+                byte: b'b'
+            },
+        ],
+        log.events
+    );
+
+    let console = ScriptedConsole::default();
+    let output = console.output();
+    let mut replayed = builder()
+        .console(Box::new(console))
+        .replay_input(log)
+        .build();
+    assert_eq!(HaltReason::Halt, replayed.run());
+
+    assert_eq!(recorded.instruction_count(), replayed.instruction_count());
+    assert_eq!(recorded.registers(), replayed.registers());
+    assert_eq!(b'a', replayed.registers().get(Register::R2) as u8);
+    assert_eq!(b"b", output.borrow().as_slice());
+}
+
+#[test]
+fn replay_ignores_console_input() {
+    let log = InputLog {
+        events: vec![
+            InputEvent {
+                instruction: 10,
+                byte: b'x',
+            },
+            InputEvent {
+                instruction: 10,
+                byte: b'y',
+            },
+        ],
+    };
+    let mut vm = builder()
+        .console(Box::new(ScriptedConsole::new(b"ab")))
+        .replay_input(log)
+        .build();
+
+    assert_eq!(HaltReason::Halt, vm.run());
+    assert_eq!(b'x', vm.registers().get(Register::R2) as u8);
+    assert_eq!(b'y', vm.registers().get(Register::R0) as u8);
+    assert_eq!(17, vm.instruction_count());
+}
+
+#[test]
+fn replay_runs_out_of_input() {
+    let mut vm = builder()
+        .console(Box::new(ScriptedConsole::new(b"ab")))
+        .replay_input(InputLog::default())
+        .build();
+
+    assert_eq!(HaltReason::InstructionLimit, vm.run());
+}
+
+#[test]
+fn text_round_trip() {
+    let log = InputLog {
+        events: vec![
+            InputEvent {
+                instruction: 1042,
+                byte: b'a',
+            },
+            InputEvent {
+                instruction: 1877,
+                byte: b'\n',
+            },
+        ],
+    };
+    let text = log.to_string();
+
+    assert_eq!("# lc3 input replay\n1042 0x61\n1877 0x0a\n", text);
+    assert_eq!(Ok(log), text.parse());
+}
+
+#[test]
+fn malformed_log() {
+    assert_eq!(
+        Err(ReplayError::Malformed { line: 2 }),
+        "1 0x61\n2 0x100\n".parse::<InputLog>()
+    );
+    assert_eq!(
+        Err(ReplayError::Malformed { line: 1 }),
+        "1\n".parse::<InputLog>()
+    );
+    assert_eq!(
+        Err(ReplayError::OutOfOrder { line: 3 }),
+        "5 0x61\n\n4 0x62\n".parse::<InputLog>()
+    );
+}
@@ -0,0 +1,74 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+use vm::{Console, HaltReason, SocketConsole, VirtualMachine};
+
+// x3000: 1111 0000 00100000 = 0xF020 = GETC
+// x3001: 1111 0000 00100001 = 0xF021 = OUT
+// x3002: 0000 111 111111101 = 0x0FFD = BRnzp -3
+const ECHO: [u16; 3] = [0xF020, 0xF021, 0x0FFD];
+
+fn echo(console: SocketConsole) -> HaltReason {
+    let mut vm = VirtualMachine::builder()
+        .program(0x3000, &ECHO)
+        .console(Box::new(console))
+        .instruction_limit(1_000)
+        .build();
+    vm.run()
+}
+
+#[cfg(unix)]
+#[test]
+fn echo_over_unix_socket() {
+    let (vm_end, mut peer) = UnixStream::pair().unwrap();
+    peer.write_all(b"hello").unwrap();
+    peer.shutdown(Shutdown::Write).unwrap();
+
+    assert_eq!(
+        HaltReason::InputExhausted,
+        echo(SocketConsole::from(vm_end))
+    );
+
+    let mut output = String::new();
+    peer.read_to_string(&mut output).unwrap();
+    assert_eq!("hello", output);
+}
+
+#[test]
+fn echo_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let peer = thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"tcp").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        output
+    });
+
+    let console = SocketConsole::accept_tcp(&listener).unwrap();
+    assert_eq!(HaltReason::InputExhausted, echo(console));
+    assert_eq!("tcp", peer.join().unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn poll_does_not_block() {
+    let (vm_end, mut peer) = UnixStream::pair().unwrap();
+    let mut console = SocketConsole::from(vm_end);
+    assert!(!console.poll());
+
+    peer.write_all(b"x").unwrap();
+    assert!(console.poll());
+    assert!(console.poll());
+    assert_eq!(Some(b'x'), console.read());
+    assert!(!console.poll());
+
+    peer.shutdown(Shutdown::Write).unwrap();
+    assert!(!console.poll());
+    assert_eq!(None, console.read());
+}
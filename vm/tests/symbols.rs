@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use vm::{Harness, Profiler, Symbols};
+
+const SYM_FILE: &str = "// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tMAIN              3000
+//\tLOOP              3002
+
+";
+
+#[test]
+fn reads_an_lc3as_sym_file() {
+    let symbols: Symbols = SYM_FILE.parse().unwrap();
+
+    assert_eq!(Some(0x3000), symbols.address("MAIN"));
+    assert_eq!(Some(0x3002), symbols.address("LOOP"));
+    assert_eq!("LOOP", symbols.symbolize(0x3002));
+    assert_eq!("LOOP+1", symbols.symbolize(0x3003));
+    assert_eq!("MAIN+1", symbols.symbolize(0x3001));
+}
+
+#[test]
+fn labels_can_share_an_address() {
+    let mut symbols = Symbols::new();
+    symbols.insert("MAIN", 0x3000);
+    symbols.insert("START", 0x3000);
+    symbols.insert("DONE", 0x3004);
+
+    assert_eq!(Some(0x3000), symbols.address("MAIN"));
+    assert_eq!(Some(0x3000), symbols.address("START"));
+    assert_eq!("MAIN+1", symbols.symbolize(0x3001));
+    assert_eq!(
+        vec![(0x3000, "MAIN"), (0x3000, "START"), (0x3004, "DONE")],
+        symbols.iter().collect::<Vec<_>>()
+    );
+
+    // Moving a label leaves the others where they were
+    symbols.insert("MAIN", 0x3004);
+    assert_eq!("START", symbols.symbolize(0x3000));
+    assert_eq!("DONE", symbols.symbolize(0x3004));
+    assert_eq!(Some(0x3004), symbols.address("MAIN"));
+}
+
+#[test]
+fn rejects_lines_that_are_not_entries() {
+    let error = "// Symbol table\nMAIN 3000\n"
+        .parse::<Symbols>()
+        .unwrap_err();
+    assert_eq!("invalid symbol file: line 2: MAIN 3000", error.to_string());
+
+    let error = "//\tMAIN  zz00\n".parse::<Symbols>().unwrap_err();
+    assert!(error.to_string().starts_with("invalid symbol file: line 1"));
+}
+
+#[test]
+fn profile_counts_each_address() {
+    // x3000: AND R0, R0, #0; ADD R0, R0, #3
+    // x3002: LOOP ADD R0, R0, #-1; BRp LOOP; HALT
+    let program = [0x5020, 0x1023, 0x103F, 0x03FE, 0xF025];
+    let profiler = Profiler::new();
+    let result = Harness::new().profile(&profiler).run(&program);
+
+    let counts = profiler.counts();
+    assert_eq!(result.instruction_count, counts.values().sum::<u64>());
+    assert_eq!(
+        BTreeMap::from([
+            (0x3000, 1),
+            (0x3001, 1),
+            (0x3002, 3),
+            (0x3003, 3),
+            (0x3004, 1)
+        ]),
+        counts
+    );
+
+    let symbols: Symbols = SYM_FILE.parse().unwrap();
+    assert_eq!(
+        vec![(String::from("LOOP"), 7), (String::from("MAIN"), 2)],
+        profiler.by_label(&symbols)
+    );
+    assert_eq!(
+        vec![
+            (String::from("x3002"), 3),
+            (String::from("x3003"), 3),
+            (String::from("x3000"), 1),
+            (String::from("x3001"), 1),
+            (String::from("x3004"), 1),
+        ],
+        profiler.by_label(&Symbols::new())
+    );
+}